@@ -15,7 +15,8 @@ async fn main() {
 
     let client = RealtimeClientBuilder::new(endpoint, access_token)
         .set_heartbeat_interval(Duration::from_secs(29))
-        .connect();
+        .connect()
+        .unwrap();
 
     let channel = RealtimeChannelBuilder::new("TestTopic")
         .set_broadcast_config(BroadcastConfig {
@@ -27,7 +28,7 @@ async fn main() {
         .await
         .unwrap();
 
-    channel.subscribe_blocking().await.unwrap();
+    channel.subscribe_blocking().await.unwrap().unwrap();
 
     let mut payload = realtime_rs::message::payload::BroadcastPayload {
         event: "test_event".into(),
@@ -42,7 +43,7 @@ async fn main() {
             count += 1;
             println!("SENDING {}", count);
             payload.payload.insert("count".into(), count.into());
-            let _ = channel.broadcast(payload.clone());
+            let _ = channel.broadcast(payload.clone()).await;
             sleep(Duration::from_millis(1000)).await;
         }
     })
@@ -14,6 +14,7 @@ fn main() {
     let client = RealtimeClientBuilder::new(endpoint, access_token)
         .set_heartbeat_interval(Duration::from_secs(29))
         .connect()
+        .unwrap()
         .to_sync();
 
     let channel = RealtimeChannelBuilder::new("TestTopic")
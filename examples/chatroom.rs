@@ -15,10 +15,7 @@ use std::{
 };
 
 use realtime_rs::{
-    message::{
-        payload::{BroadcastConfig, BroadcastPayload},
-        presence::PresenceEvent,
-    },
+    message::payload::{BroadcastConfig, BroadcastPayload},
     realtime_channel::RealtimeChannelBuilder,
     realtime_client::{ClientState, RealtimeClientBuilder},
 };
@@ -78,7 +75,7 @@ async fn main() {
     let url = "http://127.0.0.1:54321/realtime/v1";
     let anon_key = env::var("LOCAL_ANON_KEY").expect("No anon key!");
 
-    let mut client = RealtimeClientBuilder::new(url, anon_key).connect();
+    let mut client = RealtimeClientBuilder::new(url, anon_key).connect().unwrap();
 
     println!("Connecting...");
 
@@ -92,7 +89,11 @@ async fn main() {
             .await
         {
             Ok(session) => {
-                client.set_access_token(session.access_token).await.unwrap();
+                client
+                    .set_access_token(session.access_token)
+                    .await
+                    .unwrap()
+                    .unwrap();
             }
             Err(e) => return println!("Login error: {:?}", e),
         }
@@ -143,8 +144,8 @@ async fn main() {
             }
             stdout().flush().unwrap();
         })
-        .on_presence(PresenceEvent::Join, move |_id, _state, joins| {
-            for (_id, data) in joins.get_phx_map() {
+        .on_presence_join(move |_key, _current, joined| {
+            for data in joined.values() {
                 print!(
                     "\r{} joined the chatroom.",
                     serde_json::from_value::<String>(data.get("alias").unwrap().clone()).unwrap()
@@ -154,8 +155,8 @@ async fn main() {
                 stdout().flush().unwrap();
             }
         })
-        .on_presence(PresenceEvent::Leave, move |_id, _state, leaves| {
-            for (_id, data) in leaves.get_phx_map() {
+        .on_presence_leave(move |_key, _current, left| {
+            for data in left.values() {
                 print!(
                     "\r{} has gone to touch grass.",
                     serde_json::from_value::<String>(data.get("alias").unwrap().clone()).unwrap()
@@ -169,7 +170,7 @@ async fn main() {
         .await
         .unwrap();
 
-    channel.subscribe_blocking().await.unwrap();
+    channel.subscribe_blocking().await.unwrap().unwrap();
 
     let mut state_data = HashMap::new();
     state_data.insert("alias".into(), serde_json::Value::String(a_guard.clone()));
@@ -262,7 +263,7 @@ async fn main() {
 
                 let payload = BroadcastPayload::new("supachat", payload);
 
-                let _ = channel.broadcast(payload);
+                let _ = channel.broadcast(payload).await;
             }
             Err(_e) => {}
         }
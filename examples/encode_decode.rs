@@ -1,64 +1,56 @@
 use std::{collections::HashMap, env};
 
 use realtime_rs::{
-    message::payload::{BroadcastConfig, BroadcastPayload, Payload},
+    message::{
+        payload::{BroadcastConfig, BroadcastPayload, Payload},
+        MessageEvent, RealtimeMessage,
+    },
+    middleware::{Middleware, MiddlewareFuture, MiddlewareScope},
     realtime_channel::RealtimeChannelBuilder,
     realtime_client::{ClientState, RealtimeClientBuilder},
 };
 
-fn main() {
-    env_logger::init();
-    let url = "http://127.0.0.1:54321/realtime/v1";
-    let anon_key = env::var("LOCAL_ANON_KEY").expect("No anon key!");
+/// Reverses the "message" field of broadcasts on the way out, and back on the way in
+struct Reverse;
+
+impl Reverse {
+    fn reverse(mut msg: RealtimeMessage) -> RealtimeMessage {
+        if let Payload::Broadcast(ref mut payload) = msg.payload {
+            if let Some(message) = payload.payload.get("message").and_then(|m| m.as_str()) {
+                let reversed: String = message.chars().rev().collect();
+                payload.payload.insert("message".into(), reversed.into());
+            }
+        }
 
-    fn reverse(s: String) -> String {
-        s.chars()
-            .rev()
-            .collect::<String>()
-            .strip_prefix("\"")
-            .unwrap()
-            .strip_suffix("\"")
-            .unwrap()
-            .to_string()
+        msg
     }
+}
 
-    let client = RealtimeClientBuilder::new(url, anon_key)
-        .set_encoder(|mut msg| {
+impl Middleware for Reverse {
+    fn outbound(&self, message: RealtimeMessage) -> MiddlewareFuture<'_> {
+        Box::pin(async move {
             println!("Encoder running...");
-            match msg.payload {
-                Payload::Broadcast(ref mut payload) => {
-                    let mut message: String =
-                        payload.payload.get_mut("message").unwrap().to_string();
-
-                    message = reverse(message);
-
-                    payload.payload.insert("message".into(), message.into());
-
-                    return msg;
-                }
-                _ => {}
-            }
-
-            msg
+            Some(Self::reverse(message))
         })
-        .set_decoder(|mut msg| {
-            println!("Decoder running...");
-            match msg.payload {
-                Payload::Broadcast(ref mut payload) => {
-                    let mut message = payload.payload.get_mut("message").unwrap().to_string();
-
-                    message = reverse(message);
+    }
 
-                    payload.payload.insert("message".into(), message.into());
+    fn inbound(&self, message: RealtimeMessage) -> MiddlewareFuture<'_> {
+        Box::pin(async move {
+            println!("Decoder running...");
+            Some(Self::reverse(message))
+        })
+    }
+}
 
-                    return msg;
-                }
-                _ => {}
-            }
+fn main() {
+    env_logger::init();
+    let url = "http://127.0.0.1:54321/realtime/v1";
+    let anon_key = env::var("LOCAL_ANON_KEY").expect("No anon key!");
 
-            msg
-        })
+    let client = RealtimeClientBuilder::new(url, anon_key)
+        .add_scoped_middleware(MiddlewareScope::events([MessageEvent::Broadcast]), Reverse)
         .connect()
+        .unwrap()
         .to_sync();
 
     let channel = RealtimeChannelBuilder::new("reverse_encoder")
@@ -70,7 +62,7 @@ fn main() {
         .build_sync(&client)
         .unwrap();
 
-    channel.subscribe_blocking().unwrap();
+    channel.subscribe_blocking().unwrap().unwrap();
 
     let mut test_payload = HashMap::new();
 
@@ -81,7 +73,7 @@ fn main() {
 
     let test_payload = BroadcastPayload::new("test", test_payload);
 
-    channel.broadcast(test_payload);
+    channel.broadcast(test_payload).unwrap();
 
     loop {
         if client.get_state().unwrap() == ClientState::Closed {
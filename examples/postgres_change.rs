@@ -37,6 +37,7 @@ fn main() {
     let client = RealtimeClientBuilder::new(url, anon_key)
         .set_access_token(session.access_token)
         .connect()
+        .unwrap()
         .to_sync();
 
     let rc = Arc::clone(&event_counter);
@@ -60,10 +61,11 @@ fn main() {
         )
         .set_presence_config(realtime_rs::message::payload::PresenceConfig {
             key: Some("test_key".into()),
+            ..Default::default()
         })
         .build_sync(&client);
 
-    channel.unwrap().subscribe_blocking().unwrap();
+    channel.unwrap().subscribe_blocking().unwrap().unwrap();
 
     loop {
         if client.get_state().unwrap() == ClientState::Closed {
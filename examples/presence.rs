@@ -1,10 +1,15 @@
-use std::{collections::HashMap, env};
+use std::env;
 
 use realtime_rs::{
-    message::presence::PresenceEvent,
     realtime_channel::RealtimeChannelBuilder,
     realtime_client::{ClientState, RealtimeClientBuilder},
 };
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug)]
+struct UserStatus {
+    kb_layout: String,
+}
 
 fn main() {
     env_logger::init();
@@ -13,28 +18,34 @@ fn main() {
 
     let client = RealtimeClientBuilder::new(url, anon_key)
         .connect()
+        .unwrap()
         .to_sync();
 
-    let mut presence_payload = HashMap::new();
-    presence_payload.insert("kb_layout".into(), "en_US".into());
+    let status = UserStatus {
+        kb_layout: "en_US".into(),
+    };
 
     let channel = RealtimeChannelBuilder::new("channel_1")
         // TODO presence_state message event
-        .on_presence(PresenceEvent::Sync, |key, _old_state, _new_state| {
-            println!("Presence sync: {:?}", key);
+        .on_presence_sync(|state| {
+            println!("Presence sync: {} keys", state.len());
         })
-        .on_presence(PresenceEvent::Join, |key, _old_state, _new_state| {
-            println!("Presence join: {:?}", key);
+        .on_presence_join(|key, _current, joined| {
+            println!("Presence join: {:?} {:?}", key, joined);
         })
-        .on_presence(PresenceEvent::Leave, |key, _old_state, _new_state| {
-            println!("Presence leave: {:?}", key);
+        .on_presence_leave(|key, _current, left| {
+            println!("Presence leave: {:?} {:?}", key, left);
         })
         .build_sync(&client)
         .unwrap();
 
-    channel.subscribe_blocking().unwrap();
+    channel.subscribe_blocking().unwrap().unwrap();
 
-    channel.track(presence_payload.clone()).unwrap();
+    channel.track_typed(&status).unwrap();
+
+    for (key, metas) in channel.presence_list::<UserStatus>().unwrap() {
+        println!("{}: {:?}", key, metas);
+    }
 
     loop {
         if client.get_state().unwrap() == ClientState::Closed {
@@ -0,0 +1,26 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use realtime_rs::message::RealtimeMessage;
+use serde_json::Value;
+
+// Decoding must never panic, and anything that decodes must encode to a frame that decodes to
+// the same message
+fuzz_target!(|data: &[u8]| {
+    let Ok(text) = std::str::from_utf8(data) else {
+        return;
+    };
+
+    let Ok(message) = serde_json::from_str::<RealtimeMessage>(text) else {
+        return;
+    };
+
+    let encoded = serde_json::to_string(&message).expect("decoded message failed to encode");
+    let decoded = serde_json::from_str::<RealtimeMessage>(&encoded)
+        .expect("encoded message failed to decode");
+
+    assert_eq!(
+        serde_json::from_str::<Value>(&encoded).unwrap(),
+        serde_json::to_value(&decoded).unwrap()
+    );
+});
@@ -0,0 +1,217 @@
+//! Compression of large broadcast payloads
+//!
+//! Enabled with the `compression` feature. [BroadcastCompression] is a [Middleware] deflating
+//! each [crate::message::payload::BroadcastPayload]'s `payload` before it leaves, once its JSON
+//! reaches a minimum size, and inflating it before `on_broadcast` callbacks run.
+//!
+//! Compressed payloads are sent as `{"compression": "deflate", "data": "<base64>"}`. Payloads
+//! without that marker pass through untouched, so a compressing client still reads broadcasts
+//! from peers that don't compress. Peers that don't compress can't read compressed payloads:
+//! enable compression on every client of a channel, or set a minimum size their payloads never
+//! reach.
+//!
+//! Inflated payloads are capped at [BroadcastCompression::max_size()], so a small compressed
+//! payload can't expand into an unbounded allocation.
+//!
+//! Not implemented: websocket permessage-deflate. No tungstenite release this crate can use
+//! implements the extension, so the client doesn't offer it during the handshake and servers
+//! always send uncompressed frames. Once one does, it belongs in a
+//! [crate::transport::Transport] rather than here.
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::io::{Read, Write};
+use std::sync::Arc;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use serde_json::Value;
+
+use crate::message::payload::{BroadcastPayload, Payload};
+use crate::message::RealtimeMessage;
+use crate::middleware::{Middleware, MiddlewareFuture};
+
+/// Payload field marking a compressed payload, and naming the algorithm
+pub const COMPRESSION_FIELD: &str = "compression";
+
+/// Payload field holding the compressed payload
+pub const DATA_FIELD: &str = "data";
+
+const DEFLATE: &str = "deflate";
+
+/// Reasons a compressed broadcast couldn't be inflated. The broadcast is dropped.
+#[derive(Debug, PartialEq, Clone)]
+pub enum CompressionError {
+    /// [COMPRESSION_FIELD] names an algorithm other than deflate
+    Algorithm(String),
+    /// [DATA_FIELD] is missing or isn't valid base64
+    Malformed,
+    /// The data isn't a valid deflate stream
+    Inflate(String),
+    /// The data inflates past [BroadcastCompression::max_size()]
+    TooLarge,
+    /// Inflated, but not to a JSON object
+    Payload(String),
+}
+
+/// Compresses and decompresses broadcast payloads. See [crate::compression].
+///
+/// Add to a channel with [crate::realtime_channel::RealtimeChannelBuilder::set_compression()].
+#[derive(Clone)]
+pub struct BroadcastCompression {
+    min_size: usize,
+    max_size: usize,
+    level: Compression,
+    on_error: Option<Arc<dyn Fn(CompressionError, RealtimeMessage) + Send + Sync>>,
+}
+
+impl Default for BroadcastCompression {
+    fn default() -> Self {
+        Self {
+            min_size: 1024,
+            max_size: 1024 * 1024,
+            level: Compression::default(),
+            on_error: None,
+        }
+    }
+}
+
+impl BroadcastCompression {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only compress payloads whose JSON is at least this many bytes. Default 1024.
+    pub fn min_size(mut self, bytes: usize) -> Self {
+        self.min_size = bytes;
+        self
+    }
+
+    /// Drop incoming payloads that inflate past this many bytes of JSON, with
+    /// [CompressionError::TooLarge]. Default 1 MiB.
+    pub fn max_size(mut self, bytes: usize) -> Self {
+        self.max_size = bytes;
+        self
+    }
+
+    /// Deflate level from 0 (none) to 9 (best). Default 6.
+    pub fn level(mut self, level: u32) -> Self {
+        self.level = Compression::new(level.min(9));
+        self
+    }
+
+    /// Set a callback for incoming broadcasts that fail to inflate. Receives the error and the
+    /// broadcast as it arrived.
+    pub fn on_error(
+        mut self,
+        callback: impl Fn(CompressionError, RealtimeMessage) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_error = Some(Arc::new(callback));
+        self
+    }
+
+    /// The compressed payload, or `None` when it's below the minimum size
+    fn compress(&self, payload: &BroadcastPayload) -> Option<HashMap<String, Value>> {
+        let json = serde_json::to_vec(&payload.payload).ok()?;
+
+        if json.len() < self.min_size {
+            return None;
+        }
+
+        let mut encoder = DeflateEncoder::new(Vec::new(), self.level);
+        encoder.write_all(&json).ok()?;
+        let deflated = encoder.finish().ok()?;
+
+        Some(HashMap::from([
+            (COMPRESSION_FIELD.into(), Value::String(DEFLATE.into())),
+            (DATA_FIELD.into(), Value::String(STANDARD.encode(deflated))),
+        ]))
+    }
+
+    /// The inflated payload, or `None` when it wasn't compressed
+    fn decompress(
+        &self,
+        payload: &BroadcastPayload,
+    ) -> Option<Result<HashMap<String, Value>, CompressionError>> {
+        let algorithm = payload.payload.get(COMPRESSION_FIELD)?.as_str()?;
+
+        if algorithm != DEFLATE {
+            return Some(Err(CompressionError::Algorithm(algorithm.into())));
+        }
+
+        let Some(Value::String(data)) = payload.payload.get(DATA_FIELD) else {
+            return Some(Err(CompressionError::Malformed));
+        };
+
+        Some(inflate(data, self.max_size))
+    }
+}
+
+fn inflate(data: &str, max_size: usize) -> Result<HashMap<String, Value>, CompressionError> {
+    let deflated = STANDARD
+        .decode(data)
+        .map_err(|_| CompressionError::Malformed)?;
+
+    // One byte over the limit is enough to tell it was exceeded
+    let mut json = Vec::new();
+    DeflateDecoder::new(deflated.as_slice())
+        .take(max_size as u64 + 1)
+        .read_to_end(&mut json)
+        .map_err(|e| CompressionError::Inflate(e.to_string()))?;
+
+    if json.len() > max_size {
+        return Err(CompressionError::TooLarge);
+    }
+
+    serde_json::from_slice(&json).map_err(|e| CompressionError::Payload(e.to_string()))
+}
+
+impl Middleware for BroadcastCompression {
+    fn outbound(&self, mut message: RealtimeMessage) -> MiddlewareFuture<'_> {
+        Box::pin(async move {
+            if let Payload::Broadcast(ref mut payload) = message.payload {
+                if let Some(compressed) = self.compress(payload) {
+                    payload.payload = compressed;
+                }
+            }
+
+            Some(message)
+        })
+    }
+
+    fn inbound(&self, mut message: RealtimeMessage) -> MiddlewareFuture<'_> {
+        Box::pin(async move {
+            let Payload::Broadcast(ref mut payload) = message.payload else {
+                return Some(message);
+            };
+
+            match self.decompress(payload) {
+                None => Some(message),
+                Some(Ok(inflated)) => {
+                    payload.payload = inflated;
+                    Some(message)
+                }
+                Some(Err(error)) => {
+                    if let Some(on_error) = &self.on_error {
+                        on_error(error, message);
+                    }
+
+                    None
+                }
+            }
+        })
+    }
+}
+
+impl Debug for BroadcastCompression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BroadcastCompression")
+            .field("min_size", &self.min_size)
+            .field("max_size", &self.max_size)
+            .field("level", &self.level)
+            .finish()
+    }
+}
@@ -0,0 +1,189 @@
+//! End to end encryption of broadcast payloads
+//!
+//! Enabled with the `encryption` feature. [BroadcastEncryption] is a [Middleware] encrypting
+//! each [crate::message::payload::BroadcastPayload]'s `payload` with ChaCha20-Poly1305 before it
+//! leaves, and decrypting it before `on_broadcast` callbacks run. The server relays ciphertext
+//! only. The broadcast's event name is not encrypted, so the server still routes by it.
+//!
+//! The key is the app's to manage: every client on the channel needs the same 32 bytes. Topic
+//! and event are authenticated with the payload, so a message replayed to another channel or
+//! under another event fails to decrypt.
+//!
+//! Encrypted payloads are sent as `{"ciphertext": "<base64 nonce and ciphertext>"}`.
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload as AeadPayload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use serde_json::Value;
+
+use crate::message::payload::{BroadcastPayload, Payload};
+use crate::message::RealtimeMessage;
+use crate::middleware::{Middleware, MiddlewareFuture};
+
+/// Payload field holding the encrypted payload
+pub const CIPHERTEXT_FIELD: &str = "ciphertext";
+
+const NONCE_LEN: usize = 12;
+
+/// Reasons a broadcast couldn't be encrypted or decrypted. The broadcast is dropped.
+#[derive(Debug, PartialEq, Clone)]
+pub enum EncryptionError {
+    /// An outgoing payload couldn't be serialized or encrypted. Its send fails with
+    /// [crate::realtime_channel::ChannelSendError::Dropped].
+    Encryption,
+    /// The payload has no [CIPHERTEXT_FIELD], it was sent in the clear
+    Unencrypted,
+    /// The ciphertext isn't valid base64 or is too short to hold a nonce
+    Malformed,
+    /// Authentication failed: the message was tampered with, or encrypted with another key
+    Decryption,
+    /// Decrypted, but not to a JSON object
+    Payload(String),
+}
+
+/// Encrypts and decrypts broadcast payloads with a shared key. See [crate::encryption].
+///
+/// Add to a channel with [crate::realtime_channel::RealtimeChannelBuilder::set_encryption()].
+#[derive(Clone)]
+pub struct BroadcastEncryption {
+    cipher: ChaCha20Poly1305,
+    on_error: Option<Arc<dyn Fn(EncryptionError, RealtimeMessage) + Send + Sync>>,
+}
+
+impl BroadcastEncryption {
+    pub fn new(key: [u8; 32]) -> Self {
+        Self {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&key)),
+            on_error: None,
+        }
+    }
+
+    /// Set a callback for broadcasts that fail to encrypt or decrypt. Receives the error and the
+    /// broadcast: outgoing ones before encryption, incoming ones as they arrived.
+    pub fn on_error(
+        mut self,
+        callback: impl Fn(EncryptionError, RealtimeMessage) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_error = Some(Arc::new(callback));
+        self
+    }
+
+    fn error(&self, error: EncryptionError, message: RealtimeMessage) {
+        if let Some(on_error) = &self.on_error {
+            on_error(error, message);
+        }
+    }
+
+    fn encrypt(&self, aad: &[u8], payload: &BroadcastPayload) -> Option<String> {
+        let plaintext = serde_json::to_vec(&payload.payload).ok()?;
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                &nonce,
+                AeadPayload {
+                    msg: &plaintext,
+                    aad,
+                },
+            )
+            .ok()?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+
+        Some(STANDARD.encode(sealed))
+    }
+
+    fn decrypt(
+        &self,
+        aad: &[u8],
+        payload: &BroadcastPayload,
+    ) -> Result<HashMap<String, Value>, EncryptionError> {
+        let Some(Value::String(sealed)) = payload.payload.get(CIPHERTEXT_FIELD) else {
+            return Err(EncryptionError::Unencrypted);
+        };
+
+        let sealed = STANDARD
+            .decode(sealed)
+            .map_err(|_| EncryptionError::Malformed)?;
+
+        if sealed.len() < NONCE_LEN {
+            return Err(EncryptionError::Malformed);
+        }
+
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+
+        let plaintext = self
+            .cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                AeadPayload {
+                    msg: ciphertext,
+                    aad,
+                },
+            )
+            .map_err(|_| EncryptionError::Decryption)?;
+
+        serde_json::from_slice(&plaintext).map_err(|e| EncryptionError::Payload(e.to_string()))
+    }
+}
+
+/// Binds ciphertext to where it was sent
+fn associated_data(topic: &str, event: &str) -> Vec<u8> {
+    format!("{}\n{}", topic, event).into_bytes()
+}
+
+impl Middleware for BroadcastEncryption {
+    fn outbound(&self, mut message: RealtimeMessage) -> MiddlewareFuture<'_> {
+        Box::pin(async move {
+            let Payload::Broadcast(ref mut payload) = message.payload else {
+                return Some(message);
+            };
+
+            let aad = associated_data(&message.topic, &payload.event);
+
+            // Never send in the clear
+            let Some(sealed) = self.encrypt(&aad, payload) else {
+                self.error(EncryptionError::Encryption, message);
+                return None;
+            };
+
+            payload.payload = HashMap::from([(CIPHERTEXT_FIELD.into(), Value::String(sealed))]);
+
+            Some(message)
+        })
+    }
+
+    fn inbound(&self, mut message: RealtimeMessage) -> MiddlewareFuture<'_> {
+        Box::pin(async move {
+            let Payload::Broadcast(ref mut payload) = message.payload else {
+                return Some(message);
+            };
+
+            let aad = associated_data(&message.topic, &payload.event);
+
+            match self.decrypt(&aad, payload) {
+                Ok(plaintext) => {
+                    payload.payload = plaintext;
+                    Some(message)
+                }
+                Err(error) => {
+                    self.error(error, message);
+                    None
+                }
+            }
+        })
+    }
+}
+
+impl Debug for BroadcastEncryption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("BroadcastEncryption")
+    }
+}
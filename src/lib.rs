@@ -2,7 +2,20 @@ use tokio::sync::oneshot;
 
 pub(crate) type Responder<T> = oneshot::Sender<T>;
 
+#[cfg(feature = "compression")]
+pub mod compression;
+#[cfg(feature = "encryption")]
+pub mod encryption;
 pub mod message;
+pub mod metrics;
+pub mod middleware;
 pub mod realtime_channel;
 pub mod realtime_client;
 pub mod realtime_presence;
+pub mod recording;
+#[cfg(feature = "schema")]
+pub mod schema;
+pub mod transport;
+
+#[cfg(feature = "testing")]
+pub mod testing;
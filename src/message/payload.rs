@@ -4,10 +4,15 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 use super::presence::{PresenceEvent, RawPresenceDiff, RawPresenceState};
+use super::MessageEvent;
 
 /// Message payload, enum allows each payload type to be contained in
 /// [crate::message::realtime_message::RealtimeMessage] without
 /// needing a seperate struct per message type.
+///
+/// A [crate::message::RealtimeMessage] decodes its payload by event, see [Payload::from_event()].
+/// Deserializing a [Payload] on its own tries each variant in order and takes the first that
+/// fits, which can mistake one payload for another.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(untagged)]
 pub enum Payload {
@@ -19,10 +24,48 @@ pub enum Payload {
     Broadcast(BroadcastPayload),
     PresenceState(RawPresenceState),
     PresenceDiff(RawPresenceDiff),
-    Reply(ReplyPayload),                 // think is only used for heartbeat?
-    PresenceTrack(PresenceTrackPayload), // TODO matches greedily
-    Empty {}, // TODO implement custom deser cos this bad. typechecking: this matches
-              // everything that can't deser elsewhere. not good.
+    Reply(ReplyPayload),
+    PresenceTrack(PresenceTrackPayload),
+    Empty {},
+    /// Payload of a `phx_close`, `phx_error`, `phx_leave` or `heartbeat` that isn't empty, kept
+    /// as sent
+    Other(Value),
+}
+
+impl Payload {
+    /// Decode the payload of a message with the given event
+    ///
+    /// `phx_reply` decodes to [Payload::Response] when its response lists `postgres_changes`,
+    /// otherwise to [Payload::Reply]. `phx_close`, `phx_error`, `phx_leave` and `heartbeat`
+    /// usually carry nothing and decode to [Payload::Empty], or to [Payload::Other] when they
+    /// do.
+    pub fn from_event(event: &MessageEvent, payload: Value) -> Result<Self, serde_json::Error> {
+        use serde_json::from_value;
+
+        Ok(match event {
+            MessageEvent::PhxJoin => Payload::Join(from_value(payload)?),
+            MessageEvent::PhxReply if payload["response"].get("postgres_changes").is_some() => {
+                Payload::Response(from_value(payload)?)
+            }
+            MessageEvent::PhxReply => Payload::Reply(from_value(payload)?),
+            MessageEvent::System => Payload::System(from_value(payload)?),
+            MessageEvent::AccessToken => Payload::AccessToken(from_value(payload)?),
+            MessageEvent::PostgresChanges => Payload::PostgresChanges(from_value(payload)?),
+            MessageEvent::Broadcast => Payload::Broadcast(from_value(payload)?),
+            MessageEvent::PresenceState => Payload::PresenceState(from_value(payload)?),
+            MessageEvent::PresenceDiff => Payload::PresenceDiff(from_value(payload)?),
+            MessageEvent::Presence | MessageEvent::Track | MessageEvent::Untrack => {
+                Payload::PresenceTrack(from_value(payload)?)
+            }
+            MessageEvent::PhxClose
+            | MessageEvent::PhxError
+            | MessageEvent::PhxLeave
+            | MessageEvent::Heartbeat => match payload {
+                Value::Object(ref map) if map.is_empty() => Payload::Empty {},
+                payload => Payload::Other(payload),
+            },
+        })
+    }
 }
 
 impl Default for Payload {
@@ -108,7 +151,12 @@ pub struct PostgresChangeData {
     pub columns: Vec<PostgresColumn>,
     pub commit_timestamp: String,
     pub errors: Option<String>,
-    pub old_record: Option<PostgresOldDataRef>,
+    /// Primary key columns of the changed row, or the whole old row with `REPLICA IDENTITY FULL`.
+    /// Absent on inserts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub old_record: Option<HashMap<String, Value>>,
+    /// Absent on deletes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub record: Option<HashMap<String, Value>>,
     #[serde(rename = "type")]
     pub change_type: PostgresChangesEvent,
@@ -123,11 +171,6 @@ pub struct PostgresColumn {
     pub column_type: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct PostgresOldDataRef {
-    pub id: isize,
-}
-
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct AccessTokenPayload {
     pub access_token: String,
@@ -155,6 +198,9 @@ pub struct JoinConfig {
     pub broadcast: BroadcastConfig,
     pub presence: PresenceConfig,
     pub postgres_changes: Vec<PostgresChange>,
+    /// Private channels are authorized with Realtime Authorization policies
+    #[serde(default)]
+    pub private: bool,
 }
 
 /// Channel broadcast options
@@ -166,9 +212,28 @@ pub struct BroadcastConfig {
 }
 
 /// Channel presence options
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PresenceConfig {
     pub key: Option<String>,
+    /// Resend the last tracked payload after the channel rejoins, e.g. after a reconnect.
+    /// Client side only, defaults to `true`.
+    #[serde(skip, default = "PresenceConfig::default_retrack")]
+    pub retrack: bool,
+}
+
+impl PresenceConfig {
+    fn default_retrack() -> bool {
+        true
+    }
+}
+
+impl Default for PresenceConfig {
+    fn default() -> Self {
+        Self {
+            key: None,
+            retrack: Self::default_retrack(),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Default, Clone, Hash)]
@@ -186,6 +251,10 @@ pub enum PostgresChangesEvent {
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct PostgresChange {
+    /// Assigned by the server in the join reply, matching the `ids` of
+    /// [PostgresChangesPayload]s
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<usize>,
     pub event: PostgresChangesEvent,
     pub schema: String,
     pub table: String,
@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::Value;
 
 /// Enum of presence event types
@@ -28,15 +28,195 @@ pub type PhxMap = HashMap<String, StateData>;
 
 pub type StateData = HashMap<String, Value>;
 
+/// A single presence meta with its data deserialized into `T`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PresenceMeta<T> {
+    pub phx_ref: String,
+    pub state: T,
+}
+
+/// Presence keys, each with their typed metas
+pub type PresenceList<T> = Vec<(String, Vec<PresenceMeta<T>>)>;
+
 /// PresenceState triple nested hashmap.
 ///
 /// Layout:
 /// HashMap<id, HashMap<phx_ref, HashMap<key, value>>>
 /// { \[id\]: { \[ref\]: { \[key\]: value } } }
-#[derive(Default, Clone, Debug)]
+///
+/// Serializes with the same layout, so snapshots can be logged, exported and loaded back.
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
 pub struct PresenceState(pub PresenceStateInner);
 
 impl PresenceState {
+    /// Replace this state with `new_state`, as recieved in a `presence_state` message.
+    ///
+    /// Port of Phoenix `Presence.syncState`. Joins and leaves are computed per meta (`phx_ref`)
+    /// and applied with [Self::sync_diff()].
+    pub fn sync_state(
+        &mut self,
+        new_state: PresenceState,
+        on_join: impl FnMut(&str, Option<&PhxMap>, &PhxMap),
+        on_leave: impl FnMut(&str, &PhxMap, &PhxMap),
+    ) {
+        let mut joins = HashMap::new();
+        let mut leaves = HashMap::new();
+
+        for (key, presence) in &self.0 {
+            if !new_state.0.contains_key(key) {
+                leaves.insert(key.clone(), presence.clone());
+            }
+        }
+
+        for (key, new_presence) in new_state.0 {
+            let Some(current_presence) = self.0.get(&key) else {
+                joins.insert(key, new_presence);
+                continue;
+            };
+
+            let joined_metas: PhxMap = new_presence
+                .iter()
+                .filter(|(phx_ref, _)| !current_presence.contains_key(*phx_ref))
+                .map(|(phx_ref, data)| (phx_ref.clone(), data.clone()))
+                .collect();
+
+            let left_metas: PhxMap = current_presence
+                .iter()
+                .filter(|(phx_ref, _)| !new_presence.contains_key(*phx_ref))
+                .map(|(phx_ref, data)| (phx_ref.clone(), data.clone()))
+                .collect();
+
+            if !joined_metas.is_empty() {
+                joins.insert(key.clone(), joined_metas);
+            }
+
+            if !left_metas.is_empty() {
+                leaves.insert(key, left_metas);
+            }
+        }
+
+        self.sync_diff(
+            PresenceDiff {
+                joins: PresenceState(joins),
+                leaves: PresenceState(leaves),
+            },
+            on_join,
+            on_leave,
+        );
+    }
+
+    /// Apply a diff, as recieved in a `presence_diff` message.
+    ///
+    /// Port of Phoenix `Presence.syncDiff`. Joined metas are merged into the key's existing
+    /// metas, left metas are removed one by one and a key is only dropped once it has no metas
+    /// left.
+    ///
+    /// `on_join` recieves the key, its metas before the join (if any) and the joined metas.
+    /// `on_leave` recieves the key, its remaining metas and the left metas.
+    pub fn sync_diff(
+        &mut self,
+        diff: PresenceDiff,
+        mut on_join: impl FnMut(&str, Option<&PhxMap>, &PhxMap),
+        mut on_leave: impl FnMut(&str, &PhxMap, &PhxMap),
+    ) {
+        for (key, new_presence) in diff.joins.0 {
+            let current_presence = self.0.get(&key).cloned();
+
+            let mut merged = new_presence.clone();
+            if let Some(current_presence) = &current_presence {
+                for (phx_ref, data) in current_presence {
+                    merged
+                        .entry(phx_ref.clone())
+                        .or_insert_with(|| data.clone());
+                }
+            }
+
+            self.0.insert(key.clone(), merged);
+
+            on_join(&key, current_presence.as_ref(), &new_presence);
+        }
+
+        for (key, left_presence) in diff.leaves.0 {
+            let Some(current_presence) = self.0.get_mut(&key) else {
+                continue;
+            };
+
+            current_presence.retain(|phx_ref, _| !left_presence.contains_key(phx_ref));
+
+            on_leave(&key, current_presence, &left_presence);
+
+            if current_presence.is_empty() {
+                self.0.remove(&key);
+            }
+        }
+    }
+
+    /// Returns every presence key with its metas deserialized into `T`
+    ///
+    /// Keys are sorted, as are each key's metas by `phx_ref`.
+    pub fn list_typed<T: DeserializeOwned>(&self) -> Result<PresenceList<T>, serde_json::Error> {
+        self.list_by(|key, phx_map| {
+            let mut metas = Vec::with_capacity(phx_map.len());
+
+            for (phx_ref, state_data) in phx_map {
+                let data = Value::Object(state_data.clone().into_iter().collect());
+
+                metas.push(PresenceMeta {
+                    phx_ref: phx_ref.clone(),
+                    state: serde_json::from_value(data)?,
+                });
+            }
+
+            metas.sort_by(|a, b| a.phx_ref.cmp(&b.phx_ref));
+
+            Ok((key.to_string(), metas))
+        })
+        .into_iter()
+        .collect()
+    }
+
+    /// Maps every presence key and its metas through `chooser`, like Phoenix `Presence.list`
+    ///
+    /// Keys are visited in sorted order.
+    /// ```
+    /// # use realtime_rs::message::presence::PresenceState;
+    /// # let state = PresenceState::default();
+    /// let online: Vec<String> = state.list_by(|key, metas| format!("{key} ({})", metas.len()));
+    /// ```
+    pub fn list_by<R>(&self, mut chooser: impl FnMut(&str, &PhxMap) -> R) -> Vec<R> {
+        let mut keys: Vec<&String> = self.0.keys().collect();
+        keys.sort();
+
+        keys.into_iter()
+            .map(|key| chooser(key, &self.0[key]))
+            .collect()
+    }
+
+    /// Iterate over presence keys
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.0.keys().map(|key| key.as_str())
+    }
+
+    /// Returns the metas tracked under `key`
+    pub fn metas(&self, key: &str) -> Option<&PhxMap> {
+        self.0.get(key)
+    }
+
+    /// Returns the number of presence keys
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns true if there are no presence keys
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns true if `key` is present
+    pub fn contains(&self, key: &str) -> bool {
+        self.0.contains_key(key)
+    }
+
     /// Returns a once flattened map of presence data:
     /// HashMap<phx_ref, Hashmap<key, value>>
     pub fn get_phx_map(&self) -> PhxMap {
@@ -112,8 +292,9 @@ impl From<RawPresenceDiff> for PresenceDiff {
     }
 }
 
-#[derive(Debug, Clone)]
-pub(crate) struct PresenceDiff {
+/// Joins and leaves recieved in a `presence_diff` message
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PresenceDiff {
     pub joins: PresenceState,
     pub leaves: PresenceState,
 }
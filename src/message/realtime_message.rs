@@ -1,10 +1,13 @@
-use serde::{Deserialize, Serialize};
+use serde::{de::Error, Deserialize, Deserializer, Serialize};
+use serde_json::Value;
 use tokio_tungstenite::tungstenite::Message;
 
 use crate::message::payload::Payload;
 
 /// Structure of messages sent to and from the server
-#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+///
+/// Deserializing reads the event first and decodes the payload with [Payload::from_event()].
+#[derive(Serialize, Debug, Default, Clone)]
 pub struct RealtimeMessage {
     pub event: MessageEvent,
     pub topic: String,
@@ -14,16 +17,39 @@ pub struct RealtimeMessage {
 }
 
 impl RealtimeMessage {
-    pub(crate) fn heartbeat() -> RealtimeMessage {
+    pub(crate) fn heartbeat(message_ref: String) -> RealtimeMessage {
         RealtimeMessage {
             event: MessageEvent::Heartbeat,
             topic: "phoenix".to_owned(),
             payload: Payload::Empty {},
-            message_ref: None,
+            message_ref: Some(message_ref),
         }
     }
 }
 
+impl<'de> Deserialize<'de> for RealtimeMessage {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct Frame {
+            event: MessageEvent,
+            topic: String,
+            payload: Value,
+            #[serde(rename = "ref")]
+            message_ref: Option<String>,
+        }
+
+        let frame = Frame::deserialize(deserializer)?;
+        let payload = Payload::from_event(&frame.event, frame.payload).map_err(D::Error::custom)?;
+
+        Ok(RealtimeMessage {
+            event: frame.event,
+            topic: frame.topic,
+            payload,
+            message_ref: frame.message_ref,
+        })
+    }
+}
+
 impl From<RealtimeMessage> for Message {
     fn from(val: RealtimeMessage) -> Self {
         let data = serde_json::to_string(&val).expect("Uhoh cannot into message");
@@ -32,7 +58,7 @@ impl From<RealtimeMessage> for Message {
 }
 
 /// Realtime message event list
-#[derive(Serialize, Deserialize, Debug, PartialEq, Default, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash, Default, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum MessageEvent {
     PhxClose,
@@ -0,0 +1,274 @@
+//! Client and channel metrics
+//!
+//! Every client keeps counters and gauges for its connection and channels, read with
+//! [crate::realtime_client::ClientManager::metrics()]. With the `metrics` feature they are also
+//! reported to the [metrics](https://docs.rs/metrics) facade as they change, for any installed
+//! exporter to pick up:
+//!
+//! | Name | Type | Labels |
+//! | --- | --- | --- |
+//! | `realtime_frames_sent_total` | counter | `event` |
+//! | `realtime_frames_received_total` | counter | `event` |
+//! | `realtime_bytes_sent_total` | counter | |
+//! | `realtime_bytes_received_total` | counter | |
+//! | `realtime_reconnect_attempts_total` | counter | |
+//! | `realtime_undecodable_frames_total` | counter | |
+//! | `realtime_dropped_messages_total` | counter | |
+//! | `realtime_queue_depth` | gauge | |
+//! | `realtime_join_latency_seconds` | histogram | |
+//! | `realtime_heartbeat_rtt_seconds` | histogram | |
+//! | `realtime_callback_duration_seconds` | histogram | |
+//!
+//! Join latency isn't labelled by topic, which would add a series for every topic ever joined.
+//! Per topic latencies are in [MetricsSnapshot::join_latency].
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::message::MessageEvent;
+
+/// Point in time copy of a client's metrics
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MetricsSnapshot {
+    /// Frames written to the websocket, by event. Heartbeats included.
+    pub frames_sent: HashMap<MessageEvent, u64>,
+    /// Frames read from the websocket and decoded, by event
+    pub frames_received: HashMap<MessageEvent, u64>,
+    pub bytes_sent: u64,
+    /// Includes frames that failed to decode
+    pub bytes_received: u64,
+    /// Handshakes attempted after the client's first, failed ones included
+    pub reconnect_attempts: u64,
+    /// Text frames from the server that aren't valid Realtime messages
+    pub undecodable_frames: u64,
+    /// Messages dropped by middleware, failing a broadcast schema, or with no channel to deliver
+    /// them to
+    pub dropped_messages: u64,
+    /// Messages waiting to be written to the websocket
+    pub queue_depth: usize,
+    /// Time from the latest join sent on each topic to the server's reply
+    pub join_latency: HashMap<String, Duration>,
+    /// Time from the latest answered heartbeat to its reply
+    pub heartbeat_rtt: Option<Duration>,
+    /// Callbacks run, sync and async
+    pub callbacks: u64,
+    /// Total time spent running callbacks
+    pub callback_time: Duration,
+}
+
+/// Live metrics shared by a client's tasks and channels
+#[derive(Debug, Default)]
+pub(crate) struct ClientMetrics {
+    frames_sent: Mutex<HashMap<MessageEvent, u64>>,
+    frames_received: Mutex<HashMap<MessageEvent, u64>>,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    handshakes: AtomicU64,
+    undecodable_frames: AtomicU64,
+    dropped_messages: AtomicU64,
+    queue_depth: AtomicUsize,
+    join_latency: Mutex<HashMap<String, Duration>>,
+    heartbeat_rtt: Mutex<Option<Duration>>,
+    callbacks: AtomicU64,
+    callback_nanos: AtomicU64,
+    /// Join ref -> topic and when the join was written
+    pending_joins: Mutex<HashMap<String, (String, Instant)>>,
+    /// Ref of the latest heartbeat and when it was written
+    pending_heartbeat: Mutex<Option<(String, Instant)>>,
+}
+
+impl ClientMetrics {
+    pub(crate) fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            frames_sent: self.frames_sent.lock().unwrap().clone(),
+            frames_received: self.frames_received.lock().unwrap().clone(),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            reconnect_attempts: self.handshakes.load(Ordering::Relaxed).saturating_sub(1),
+            undecodable_frames: self.undecodable_frames.load(Ordering::Relaxed),
+            dropped_messages: self.dropped_messages.load(Ordering::Relaxed),
+            queue_depth: self.queue_depth.load(Ordering::Relaxed),
+            join_latency: self.join_latency.lock().unwrap().clone(),
+            heartbeat_rtt: *self.heartbeat_rtt.lock().unwrap(),
+            callbacks: self.callbacks.load(Ordering::Relaxed),
+            callback_time: Duration::from_nanos(self.callback_nanos.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// A handshake is about to be attempted. Replies still pending from an earlier connection
+    /// will never arrive.
+    pub(crate) fn handshake(&self) {
+        if self.handshakes.fetch_add(1, Ordering::Relaxed) > 0 {
+            #[cfg(feature = "metrics")]
+            ::metrics::counter!("realtime_reconnect_attempts_total").increment(1);
+        }
+
+        self.pending_joins.lock().unwrap().clear();
+        *self.pending_heartbeat.lock().unwrap() = None;
+    }
+
+    /// A message was put on the send queue
+    pub(crate) fn queued(&self) {
+        self.queue_depth.fetch_add(1, Ordering::Relaxed);
+
+        #[cfg(feature = "metrics")]
+        self.report_queue_depth();
+    }
+
+    /// The send task took a message off the queue
+    pub(crate) fn dequeued(&self) {
+        self.queue_depth.fetch_sub(1, Ordering::Relaxed);
+
+        #[cfg(feature = "metrics")]
+        self.report_queue_depth();
+    }
+
+    #[cfg(feature = "metrics")]
+    fn report_queue_depth(&self) {
+        ::metrics::gauge!("realtime_queue_depth")
+            .set(self.queue_depth.load(Ordering::Relaxed) as f64);
+    }
+
+    pub(crate) fn frame_sent(
+        &self,
+        event: &MessageEvent,
+        topic: &str,
+        message_ref: Option<&str>,
+        bytes: usize,
+    ) {
+        *self
+            .frames_sent
+            .lock()
+            .unwrap()
+            .entry(event.clone())
+            .or_default() += 1;
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+
+        #[cfg(feature = "metrics")]
+        {
+            ::metrics::counter!("realtime_frames_sent_total", "event" => event_label(event))
+                .increment(1);
+            ::metrics::counter!("realtime_bytes_sent_total").increment(bytes as u64);
+        }
+
+        let Some(message_ref) = message_ref else {
+            return;
+        };
+
+        match event {
+            MessageEvent::Heartbeat => {
+                *self.pending_heartbeat.lock().unwrap() =
+                    Some((message_ref.to_owned(), Instant::now()));
+            }
+            MessageEvent::PhxJoin => {
+                self.pending_joins
+                    .lock()
+                    .unwrap()
+                    .insert(message_ref.to_owned(), (topic.to_owned(), Instant::now()));
+            }
+            _ => {}
+        }
+    }
+
+    pub(crate) fn frame_received(
+        &self,
+        event: &MessageEvent,
+        message_ref: Option<&str>,
+        bytes: usize,
+    ) {
+        *self
+            .frames_received
+            .lock()
+            .unwrap()
+            .entry(event.clone())
+            .or_default() += 1;
+        self.bytes_received
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+
+        #[cfg(feature = "metrics")]
+        {
+            ::metrics::counter!("realtime_frames_received_total", "event" => event_label(event))
+                .increment(1);
+            ::metrics::counter!("realtime_bytes_received_total").increment(bytes as u64);
+        }
+
+        if *event != MessageEvent::PhxReply {
+            return;
+        }
+
+        let Some(message_ref) = message_ref else {
+            return;
+        };
+
+        self.reply(message_ref);
+    }
+
+    /// Times the heartbeat or join answered by a reply with `message_ref`
+    fn reply(&self, message_ref: &str) {
+        {
+            let mut heartbeat = self.pending_heartbeat.lock().unwrap();
+
+            if heartbeat.as_ref().map(|(r, _)| r.as_str()) == Some(message_ref) {
+                if let Some((_, sent)) = heartbeat.take() {
+                    let rtt = sent.elapsed();
+                    *self.heartbeat_rtt.lock().unwrap() = Some(rtt);
+
+                    #[cfg(feature = "metrics")]
+                    ::metrics::histogram!("realtime_heartbeat_rtt_seconds")
+                        .record(rtt.as_secs_f64());
+                }
+                return;
+            }
+        }
+
+        let Some((topic, sent)) = self.pending_joins.lock().unwrap().remove(message_ref) else {
+            return;
+        };
+
+        let latency = sent.elapsed();
+
+        #[cfg(feature = "metrics")]
+        ::metrics::histogram!("realtime_join_latency_seconds").record(latency.as_secs_f64());
+
+        self.join_latency.lock().unwrap().insert(topic, latency);
+    }
+
+    pub(crate) fn undecodable(&self, bytes: usize) {
+        self.undecodable_frames.fetch_add(1, Ordering::Relaxed);
+        self.bytes_received
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+
+        #[cfg(feature = "metrics")]
+        {
+            ::metrics::counter!("realtime_undecodable_frames_total").increment(1);
+            ::metrics::counter!("realtime_bytes_received_total").increment(bytes as u64);
+        }
+    }
+
+    pub(crate) fn dropped(&self) {
+        self.dropped_messages.fetch_add(1, Ordering::Relaxed);
+
+        #[cfg(feature = "metrics")]
+        ::metrics::counter!("realtime_dropped_messages_total").increment(1);
+    }
+
+    pub(crate) fn callback(&self, duration: Duration) {
+        self.callbacks.fetch_add(1, Ordering::Relaxed);
+        self.callback_nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+
+        #[cfg(feature = "metrics")]
+        ::metrics::histogram!("realtime_callback_duration_seconds").record(duration.as_secs_f64());
+    }
+}
+
+/// The event's name on the wire
+#[cfg(feature = "metrics")]
+fn event_label(event: &MessageEvent) -> String {
+    serde_json::to_value(event)
+        .ok()
+        .and_then(|v| v.as_str().map(str::to_owned))
+        .unwrap_or_default()
+}
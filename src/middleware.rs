@@ -0,0 +1,180 @@
+//! Middleware inspecting, rewriting or dropping messages on their way to and from the server
+//!
+//! Register with [crate::realtime_client::RealtimeClientBuilder::add_middleware()] to see every
+//! message on the connection, or [crate::realtime_channel::RealtimeChannelBuilder::add_middleware()]
+//! for a single channel's messages.
+//!
+//! Outbound hooks run in registration order, channel middleware before client middleware.
+//! Inbound hooks run in reverse: client middleware first, last registered first, then the
+//! channel's, so each middleware sees inbound messages as it left outbound ones.
+
+use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use crate::message::{MessageEvent, RealtimeMessage};
+
+pub type MiddlewareFuture<'a> = Pin<Box<dyn Future<Output = Option<RealtimeMessage>> + Send + 'a>>;
+
+/// Hooks called with each message passing through the client or a channel
+///
+/// Return the message, changed or not, to pass it on, or `None` to drop it. Both hooks pass
+/// messages through unchanged by default.
+///
+/// ```
+/// # use std::sync::atomic::{AtomicUsize, Ordering};
+/// # use realtime_rs::message::RealtimeMessage;
+/// # use realtime_rs::middleware::{Middleware, MiddlewareFuture};
+/// /// Counts messages sent, dropping any past the limit
+/// struct Limit {
+///     sent: AtomicUsize,
+///     max: usize,
+/// }
+///
+/// impl Middleware for Limit {
+///     fn outbound(&self, message: RealtimeMessage) -> MiddlewareFuture<'_> {
+///         Box::pin(async move {
+///             (self.sent.fetch_add(1, Ordering::SeqCst) < self.max).then_some(message)
+///         })
+///     }
+/// }
+/// ```
+pub trait Middleware: Send + Sync {
+    /// Called with messages on their way to the server
+    fn outbound(&self, message: RealtimeMessage) -> MiddlewareFuture<'_> {
+        Box::pin(async move { Some(message) })
+    }
+
+    /// Called with messages from the server, before they reach channel callbacks
+    fn inbound(&self, message: RealtimeMessage) -> MiddlewareFuture<'_> {
+        Box::pin(async move { Some(message) })
+    }
+}
+
+/// [Middleware] with only an outbound hook, from any
+/// `Fn(RealtimeMessage) -> impl Future<Output = Option<RealtimeMessage>>`
+pub struct Outbound<F>(pub F);
+
+impl<F, Fut> Middleware for Outbound<F>
+where
+    F: Fn(RealtimeMessage) -> Fut + Send + Sync,
+    Fut: Future<Output = Option<RealtimeMessage>> + Send + 'static,
+{
+    fn outbound(&self, message: RealtimeMessage) -> MiddlewareFuture<'_> {
+        Box::pin((self.0)(message))
+    }
+}
+
+/// [Middleware] with only an inbound hook, from any
+/// `Fn(RealtimeMessage) -> impl Future<Output = Option<RealtimeMessage>>`
+pub struct Inbound<F>(pub F);
+
+impl<F, Fut> Middleware for Inbound<F>
+where
+    F: Fn(RealtimeMessage) -> Fut + Send + Sync,
+    Fut: Future<Output = Option<RealtimeMessage>> + Send + 'static,
+{
+    fn inbound(&self, message: RealtimeMessage) -> MiddlewareFuture<'_> {
+        Box::pin((self.0)(message))
+    }
+}
+
+/// Limits a [Middleware] to some messages. Middleware registered without a scope sees every
+/// message.
+#[derive(Debug, Clone, Default)]
+pub struct MiddlewareScope {
+    /// Events to run for. Empty runs for every event.
+    pub events: Vec<MessageEvent>,
+    /// Topics to run for, as passed to [crate::realtime_channel::RealtimeChannelBuilder::new()].
+    /// Empty runs for every topic.
+    pub topics: Vec<String>,
+}
+
+impl MiddlewareScope {
+    /// Scope to the given events
+    pub fn events(events: impl IntoIterator<Item = MessageEvent>) -> Self {
+        Self {
+            events: events.into_iter().collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Scope to the given topics
+    pub fn topics(topics: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            topics: topics.into_iter().map(Into::into).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn matches(&self, message: &RealtimeMessage) -> bool {
+        let event = self.events.is_empty() || self.events.contains(&message.event);
+
+        let topic = self.topics.is_empty()
+            || self.topics.iter().any(|topic| {
+                message.topic == *topic
+                    || message.topic.strip_prefix("realtime:") == Some(topic.as_str())
+            });
+
+        event && topic
+    }
+}
+
+/// Registered middleware, in order. Middleware set under a slot name can be replaced.
+#[derive(Clone, Default)]
+pub(crate) struct MiddlewareChain(
+    Vec<(Option<&'static str>, MiddlewareScope, Arc<dyn Middleware>)>,
+);
+
+impl MiddlewareChain {
+    pub(crate) fn push(&mut self, scope: MiddlewareScope, middleware: impl Middleware + 'static) {
+        self.0.push((None, scope, Arc::new(middleware)));
+    }
+
+    /// Replace the middleware in `slot` where it sits, or push it if the slot is empty
+    #[cfg(any(feature = "compression", feature = "encryption"))]
+    pub(crate) fn set(
+        &mut self,
+        slot: &'static str,
+        scope: MiddlewareScope,
+        middleware: impl Middleware + 'static,
+    ) {
+        let entry = (
+            Some(slot),
+            scope,
+            Arc::new(middleware) as Arc<dyn Middleware>,
+        );
+
+        match self.0.iter_mut().find(|(name, ..)| *name == Some(slot)) {
+            Some(existing) => *existing = entry,
+            None => self.0.push(entry),
+        }
+    }
+
+    pub(crate) async fn outbound(&self, mut message: RealtimeMessage) -> Option<RealtimeMessage> {
+        for (_, scope, middleware) in &self.0 {
+            if scope.matches(&message) {
+                message = middleware.outbound(message).await?;
+            }
+        }
+
+        Some(message)
+    }
+
+    pub(crate) async fn inbound(&self, mut message: RealtimeMessage) -> Option<RealtimeMessage> {
+        for (_, scope, middleware) in self.0.iter().rev() {
+            if scope.matches(&message) {
+                message = middleware.inbound(message).await?;
+            }
+        }
+
+        Some(message)
+    }
+}
+
+impl Debug for MiddlewareChain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "MiddlewareChain({})", self.0.len())
+    }
+}
@@ -1,17 +1,25 @@
+use crate::metrics::ClientMetrics;
+use crate::middleware::{Middleware, MiddlewareChain, MiddlewareScope};
+use crate::realtime_client::bearer_header;
+use crate::realtime_client::ChannelRouter;
 use crate::realtime_client::ClientManager;
 use crate::realtime_client::ClientManagerSync;
-use crate::realtime_presence::PresenceCallbackMap;
+use crate::realtime_client::WsSender;
 use crate::realtime_presence::RealtimePresence;
+use crate::realtime_presence::{PresenceCallbacks, PresenceJoinArgs, PresenceLeaveArgs};
+#[cfg(feature = "schema")]
+use crate::schema::{BroadcastSchemas, InvalidBroadcast, SchemaError};
 use crate::Responder;
 
 use log::debug;
+use serde::{de::DeserializeOwned, Serialize};
 use serde_json::Value;
 use tokio::{
     runtime::Runtime,
     sync::{
         mpsc::{self, error::SendError, UnboundedReceiver, UnboundedSender},
         oneshot::{self, error::RecvError},
-        Mutex,
+        Mutex, Semaphore,
     },
     task::JoinHandle,
 };
@@ -21,29 +29,114 @@ use crate::message::{
     payload::{
         AccessTokenPayload, BroadcastConfig, BroadcastPayload, JoinConfig, JoinPayload, Payload,
         PayloadStatus, PostgresChange, PostgresChangesEvent, PostgresChangesPayload,
-        PresenceConfig,
+        PresenceConfig, PresenceTrackPayload,
     },
-    presence::{PresenceEvent, PresenceState},
+    presence::{PhxMap, PresenceEvent, PresenceList, PresenceState},
     MessageEvent, PostgresChangeFilter, RealtimeMessage,
 };
 
 use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Instant;
 use std::{collections::HashMap, sync::Arc};
 
+pub(crate) type CallbackFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Sync or async callback taking a `T`
+#[derive(Clone)]
+pub(crate) enum Callback<T> {
+    Sync(Arc<dyn Fn(&T) + Send + Sync>),
+    Async(Arc<dyn Fn(T) -> CallbackFuture + Send + Sync>),
+}
+
+impl<T: Clone> Callback<T> {
+    fn new_async<F, Fut>(callback: F) -> Self
+    where
+        F: Fn(T) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        Callback::Async(Arc::new(move |arg| Box::pin(callback(arg))))
+    }
+
+    /// Sync callbacks run inline, async callbacks are handed to the [CallbackRunner]
+    pub(crate) async fn call(&self, arg: &T, runner: &CallbackRunner) {
+        match self {
+            Callback::Sync(f) => {
+                let started = Instant::now();
+                f(arg);
+                runner.metrics.callback(started.elapsed());
+            }
+            Callback::Async(f) => runner.run(f(arg.clone())).await,
+        }
+    }
+}
+
 #[derive(Clone)]
-struct CdcCallback(
-    PostgresChangeFilter,
-    Arc<dyn Fn(&PostgresChangesPayload) + Send + Sync>,
-);
+struct CdcCallback(PostgresChangeFilter, Callback<PostgresChangesPayload>);
 
-#[allow(clippy::type_complexity)]
 #[derive(Clone)]
-struct BroadcastCallback(Arc<dyn Fn(&HashMap<String, Value>) + Send + Sync>);
+struct BroadcastCallback(Callback<HashMap<String, Value>>);
 
+/// Execution mode for a channel's async callbacks
+///
+/// Sync callbacks always run inline on the channel's message task.
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub enum CallbackMode {
+    /// Await each async callback before handling the next message. Preserves message ordering.
+    #[default]
+    Sequential,
+    /// Spawn async callbacks, running at most `n` at once. Ordering is not guaranteed.
+    ///
+    /// The channel stops reading messages while all `n` slots are busy.
+    Parallel(usize),
+}
+
+/// Runs async callbacks according to the channel's [CallbackMode]
 #[derive(Clone)]
-pub(crate) struct PresenceCallback(
-    pub Arc<dyn Fn(String, PresenceState, PresenceState) + Send + Sync>,
-);
+pub(crate) struct CallbackRunner {
+    permits: Option<Arc<Semaphore>>,
+    rt: Arc<Runtime>,
+    metrics: Arc<ClientMetrics>,
+}
+
+impl CallbackRunner {
+    fn new(mode: CallbackMode, rt: Arc<Runtime>, metrics: Arc<ClientMetrics>) -> Self {
+        let permits = match mode {
+            CallbackMode::Sequential => None,
+            CallbackMode::Parallel(n) => Some(Arc::new(Semaphore::new(n.max(1)))),
+        };
+
+        Self {
+            permits,
+            rt,
+            metrics,
+        }
+    }
+
+    async fn run(&self, future: CallbackFuture) {
+        let metrics = self.metrics.clone();
+        let future = async move {
+            let started = Instant::now();
+            future.await;
+            metrics.callback(started.elapsed());
+        };
+
+        let Some(permits) = &self.permits else {
+            future.await;
+            return;
+        };
+
+        let Ok(permit) = permits.clone().acquire_owned().await else {
+            return;
+        };
+
+        self.rt.spawn(async move {
+            future.await;
+            drop(permit);
+        });
+    }
+}
 
 /// Channel states
 #[derive(PartialEq, Clone, Copy, Debug)]
@@ -55,37 +148,190 @@ pub enum ChannelState {
     Leaving,
 }
 
+/// Reason a channel entered [ChannelState::Errored]
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChannelError {
+    /// The server denied the join, usually a private channel failing its Realtime Authorization
+    /// policies on `realtime.messages`
+    Unauthorized(String),
+    /// The server rejected the join for any other reason
+    JoinRejected(String),
+    /// The client's access token expired before a fresh one was set
+    TokenExpired,
+    /// The server rejected the channel's access token with a `system` error, then closed the
+    /// channel
+    TokenRejected(String),
+}
+
+/// Error codes the server prefixes a denied join's reason with, as in
+/// `"Unauthorized: You do not have permissions to read from this Channel topic: room"`
+const UNAUTHORIZED_CODES: [&str; 1] = ["Unauthorized"];
+
+/// Reasons sent by servers from before error codes, for the same denial
+const UNAUTHORIZED_REASONS: [&str; 1] =
+    ["You do not have permissions to read from this Channel topic"];
+
+/// Error codes the server prefixes a rejected access token's message with, as in
+/// `"InvalidJWTToken: Token has expired 5 seconds ago"`
+const TOKEN_REJECTED_CODES: [&str; 3] = ["InvalidJWTToken", "JwtSignatureError", "MalformedJWT"];
+
+/// Messages sent by servers from before error codes, for the same rejections
+const TOKEN_REJECTED_MESSAGES: [&str; 4] = [
+    "Token has expired",
+    "Invalid JWT Token",
+    "Fields `role` and `exp` are required in JWT",
+    "Access token has expired",
+];
+
+impl ChannelError {
+    /// Classifies the reason the server gave for denying a join. Reasons that aren't a known
+    /// authorization failure, such as rate limits or tenant errors, are [ChannelError::JoinRejected].
+    fn from_reason(reason: impl Into<String>) -> Self {
+        let reason: String = reason.into();
+
+        if has_code(&reason, &UNAUTHORIZED_CODES)
+            || UNAUTHORIZED_REASONS.iter().any(|r| reason.starts_with(r))
+        {
+            return ChannelError::Unauthorized(reason);
+        }
+
+        ChannelError::JoinRejected(reason)
+    }
+
+    /// Classifies a `system` error message. Token failures are reported by the server this way.
+    fn from_system_message(message: impl Into<String>) -> Self {
+        let message: String = message.into();
+
+        if has_code(&message, &TOKEN_REJECTED_CODES)
+            || TOKEN_REJECTED_MESSAGES
+                .iter()
+                .any(|m| message.starts_with(m))
+        {
+            return ChannelError::TokenRejected(message);
+        }
+
+        ChannelError::from_reason(message)
+    }
+
+    /// True for errors the client's `on_auth_error` hook is told about
+    pub fn is_auth_error(&self) -> bool {
+        matches!(
+            self,
+            ChannelError::Unauthorized(_)
+                | ChannelError::TokenExpired
+                | ChannelError::TokenRejected(_)
+        )
+    }
+}
+
+/// True if `reason` starts with one of `codes`, followed by a colon
+fn has_code(reason: &str, codes: &[&str]) -> bool {
+    reason
+        .split_once(':')
+        .is_some_and(|(code, _)| codes.contains(&code.trim()))
+}
+
+/// The `reason` of an error reply, or the whole response if it has none
+fn reply_reason(response: &Value) -> String {
+    match response.get("reason") {
+        Some(Value::String(reason)) => reason.clone(),
+        _ => response.to_string(),
+    }
+}
+
+/// [PresenceState] holding only `key`, as passed to deprecated presence callbacks
+fn key_state(key: &str, metas: PhxMap) -> PresenceState {
+    PresenceState(HashMap::from([(key.to_string(), metas)]))
+}
+
+/// Error returned by the typed presence helpers, [ChannelManager::track_typed()] and
+/// [ChannelManager::presence_list()]
+#[derive(Debug)]
+pub enum PresenceError {
+    /// The payload could not be converted to or from presence data. Tracked payloads must
+    /// serialize to a JSON object.
+    Serde(serde_json::Error),
+    /// The channel's manager task has gone away
+    Recv(RecvError),
+}
+
+impl From<serde_json::Error> for PresenceError {
+    fn from(value: serde_json::Error) -> Self {
+        PresenceError::Serde(value)
+    }
+}
+
+impl From<RecvError> for PresenceError {
+    fn from(value: RecvError) -> Self {
+        PresenceError::Recv(value)
+    }
+}
+
+/// Error returned by [RealtimeChannelBuilder::build()]
+#[derive(Debug)]
+pub enum ChannelBuildError {
+    /// The client's manager task has gone away
+    ClientGone(RecvError),
+    /// A channel with this topic already exists and the client's
+    /// [crate::realtime_client::DuplicateTopicPolicy] is
+    /// [crate::realtime_client::DuplicateTopicPolicy::Reject]
+    DuplicateTopic(String),
+    /// The schema registered for a broadcast event is not a valid JSON Schema
+    #[cfg(feature = "schema")]
+    InvalidSchema { event: String, error: String },
+    /// The client has no connection to build on, as connecting failed. See
+    /// [crate::realtime_client::ClientManager::get_error()].
+    NotConnected,
+    /// A deprecated `on_presence` callback was set for [PresenceEvent::Track] or
+    /// [PresenceEvent::Untrack], which the server never sends
+    UnsupportedPresenceEvent(PresenceEvent),
+}
+
+impl From<RecvError> for ChannelBuildError {
+    fn from(value: RecvError) -> Self {
+        ChannelBuildError::ClientGone(value)
+    }
+}
+
 /// Error for channel send failures
 #[derive(Debug)]
 pub enum ChannelSendError {
     NoChannel,
-    SendError(SendError<RealtimeMessage>),
+    /// The connection is gone. Boxed, as it holds the unsent message.
+    SendError(Box<SendError<RealtimeMessage>>),
     ChannelError(ChannelState),
+    /// A [Middleware] dropped the message, so it was never sent. Broadcasts that fail to
+    /// encrypt end up here.
+    Dropped,
+    /// The broadcast payload failed its event's schema, so it was never sent. Only with
+    /// [RealtimeChannelBuilder::set_validate_outgoing()].
+    #[cfg(feature = "schema")]
+    InvalidPayload(Vec<SchemaError>),
 }
 
+type JoinResponder = Responder<Result<(), ChannelError>>;
+
 pub(crate) enum ChannelManagerMessage {
     Subscribe,
     Unsubscribe {
-        res: Responder<Result<ChannelState, ChannelSendError>>,
+        res: Responder<Result<RealtimeChannelBuilder, ChannelSendError>>,
     },
     SubscribeBlocking {
-        res: Responder<()>,
+        res: JoinResponder,
     },
     Broadcast {
         payload: BroadcastPayload,
+        res: Responder<Result<(), ChannelSendError>>,
     },
     ClientTx {
-        new_tx: UnboundedSender<RealtimeMessage>,
+        new_tx: WsSender,
         res: Responder<()>,
     },
     GetState {
         res: Responder<ChannelState>,
     },
-    GetTx {
-        res: Responder<UnboundedSender<RealtimeMessage>>,
-    },
-    GetTopic {
-        res: Responder<String>,
+    GetError {
+        res: Responder<Option<ChannelError>>,
     },
     GetPresenceState {
         res: Responder<PresenceState>,
@@ -97,9 +343,11 @@ pub(crate) enum ChannelManagerMessage {
     PresenceUntrack {
         res: Responder<()>,
     },
+    JoinOk,
     ReAuth {
         res: Responder<()>,
     },
+    TokenExpired,
 }
 
 /// Manager struct for a [RealtimeChannel]
@@ -109,32 +357,54 @@ pub(crate) enum ChannelManagerMessage {
 #[derive(Clone, Debug)]
 pub struct ChannelManager {
     pub(crate) tx: UnboundedSender<ChannelManagerMessage>,
+    topic: String,
     rt: Arc<Runtime>,
 }
 
+/// Managers are equal when they control the same channel
+impl PartialEq for ChannelManager {
+    fn eq(&self, other: &Self) -> bool {
+        self.tx.same_channel(&other.tx)
+    }
+}
+
 impl ChannelManager {
+    pub(crate) fn topic(&self) -> &str {
+        &self.topic
+    }
     /// Send a JoinMessage for the channel
     pub fn subscribe(&self) {
         let _ = self.send(ChannelManagerMessage::Subscribe);
     }
     /// Leave the channel and stop recieving messages
     ///
-    /// Once unsubscribed this manager is useless and should be dropped
-    // TODO return a preconfigured ChannelBuilder here, like client does?
-    pub async fn unsubscribe(&self) -> Result<Result<ChannelState, ChannelSendError>, RecvError> {
+    /// Returns a preconfigured [RealtimeChannelBuilder], including topic, configs and callbacks,
+    /// for modification or rejoining. Once unsubscribed this manager is useless and should be
+    /// dropped.
+    pub async fn unsubscribe(
+        &self,
+    ) -> Result<Result<RealtimeChannelBuilder, ChannelSendError>, RecvError> {
         let (tx, rx) = oneshot::channel();
         let _ = self.send(ChannelManagerMessage::Unsubscribe { res: tx });
         rx.await
     }
     /// Send a JoinMessage for the channel and wait until the server has responded
-    pub async fn subscribe_blocking(&self) -> Result<(), oneshot::error::RecvError> {
+    ///
+    /// A join denied by the server resolves to the [ChannelError] describing why
+    pub async fn subscribe_blocking(
+        &self,
+    ) -> Result<Result<(), ChannelError>, oneshot::error::RecvError> {
         let (tx, rx) = oneshot::channel();
         let _ = self.send(ChannelManagerMessage::SubscribeBlocking { res: tx });
         rx.await
     }
-    /// Send a broadcast message on the channel
-    pub fn broadcast(&self, payload: BroadcastPayload) {
-        let _ = self.send(ChannelManagerMessage::Broadcast { payload });
+    /// Send a broadcast on this channel
+    ///
+    /// Resolves once the broadcast is queued for sending, or with the reason it won't be sent
+    pub async fn broadcast(&self, payload: BroadcastPayload) -> Result<(), ChannelSendError> {
+        let (tx, rx) = oneshot::channel();
+        let _ = self.send(ChannelManagerMessage::Broadcast { payload, res: tx });
+        rx.await.unwrap_or(Err(ChannelSendError::NoChannel))
     }
     /// Track data in Presence
     pub async fn track(&self, payload: HashMap<String, Value>) -> Result<(), RecvError> {
@@ -142,6 +412,11 @@ impl ChannelManager {
         let _ = self.send(ChannelManagerMessage::PresenceTrack { payload, res: tx });
         rx.await
     }
+    /// Track any serializable value in Presence. The value must serialize to a JSON object.
+    pub async fn track_typed<T: Serialize>(&self, payload: &T) -> Result<(), PresenceError> {
+        let payload = serde_json::from_value(serde_json::to_value(payload)?)?;
+        Ok(self.track(payload).await?)
+    }
     /// Stop tracking with Presence
     pub async fn untrack(&self) -> Result<(), RecvError> {
         let (tx, rx) = oneshot::channel();
@@ -154,17 +429,33 @@ impl ChannelManager {
         let _ = self.send(ChannelManagerMessage::GetState { res: tx });
         rx.await
     }
+    /// Returns the reason the channel last entered [ChannelState::Errored], if any
+    pub async fn get_error(&self) -> Result<Option<ChannelError>, RecvError> {
+        let (tx, rx) = oneshot::channel();
+        let _ = self.send(ChannelManagerMessage::GetError { res: tx });
+        rx.await
+    }
     /// Returns the associated channel's topic
     pub async fn get_topic(&self) -> String {
-        let (tx, rx) = oneshot::channel();
-        let _ = self.send(ChannelManagerMessage::GetTopic { res: tx });
-        rx.await.unwrap()
+        self.topic.clone()
     }
     /// Returns the current [PresenceState] of the associated channel
     pub async fn get_presence_state(&self) -> PresenceState {
+        self.presence_state().await.unwrap()
+    }
+    /// Returns the channel's presence keys, each with its metas deserialized into `T`
+    ///
+    /// See [PresenceState::list_typed()]. Fails with [PresenceError::Recv] if the channel's task
+    /// is gone.
+    pub async fn presence_list<T: DeserializeOwned>(
+        &self,
+    ) -> Result<PresenceList<T>, PresenceError> {
+        Ok(self.presence_state().await?.list_typed()?)
+    }
+    async fn presence_state(&self) -> Result<PresenceState, RecvError> {
         let (tx, rx) = oneshot::channel();
         let _ = self.send(ChannelManagerMessage::GetPresenceState { res: tx });
-        rx.await.unwrap()
+        rx.await
     }
     /// Return a sync wrapper [ChannelManagerSync] for this manager
     pub fn to_sync(self) -> ChannelManagerSync {
@@ -176,15 +467,17 @@ impl ChannelManager {
     ) -> Result<(), SendError<ChannelManagerMessage>> {
         self.tx.send(message)
     }
+    /// Reauthorize the channel with the client's current access token
+    ///
+    /// Resolves once the token is pushed. Realtime doesn't answer a token it accepts, and one it
+    /// rejects errors the channel through the `on_auth_error` hook.
     pub(crate) async fn reauth(&self) -> Result<(), RecvError> {
         let (tx, rx) = oneshot::channel();
         let _ = self.send(ChannelManagerMessage::ReAuth { res: tx });
         rx.await
     }
-    pub(crate) async fn get_tx(&self) -> UnboundedSender<RealtimeMessage> {
-        let (tx, rx) = oneshot::channel();
-        let _ = self.send(ChannelManagerMessage::GetTx { res: tx });
-        rx.await.unwrap()
+    pub(crate) fn token_expired(&self) {
+        let _ = self.send(ChannelManagerMessage::TokenExpired);
     }
 }
 
@@ -197,22 +490,32 @@ impl ChannelManagerSync {
     pub fn subscribe(&self) {
         self.inner.subscribe()
     }
-    pub fn unsubscribe(&self) -> Result<Result<ChannelState, ChannelSendError>, RecvError> {
+    /// Leave the channel and stop recieving messages
+    ///
+    /// Returns a preconfigured [RealtimeChannelBuilder] for modification or rejoining
+    pub fn unsubscribe(
+        &self,
+    ) -> Result<Result<RealtimeChannelBuilder, ChannelSendError>, RecvError> {
         self.inner.rt.block_on(self.inner.unsubscribe())
     }
-    pub fn subscribe_blocking(&self) -> Result<(), RecvError> {
+    pub fn subscribe_blocking(&self) -> Result<Result<(), ChannelError>, RecvError> {
         self.inner.rt.block_on(self.inner.subscribe_blocking())
     }
-    pub fn broadcast(&self, payload: BroadcastPayload) {
-        self.inner.broadcast(payload)
+    /// Send a broadcast on this channel, see [ChannelManager::broadcast()]
+    pub fn broadcast(&self, payload: BroadcastPayload) -> Result<(), ChannelSendError> {
+        self.inner.rt.block_on(self.inner.broadcast(payload))
     }
     /// Returns the associated channel's topic
     pub fn get_topic(&self) -> String {
-        self.inner.rt.block_on(self.inner.get_topic())
+        self.inner.topic.clone()
     }
     pub fn get_state(&self) -> Result<ChannelState, RecvError> {
         self.inner.rt.block_on(self.inner.get_state())
     }
+    /// Returns the reason the channel last entered [ChannelState::Errored], if any
+    pub fn get_error(&self) -> Result<Option<ChannelError>, RecvError> {
+        self.inner.rt.block_on(self.inner.get_error())
+    }
     /// Returns the current [PresenceState] of the associated channel
     pub fn get_presence_state(&self) -> PresenceState {
         self.inner.rt.block_on(self.inner.get_presence_state())
@@ -220,9 +523,17 @@ impl ChannelManagerSync {
     pub fn track(&self, payload: HashMap<String, Value>) -> Result<(), RecvError> {
         self.inner.rt.block_on(self.inner.track(payload))
     }
+    /// Track any serializable value in Presence. The value must serialize to a JSON object.
+    pub fn track_typed<T: Serialize>(&self, payload: &T) -> Result<(), PresenceError> {
+        self.inner.rt.block_on(self.inner.track_typed(payload))
+    }
     pub fn untrack(&self) -> Result<(), RecvError> {
         self.inner.rt.block_on(self.inner.untrack())
     }
+    /// Returns the channel's presence keys, each with its metas deserialized into `T`
+    pub fn presence_list<T: DeserializeOwned>(&self) -> Result<PresenceList<T>, PresenceError> {
+        self.inner.rt.block_on(self.inner.presence_list())
+    }
     /// Unwrap the inner [ChannelManager]. Consumes self.
     pub fn to_async(self) -> ChannelManager {
         self.inner
@@ -245,17 +556,28 @@ struct RealtimeChannel {
     pub(crate) id: Uuid,
     pub(crate) cdc_callbacks: Arc<Mutex<HashMap<PostgresChangesEvent, Vec<CdcCallback>>>>,
     pub(crate) broadcast_callbacks: Arc<Mutex<HashMap<String, Vec<BroadcastCallback>>>>,
-    pub(crate) client_tx: mpsc::UnboundedSender<RealtimeMessage>,
+    pub(crate) client_tx: WsSender,
     join_payload: JoinPayload,
     presence: Arc<Mutex<RealtimePresence>>,
+    tracked: Option<Tracked>,
     pub(crate) tx: Option<UnboundedSender<RealtimeMessage>>,
     pub(crate) manager_channel: (
         UnboundedSender<ChannelManagerMessage>,
         UnboundedReceiver<ChannelManagerMessage>,
     ),
     pub(crate) message_handle: Option<JoinHandle<()>>,
+    router: ChannelRouter,
+    join_ref: Arc<Mutex<Option<String>>>,
+    error: Arc<Mutex<Option<ChannelError>>>,
+    join_waiters: Arc<Mutex<Vec<JoinResponder>>>,
+    callback_mode: CallbackMode,
+    callback_runner: CallbackRunner,
+    middleware: MiddlewareChain,
+    #[cfg(feature = "schema")]
+    schemas: BroadcastSchemas,
     rt: Arc<Runtime>,
     access_token: Arc<Mutex<String>>,
+    client: ClientManager,
 }
 
 impl RealtimeChannel {
@@ -266,13 +588,17 @@ impl RealtimeChannel {
                     self.subscribe().await;
                 }
                 ChannelManagerMessage::Unsubscribe { res } => {
-                    res.send(self.unsubscribe().await).unwrap();
+                    let result = match self.unsubscribe().await {
+                        Ok(_state) => Ok(self.to_builder().await),
+                        Err(e) => Err(e),
+                    };
+                    let _ = res.send(result);
                 }
                 ChannelManagerMessage::SubscribeBlocking { res } => {
                     self.subscribe_blocking(res).await;
                 }
-                ChannelManagerMessage::Broadcast { payload } => {
-                    self.broadcast(payload).await.unwrap();
+                ChannelManagerMessage::Broadcast { payload, res } => {
+                    let _ = res.send(self.broadcast(payload).await);
                 }
                 ChannelManagerMessage::ClientTx { new_tx, res } => {
                     self.client_tx = new_tx;
@@ -281,131 +607,289 @@ impl RealtimeChannel {
                 ChannelManagerMessage::GetState { res } => {
                     res.send(*self.state.lock().await).unwrap();
                 }
-                ChannelManagerMessage::GetTx { res } => {
-                    res.send(self.tx.clone().unwrap()).unwrap();
-                }
-                ChannelManagerMessage::GetTopic { res } => {
-                    res.send(self.topic.clone()).unwrap();
+                ChannelManagerMessage::GetError { res } => {
+                    let _ = res.send(self.error.lock().await.clone());
                 }
                 ChannelManagerMessage::PresenceTrack { payload, res } => {
-                    self.track(payload).await.unwrap();
+                    if let Err(e) = self.track(payload).await {
+                        debug!("Track not sent: {:?}", e);
+                    }
                     res.send(()).unwrap();
                 }
                 ChannelManagerMessage::PresenceUntrack { res } => {
-                    self.untrack().await.unwrap();
-                    res.send(()).unwrap()
+                    if let Err(e) = self.untrack().await {
+                        debug!("Untrack not sent: {:?}", e);
+                    }
+                    let _ = res.send(());
+                }
+                ChannelManagerMessage::JoinOk => {
+                    self.retrack().await;
                 }
                 ChannelManagerMessage::GetPresenceState { res } => {
                     let presence = self.presence.lock().await;
                     res.send(presence.state.clone()).unwrap();
                 }
                 ChannelManagerMessage::ReAuth { res } => {
-                    self.reauth().await.unwrap();
-                    res.send(()).unwrap();
+                    if let Err(e) = self.reauth().await {
+                        debug!("Reauth failed: {:?}", e);
+                    }
+                    let _ = res.send(());
+                }
+                ChannelManagerMessage::TokenExpired => {
+                    self.token_expired().await;
                 }
             }
         }
     }
 
     /// Send a join request to the channel
+    ///
+    /// Every join gets a fresh ref, so replies and presence diffs belonging to an earlier join
+    /// can be told apart.
     async fn subscribe(&mut self) {
+        self.refresh_access_token().await;
+
+        let join_ref = format!("{}+join+{}", self.id, Uuid::new_v4());
+
         let join_message = RealtimeMessage {
             event: MessageEvent::PhxJoin,
             topic: self.topic.clone(),
             payload: Payload::Join(self.join_payload.clone()),
-            message_ref: Some(self.id.into()),
+            message_ref: Some(join_ref.clone()),
         };
 
+        *self.join_ref.lock().await = Some(join_ref.clone());
+        self.presence.lock().await.set_channel_join_ref(join_ref);
+
         let mut state = self.state.lock().await;
         *state = ChannelState::Joining;
         drop(state);
 
+        *self.error.lock().await = None;
+
+        if let Some(tx) = self.tx.clone() {
+            self.router.insert(&self.topic, tx).await;
+        }
+
         let _ = self.send(join_message).await;
     }
 
-    async fn subscribe_blocking(&mut self, tx: Responder<()>) {
-        self.subscribe().await;
+    /// Ask the client's access token provider, if any, for a token to join with
+    ///
+    /// A changed token is handed to the client to cascade to the other channels. That runs on its
+    /// own task, as the client waits on every channel while reauthorizing. A token that can't be
+    /// sent is ignored, as if the provider had none.
+    async fn refresh_access_token(&mut self) {
+        let Some(provider) = self.client.access_token_provider() else {
+            return;
+        };
 
-        let state = self.state.clone();
+        let Some(access_token) = provider.fetch().await else {
+            return;
+        };
+
+        if let Err(e) = bearer_header(&access_token) {
+            debug!("Provided access token not used: {:?}", e);
+            return;
+        }
+
+        self.join_payload.access_token = access_token.clone();
+
+        if *self.access_token.lock().await == access_token {
+            return;
+        }
 
+        let client = self.client.clone();
         self.rt.spawn(async move {
-            loop {
-                let state = state.lock().await;
-                if *state == ChannelState::Joined {
-                    break;
-                }
-            }
-            tx.send(()).unwrap();
+            let _ = client.set_access_token(access_token).await;
         });
     }
 
+    async fn subscribe_blocking(&mut self, tx: JoinResponder) {
+        self.join_waiters.lock().await.push(tx);
+        self.subscribe().await;
+    }
+
     fn client_recv(&mut self) {
         let (channel_tx, mut channel_rx) = mpsc::unbounded_channel::<RealtimeMessage>();
+        let route_tx = channel_tx.clone();
         self.tx = Some(channel_tx);
+        let router = self.router.clone();
+        let topic = self.topic.clone();
         let task_state = self.state.clone();
         let task_cdc_cbs = self.cdc_callbacks.clone();
         let task_bc_cbs = self.broadcast_callbacks.clone();
         let id = self.id;
+        let task_join_ref = self.join_ref.clone();
         let presence = self.presence.clone();
+        let runner = self.callback_runner.clone();
+        let task_error = self.error.clone();
+        let join_waiters = self.join_waiters.clone();
+        let manager_tx = self.manager_channel.0.clone();
+        let client = self.client.clone();
+        let middleware = self.middleware.clone();
+        #[cfg(feature = "schema")]
+        let schemas = self.schemas.clone();
+        let manager = ChannelManager {
+            tx: manager_tx.clone(),
+            topic: self.topic.clone(),
+            rt: self.rt.clone(),
+        };
 
         self.message_handle = Some(self.rt.spawn(async move {
             while let Some(message) = channel_rx.recv().await {
-                // get locks
-                let mut broadcast_callbacks = task_bc_cbs.lock().await;
-                let mut cdc_callbacks = task_cdc_cbs.lock().await;
+                let Some(message) = middleware.inbound(message).await else {
+                    client.client_metrics().dropped();
+                    continue;
+                };
+
+                if message.event == MessageEvent::PhxClose {
+                    let mut channel_state = task_state.lock().await;
+
+                    // The server doesn't start a channel before replying to its join, so a close
+                    // arriving while joining belongs to the previous join
+                    if *channel_state == ChannelState::Joining {
+                        continue;
+                    }
+
+                    // Channels closed over an auth error stay with the client, so
+                    // `set_access_token()` can rejoin them with a new token
+                    let auth_error = *channel_state == ChannelState::Errored
+                        && task_error
+                            .lock()
+                            .await
+                            .as_ref()
+                            .is_some_and(|error| error.is_auth_error());
+
+                    // Unroute first, so nothing reaches a channel seen as closed
+                    router.remove(&topic, &route_tx).await;
+                    if !auth_error {
+                        client.forget_channel(&manager);
+                    }
+
+                    // A channel closed over an error keeps it, so a new token can rejoin
+                    if *channel_state != ChannelState::Errored {
+                        *channel_state = ChannelState::Closed;
+                    }
+                    continue;
+                }
+
+                let is_join_reply = message.message_ref.is_some()
+                    && message.message_ref == *task_join_ref.lock().await;
+
+                if is_join_reply {
+                    let result = match &message.payload {
+                        Payload::Response(response) => match response.status {
+                            PayloadStatus::Ok => Some(Ok(())),
+                            PayloadStatus::Error => Some(Err(ChannelError::from_reason(
+                                serde_json::to_string(&response.response).unwrap_or_default(),
+                            ))),
+                        },
+                        Payload::Reply(reply) => match reply.status.as_str() {
+                            "ok" => Some(Ok(())),
+                            _ => Some(Err(ChannelError::from_reason(reply_reason(
+                                &reply.response,
+                            )))),
+                        },
+                        _ => None,
+                    };
+
+                    match result {
+                        Some(Ok(())) => {
+                            *task_state.lock().await = ChannelState::Joined;
+
+                            for waiter in join_waiters.lock().await.drain(..) {
+                                let _ = waiter.send(Ok(()));
+                            }
+
+                            let _ = manager_tx.send(ChannelManagerMessage::JoinOk);
+                            continue;
+                        }
+                        Some(Err(error)) => {
+                            debug!("Join failed: {:?}", error);
+
+                            *task_state.lock().await = ChannelState::Errored;
+                            *task_error.lock().await = Some(error.clone());
+
+                            for waiter in join_waiters.lock().await.drain(..) {
+                                let _ = waiter.send(Err(error.clone()));
+                            }
+
+                            if error.is_auth_error() {
+                                client.auth_error(manager.clone(), error);
+                            }
+                            continue;
+                        }
+                        None => {}
+                    }
+                }
 
                 match message.payload {
                     Payload::Broadcast(payload) => {
-                        if let Some(cb_vec) = broadcast_callbacks.get_mut(&payload.event) {
-                            for cb in cb_vec {
-                                cb.0(&payload.payload);
-                            }
+                        #[cfg(feature = "schema")]
+                        if !schemas.check_incoming(&payload) {
+                            client.client_metrics().dropped();
+                            continue;
+                        }
+
+                        let cb_vec = task_bc_cbs.lock().await.get(&payload.event).cloned();
+                        for cb in cb_vec.unwrap_or_default() {
+                            cb.0.call(&payload.payload, &runner).await;
                         }
                     }
                     Payload::PostgresChanges(ref payload) => {
-                        if let Some(cb_vec) = cdc_callbacks.get_mut(&payload.data.change_type) {
-                            for cb in cb_vec {
-                                if !cb.0.check(&message) {
-                                    continue;
-                                }
-                                cb.1(payload);
-                            }
-                        }
-                        if let Some(cb_vec) = cdc_callbacks.get_mut(&PostgresChangesEvent::All) {
-                            for cb in cb_vec {
-                                if !cb.0.check(&message) {
-                                    continue;
-                                }
-                                cb.1(payload);
-                            }
+                        let cb_vec: Vec<CdcCallback> = {
+                            let cdc_callbacks = task_cdc_cbs.lock().await;
+                            [&payload.data.change_type, &PostgresChangesEvent::All]
+                                .into_iter()
+                                .filter_map(|event| cdc_callbacks.get(event))
+                                .flatten()
+                                .filter(|cb| cb.0.check(&message))
+                                .cloned()
+                                .collect()
+                        };
+                        for cb in cb_vec {
+                            cb.1.call(payload, &runner).await;
                         }
                     }
-                    Payload::Response(join_response) => {
-                        let target_id = message.message_ref.clone().unwrap_or("".to_string());
-                        if target_id != id.to_string() {
-                            return;
+                    Payload::System(ref system)
+                        if system.status == PayloadStatus::Error
+                            && system.extension == "system" =>
+                    {
+                        let error = ChannelError::from_system_message(system.message.clone());
+                        debug!("System error: {:?}", error);
+
+                        *task_state.lock().await = ChannelState::Errored;
+                        *task_error.lock().await = Some(error.clone());
+
+                        for waiter in join_waiters.lock().await.drain(..) {
+                            let _ = waiter.send(Err(error.clone()));
                         }
-                        if join_response.status == PayloadStatus::Ok {
-                            let mut channel_state = task_state.lock().await;
-                            *channel_state = ChannelState::Joined;
-                            drop(channel_state);
+
+                        if error.is_auth_error() {
+                            client.auth_error(manager.clone(), error);
                         }
                     }
+                    Payload::Reply(_) if message.message_ref == Some(format!("{}+leave", id)) => {
+                        router.remove(&topic, &route_tx).await;
+                        client.forget_channel(&manager);
+
+                        *task_state.lock().await = ChannelState::Closed;
+                    }
                     Payload::PresenceDiff(diff) => {
-                        let mut presence = presence.lock().await;
-                        presence.sync_diff(diff.into());
+                        let changes = presence.lock().await.sync_diff(diff.into());
+                        changes.notify(&runner).await;
                     }
-                    Payload::PresenceState(state) => {
-                        let mut presence = presence.lock().await;
-                        presence.sync(state.into());
+                    Payload::PresenceState(state)
+                        if message.event == MessageEvent::PresenceState =>
+                    {
+                        let changes = presence.lock().await.sync(state.into());
+                        changes.notify(&runner).await;
                     }
                     _ => {
                         debug!("Unmatched payload ;_;");
                     }
                 }
-
-                drop(broadcast_callbacks);
-                drop(cdc_callbacks);
             }
         }));
     }
@@ -439,23 +923,79 @@ impl RealtimeChannel {
         }
     }
 
+    /// Returns a [RealtimeChannelBuilder] configured to recreate this channel
+    async fn to_builder(&self) -> RealtimeChannelBuilder {
+        let config = &self.join_payload.config;
+
+        RealtimeChannelBuilder {
+            topic: self.topic.clone(),
+            broadcast: config.broadcast.clone(),
+            presence: config.presence.clone(),
+            // A fresh id, so replies meant for this channel can't reach the rebuilt one
+            id: Uuid::new_v4(),
+            postgres_changes: config.postgres_changes.clone(),
+            private: config.private,
+            cdc_callbacks: self.cdc_callbacks.lock().await.clone(),
+            broadcast_callbacks: self.broadcast_callbacks.lock().await.clone(),
+            presence_callbacks: self.presence.lock().await.callbacks(),
+            callback_mode: self.callback_mode,
+            middleware: self.middleware.clone(),
+            #[cfg(feature = "schema")]
+            schemas: self.schemas.clone(),
+            unsupported_presence_event: None,
+        }
+    }
+
     /// Track provided state in Realtime Presence
     async fn track(&mut self, payload: HashMap<String, Value>) -> Result<(), ChannelSendError> {
         self.send(RealtimeMessage {
             event: MessageEvent::Presence,
             topic: self.topic.clone(),
-            payload: Payload::PresenceTrack(payload.into()),
+            payload: Payload::PresenceTrack(payload.clone().into()),
             message_ref: None,
         })
-        .await
+        .await?;
+
+        // The server applies a track sent after a join to that join, even before replying
+        let join_ref = match *self.state.lock().await {
+            ChannelState::Joining | ChannelState::Joined => self.join_ref.lock().await.clone(),
+            _ => None,
+        };
+
+        self.tracked = Some(Tracked { payload, join_ref });
+
+        Ok(())
+    }
+
+    /// Resend the last tracked payload, if any, after a successful (re)join
+    async fn retrack(&mut self) {
+        if !self.join_payload.config.presence.retrack {
+            return;
+        }
+
+        let Some(tracked) = self.tracked.clone() else {
+            return;
+        };
+
+        if tracked.join_ref.is_some() && tracked.join_ref == *self.join_ref.lock().await {
+            return;
+        }
+
+        debug!("Re-tracking presence on {}", self.topic);
+        let _ = self.track(tracked.payload).await;
     }
 
     /// Sends a message to stop tracking this channel's presence
     async fn untrack(&mut self) -> Result<(), ChannelSendError> {
+        self.tracked = None;
+
         self.send(RealtimeMessage {
-            event: MessageEvent::Untrack,
+            event: MessageEvent::Presence,
             topic: self.topic.clone(),
-            payload: Payload::Empty {},
+            payload: Payload::PresenceTrack(PresenceTrackPayload {
+                event: PresenceEvent::Untrack,
+                payload: HashMap::new(),
+            }),
             message_ref: None,
         })
         .await
@@ -472,13 +1012,25 @@ impl RealtimeChannel {
             return Err(ChannelSendError::ChannelError(*state));
         }
 
-        match self.client_tx.send(message) {
-            Ok(()) => Ok(()),
-            Err(e) => Err(ChannelSendError::SendError(e)),
-        }
+        drop(state);
+
+        let Some(message) = self.middleware.outbound(message).await else {
+            self.client.client_metrics().dropped();
+            return Err(ChannelSendError::Dropped);
+        };
+
+        self.client_tx
+            .send(message)
+            .map_err(ChannelSendError::SendError)
     }
 
     async fn broadcast(&mut self, payload: BroadcastPayload) -> Result<(), ChannelSendError> {
+        #[cfg(feature = "schema")]
+        if let Err(errors) = self.schemas.check_outgoing(&payload) {
+            self.client.client_metrics().dropped();
+            return Err(ChannelSendError::InvalidPayload(errors));
+        }
+
         self.send(RealtimeMessage {
             event: MessageEvent::Broadcast,
             topic: "".into(),
@@ -488,17 +1040,33 @@ impl RealtimeChannel {
         .await
     }
 
+    /// Update the join payload with the client's current token and push it to the server
+    ///
+    /// Channels errored over authorization rejoin instead
     async fn reauth(&mut self) -> Result<(), ChannelSendError> {
         let access_token = self.access_token.lock().await;
         self.join_payload.access_token = access_token.clone();
 
-        let state = self.state.lock().await;
+        let state = *self.state.lock().await;
+        let error = self.error.lock().await.clone();
 
-        if *state != ChannelState::Joined {
+        // Private channels denied under the old token, and channels errored by an expired or
+        // rejected token, get another attempt with the new one
+        let retry = match error {
+            Some(ChannelError::Unauthorized(_)) => self.join_payload.config.private,
+            Some(ChannelError::TokenExpired) | Some(ChannelError::TokenRejected(_)) => true,
+            _ => false,
+        };
+
+        if state == ChannelState::Errored && retry {
+            drop(access_token);
+            self.subscribe().await;
             return Ok(());
         }
 
-        drop(state);
+        if state != ChannelState::Joined {
+            return Ok(());
+        }
 
         let access_token_message = RealtimeMessage {
             event: MessageEvent::AccessToken,
@@ -513,6 +1081,35 @@ impl RealtimeChannel {
 
         self.send(access_token_message).await
     }
+
+    /// Error a joined channel whose access token expired without being replaced
+    async fn token_expired(&mut self) {
+        let mut state = self.state.lock().await;
+        if *state != ChannelState::Joined {
+            return;
+        }
+
+        *state = ChannelState::Errored;
+        *self.error.lock().await = Some(ChannelError::TokenExpired);
+        drop(state);
+
+        self.client.auth_error(
+            ChannelManager {
+                tx: self.manager_channel.0.clone(),
+                topic: self.topic.clone(),
+                rt: self.rt.clone(),
+            },
+            ChannelError::TokenExpired,
+        );
+    }
+}
+
+/// A channel's last tracked payload
+#[derive(Clone)]
+struct Tracked {
+    payload: HashMap<String, Value>,
+    /// Join the track was sent after, None if the channel wasn't joining or joined
+    join_ref: Option<String>,
 }
 
 /// Builder struct for [RealtimeChannel]
@@ -523,9 +1120,16 @@ pub struct RealtimeChannelBuilder {
     presence: PresenceConfig,
     id: Uuid,
     postgres_changes: Vec<PostgresChange>,
+    private: bool,
     cdc_callbacks: HashMap<PostgresChangesEvent, Vec<CdcCallback>>,
     broadcast_callbacks: HashMap<String, Vec<BroadcastCallback>>,
-    presence_callbacks: PresenceCallbackMap,
+    presence_callbacks: PresenceCallbacks,
+    callback_mode: CallbackMode,
+    middleware: MiddlewareChain,
+    #[cfg(feature = "schema")]
+    schemas: BroadcastSchemas,
+    /// Event of a deprecated presence callback that can never fire, failing the build
+    unsupported_presence_event: Option<PresenceEvent>,
 }
 
 impl RealtimeChannelBuilder {
@@ -538,12 +1142,22 @@ impl RealtimeChannelBuilder {
             presence: Default::default(),
             id: Uuid::new_v4(),
             postgres_changes: Default::default(),
+            private: false,
             cdc_callbacks: Default::default(),
             broadcast_callbacks: Default::default(),
             presence_callbacks: Default::default(),
+            callback_mode: Default::default(),
+            middleware: Default::default(),
+            #[cfg(feature = "schema")]
+            schemas: Default::default(),
+            unsupported_presence_event: None,
         }
     }
 
+    pub(crate) fn topic(&self) -> &str {
+        &self.topic
+    }
+
     /// Set the topic of the channel
     pub fn set_topic(&mut self, topic: impl Into<String>) -> &mut Self {
         self.topic = format!("realtime:{}", topic.into());
@@ -562,18 +1176,109 @@ impl RealtimeChannelBuilder {
         self
     }
 
+    /// Mark this channel as private. Private channels are authorized by Realtime Authorization
+    /// RLS policies on `realtime.messages`, using the client's access token.
+    ///
+    /// A denied join errors the channel with [ChannelError::Unauthorized]
+    pub fn set_private(&mut self, private: bool) -> &mut Self {
+        self.private = private;
+        self
+    }
+
+    /// Set how async callbacks are executed on this channel. Defaults to
+    /// [CallbackMode::Sequential]
+    pub fn set_callback_mode(&mut self, callback_mode: CallbackMode) -> &mut Self {
+        self.callback_mode = callback_mode;
+        self
+    }
+
+    /// Add a [Middleware] seeing this channel's messages. Runs inside any client middleware,
+    /// see [crate::middleware].
+    pub fn add_middleware(&mut self, middleware: impl Middleware + 'static) -> &mut Self {
+        self.middleware.push(MiddlewareScope::default(), middleware);
+        self
+    }
+
+    /// Compress this channel's large broadcast payloads, see [crate::compression]. Runs as
+    /// broadcast-scoped middleware. Set before [Self::set_encryption()], as ciphertext doesn't
+    /// compress. Setting it again replaces the previous compression in place.
+    #[cfg(feature = "compression")]
+    pub fn set_compression(
+        &mut self,
+        compression: crate::compression::BroadcastCompression,
+    ) -> &mut Self {
+        self.middleware.set(
+            "compression",
+            MiddlewareScope::events([MessageEvent::Broadcast]),
+            compression,
+        );
+        self
+    }
+
+    /// Encrypt this channel's broadcast payloads end to end, see [crate::encryption]. Runs as
+    /// broadcast-scoped middleware, after middleware added before it on the way out. Setting it
+    /// again replaces the previous encryption in place, so payloads are only encrypted once.
+    #[cfg(feature = "encryption")]
+    pub fn set_encryption(
+        &mut self,
+        encryption: crate::encryption::BroadcastEncryption,
+    ) -> &mut Self {
+        self.middleware.set(
+            "encryption",
+            MiddlewareScope::events([MessageEvent::Broadcast]),
+            encryption,
+        );
+        self
+    }
+
+    /// Add a [Middleware] seeing only this channel's messages that `scope` matches
+    pub fn add_scoped_middleware(
+        &mut self,
+        scope: MiddlewareScope,
+        middleware: impl Middleware + 'static,
+    ) -> &mut Self {
+        self.middleware.push(scope, middleware);
+        self
+    }
+
     /// Add a postgres changes callback to this channel
     pub fn on_postgres_change(
         &mut self,
         event: PostgresChangesEvent,
         filter: PostgresChangeFilter,
         callback: impl Fn(&PostgresChangesPayload) + 'static + Send + Sync,
+    ) -> &mut Self {
+        self.add_cdc_callback(event, filter, Callback::Sync(Arc::new(callback)))
+    }
+
+    /// Add an async postgres changes callback to this channel
+    ///
+    /// Execution is controlled by [Self::set_callback_mode()]
+    pub fn on_postgres_change_async<F, Fut>(
+        &mut self,
+        event: PostgresChangesEvent,
+        filter: PostgresChangeFilter,
+        callback: F,
+    ) -> &mut Self
+    where
+        F: Fn(PostgresChangesPayload) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.add_cdc_callback(event, filter, Callback::new_async(callback))
+    }
+
+    fn add_cdc_callback(
+        &mut self,
+        event: PostgresChangesEvent,
+        filter: PostgresChangeFilter,
+        callback: Callback<PostgresChangesPayload>,
     ) -> &mut Self {
         self.postgres_changes.push(PostgresChange {
             event: event.clone(),
             schema: filter.schema.clone(),
             table: filter.table.clone().unwrap_or("".into()),
             filter: filter.filter.clone(),
+            ..Default::default()
         });
 
         if self.cdc_callbacks.get_mut(&event).is_none() {
@@ -583,27 +1288,177 @@ impl RealtimeChannelBuilder {
         self.cdc_callbacks
             .get_mut(&event)
             .unwrap_or(&mut vec![])
-            .push(CdcCallback(filter, Arc::new(callback)));
+            .push(CdcCallback(filter, callback));
 
         self
     }
 
+    /// Add a callback run once per presence sync, with the full presence state
+    ///
+    /// Called after each `presence_state` and after each applied `presence_diff`
+    pub fn on_presence_sync(
+        &mut self,
+        callback: impl Fn(&PresenceState) + Send + 'static + Sync,
+    ) -> &mut Self {
+        self.presence_callbacks
+            .sync
+            .push(Callback::Sync(Arc::new(callback)));
+        self
+    }
+
+    /// Add an async presence sync callback to this channel
+    ///
+    /// Execution is controlled by [Self::set_callback_mode()]
+    pub fn on_presence_sync_async<F, Fut>(&mut self, callback: F) -> &mut Self
+    where
+        F: Fn(PresenceState) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.presence_callbacks
+            .sync
+            .push(Callback::new_async(callback));
+        self
+    }
+
+    /// Add a callback run for each presence key that gains metas
+    ///
+    /// Called with the key, its metas before the join (`None` for a new key) and the joined
+    /// metas
+    pub fn on_presence_join(
+        &mut self,
+        callback: impl Fn(&str, Option<&PhxMap>, &PhxMap) + Send + 'static + Sync,
+    ) -> &mut Self {
+        self.presence_callbacks.join.push(Callback::Sync(Arc::new(
+            move |(key, current, joined): &PresenceJoinArgs| {
+                callback(key, current.as_ref(), joined)
+            },
+        )));
+        self
+    }
+
+    /// Add an async presence join callback to this channel
+    ///
+    /// Execution is controlled by [Self::set_callback_mode()]
+    pub fn on_presence_join_async<F, Fut>(&mut self, callback: F) -> &mut Self
+    where
+        F: Fn(String, Option<PhxMap>, PhxMap) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.presence_callbacks.join.push(Callback::new_async(
+            move |(key, current, joined): PresenceJoinArgs| callback(key, current, joined),
+        ));
+        self
+    }
+
+    /// Add a callback run for each presence key that loses metas
+    ///
+    /// Called with the key, its remaining metas and the left metas
+    pub fn on_presence_leave(
+        &mut self,
+        callback: impl Fn(&str, &PhxMap, &PhxMap) + Send + 'static + Sync,
+    ) -> &mut Self {
+        self.presence_callbacks.leave.push(Callback::Sync(Arc::new(
+            move |(key, current, left): &PresenceLeaveArgs| callback(key, current, left),
+        )));
+        self
+    }
+
+    /// Add an async presence leave callback to this channel
+    ///
+    /// Execution is controlled by [Self::set_callback_mode()]
+    pub fn on_presence_leave_async<F, Fut>(&mut self, callback: F) -> &mut Self
+    where
+        F: Fn(String, PhxMap, PhxMap) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.presence_callbacks.leave.push(Callback::new_async(
+            move |(key, current, left): PresenceLeaveArgs| callback(key, current, left),
+        ));
+        self
+    }
+
     /// Add a presence callback to this channel
+    ///
+    /// Join and leave callbacks get the key, its presence before the join (or what remains
+    /// after the leave) and the joined or left presence, each as a [PresenceState] holding only
+    /// that key. Sync callbacks run once per sync with an empty key, the previous and the new
+    /// state. The server never sends track or untrack events, so callbacks for them fail
+    /// [Self::build()] with [ChannelBuildError::UnsupportedPresenceEvent].
+    #[deprecated(note = "use on_presence_sync, on_presence_join or on_presence_leave")]
     pub fn on_presence(
         &mut self,
         event: PresenceEvent,
         callback: impl Fn(String, PresenceState, PresenceState) + Send + 'static + Sync,
     ) -> &mut Self {
-        if self.presence_callbacks.get_mut(&event).is_none() {
-            self.presence_callbacks.insert(event.clone(), vec![]);
+        match event {
+            PresenceEvent::Join => self.on_presence_join(move |key, current, joined| {
+                callback(
+                    key.into(),
+                    key_state(key, current.cloned().unwrap_or_default()),
+                    key_state(key, joined.clone()),
+                )
+            }),
+            PresenceEvent::Leave => self.on_presence_leave(move |key, current, left| {
+                callback(
+                    key.into(),
+                    key_state(key, current.clone()),
+                    key_state(key, left.clone()),
+                )
+            }),
+            PresenceEvent::Sync => {
+                let prev_state = std::sync::Mutex::new(PresenceState::default());
+
+                self.on_presence_sync(move |state| {
+                    let prev_state =
+                        std::mem::replace(&mut *prev_state.lock().unwrap(), state.clone());
+                    callback(String::new(), prev_state, state.clone())
+                })
+            }
+            PresenceEvent::Track | PresenceEvent::Untrack => {
+                self.unsupported_presence_event = Some(event);
+                self
+            }
         }
+    }
 
-        self.presence_callbacks
-            .get_mut(&event)
-            .unwrap_or(&mut vec![])
-            .push(PresenceCallback(Arc::new(callback)));
-
-        self
+    /// Add an async presence callback to this channel
+    ///
+    /// Called as [Self::on_presence()] callbacks are. Execution is controlled by
+    /// [Self::set_callback_mode()]
+    #[deprecated(
+        note = "use on_presence_sync_async, on_presence_join_async or on_presence_leave_async"
+    )]
+    pub fn on_presence_async<F, Fut>(&mut self, event: PresenceEvent, callback: F) -> &mut Self
+    where
+        F: Fn(String, PresenceState, PresenceState) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        match event {
+            PresenceEvent::Join => self.on_presence_join_async(move |key, current, joined| {
+                let (current, joined) = (
+                    key_state(&key, current.unwrap_or_default()),
+                    key_state(&key, joined),
+                );
+                callback(key, current, joined)
+            }),
+            PresenceEvent::Leave => self.on_presence_leave_async(move |key, current, left| {
+                let (current, left) = (key_state(&key, current), key_state(&key, left));
+                callback(key, current, left)
+            }),
+            PresenceEvent::Sync => {
+                let prev_state = std::sync::Mutex::new(PresenceState::default());
+
+                self.on_presence_sync_async(move |state| {
+                    let prev_state =
+                        std::mem::replace(&mut *prev_state.lock().unwrap(), state.clone());
+                    callback(String::new(), prev_state, state)
+                })
+            }
+            PresenceEvent::Track | PresenceEvent::Untrack => {
+                self.unsupported_presence_event = Some(event);
+                self
+            }
+        }
     }
 
     /// Add a broadcast callback to this channel
@@ -612,8 +1467,55 @@ impl RealtimeChannelBuilder {
         event: impl Into<String>,
         callback: impl Fn(&HashMap<String, Value>) + Sync + Send + 'static,
     ) -> &mut Self {
-        let event: String = event.into();
+        self.add_broadcast_callback(event.into(), Callback::Sync(Arc::new(callback)))
+    }
+
+    /// Add an async broadcast callback to this channel
+    ///
+    /// Execution is controlled by [Self::set_callback_mode()]
+    pub fn on_broadcast_async<F, Fut>(&mut self, event: impl Into<String>, callback: F) -> &mut Self
+    where
+        F: Fn(HashMap<String, Value>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.add_broadcast_callback(event.into(), Callback::new_async(callback))
+    }
+
+    /// Validate incoming payloads for broadcast `event` against a JSON Schema, see
+    /// [crate::schema]. Replaces any schema set for the event before.
+    ///
+    /// The schema is compiled on [Self::build()], which fails with
+    /// [ChannelBuildError::InvalidSchema] if it isn't valid.
+    #[cfg(feature = "schema")]
+    pub fn set_broadcast_schema(&mut self, event: impl Into<String>, schema: Value) -> &mut Self {
+        self.schemas.insert(event.into(), schema);
+        self
+    }
+
+    /// Set a callback for incoming broadcast payloads failing their event's schema. The payload
+    /// is dropped either way.
+    #[cfg(feature = "schema")]
+    pub fn on_invalid(
+        &mut self,
+        callback: impl Fn(&InvalidBroadcast) + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.schemas.set_on_invalid(callback);
+        self
+    }
+
+    /// Also validate payloads passed to [ChannelManager::broadcast()]. Invalid payloads are not
+    /// sent, and `broadcast()` returns [ChannelSendError::InvalidPayload]. Default false.
+    #[cfg(feature = "schema")]
+    pub fn set_validate_outgoing(&mut self, validate: bool) -> &mut Self {
+        self.schemas.set_validate_outgoing(validate);
+        self
+    }
 
+    fn add_broadcast_callback(
+        &mut self,
+        event: String,
+        callback: Callback<HashMap<String, Value>>,
+    ) -> &mut Self {
         if self.broadcast_callbacks.get_mut(&event).is_none() {
             self.broadcast_callbacks.insert(event.clone(), vec![]);
         }
@@ -621,18 +1523,19 @@ impl RealtimeChannelBuilder {
         self.broadcast_callbacks
             .get_mut(&event)
             .unwrap_or(&mut vec![])
-            .push(BroadcastCallback(Arc::new(callback)));
+            .push(BroadcastCallback(callback));
 
         self
     }
 
-    fn build_common(
+    pub(crate) fn build_common(
         &mut self,
-        client_tx: UnboundedSender<RealtimeMessage>,
+        client_tx: WsSender,
         access_token: String,
         access_token_arc: Arc<Mutex<String>>,
-        rt: Arc<Runtime>,
+        client: &ClientManager,
     ) -> ChannelManager {
+        let rt = client.get_rt();
         let state = Arc::new(Mutex::new(ChannelState::Closed));
         let cdc_callbacks = Arc::new(Mutex::new(self.cdc_callbacks.clone()));
         let broadcast_callbacks = Arc::new(Mutex::new(self.broadcast_callbacks.clone()));
@@ -640,6 +1543,20 @@ impl RealtimeChannelBuilder {
 
         let mut channel = RealtimeChannel {
             access_token: access_token_arc,
+            client: client.clone(),
+            router: client.router(),
+            join_ref: Default::default(),
+            error: Default::default(),
+            join_waiters: Default::default(),
+            callback_mode: self.callback_mode,
+            callback_runner: CallbackRunner::new(
+                self.callback_mode,
+                rt.clone(),
+                client.client_metrics(),
+            ),
+            middleware: self.middleware.clone(),
+            #[cfg(feature = "schema")]
+            schemas: self.schemas.clone(),
             rt: rt.clone(),
             tx: None,
             topic: self.topic.clone(),
@@ -653,9 +1570,11 @@ impl RealtimeChannelBuilder {
                     broadcast: self.broadcast.clone(),
                     presence: self.presence.clone(),
                     postgres_changes: self.postgres_changes.clone(),
+                    private: self.private,
                 },
                 access_token,
             },
+            tracked: None,
             presence: Arc::new(Mutex::new(RealtimePresence::from_channel_builder(
                 self.presence_callbacks.clone(),
             ))),
@@ -668,45 +1587,70 @@ impl RealtimeChannelBuilder {
 
         let _handle = rt.spawn(async move { channel.manager_recv().await });
 
-        ChannelManager { tx, rt }
+        ChannelManager {
+            tx,
+            topic: self.topic.clone(),
+            rt,
+        }
     }
 
-    // TODO unify the builds using a clientmanager trait. Need async-trait
-
     /// Consume self and return a new [ChannelManagerSync] that controls the newly created channel
     /// Automatically assigns the new channel in the client.
     ///
+    /// If the client already holds a channel on this topic the outcome depends on the client's
+    /// [crate::realtime_client::DuplicateTopicPolicy].
+    ///
     /// For async applications you may want `self::build()`
     pub fn build_sync(
         &mut self,
         client: &ClientManagerSync,
-    ) -> Result<ChannelManagerSync, RecvError> {
-        let client_tx = client.clone().get_ws_tx().unwrap();
-        let access_token = client.clone().get_access_token().unwrap();
-        let access_token_arc = client.clone().get_access_token_arc().unwrap();
-
-        let channel_manager =
-            self.build_common(client_tx, access_token, access_token_arc, client.get_rt());
-
-        client.add_channel(channel_manager.clone()).unwrap();
-
-        Ok(channel_manager.to_sync())
+    ) -> Result<ChannelManagerSync, ChannelBuildError> {
+        client
+            .get_rt()
+            .block_on(self.build(&client.clone().to_async()))
+            .map(|manager| manager.to_sync())
     }
 
     /// Consume self and return a new [ChannelManager] that controls the newly created channel
     /// Automatically assigns the new channel in the client.
     ///
+    /// If the client already holds a channel on this topic the outcome depends on the client's
+    /// [crate::realtime_client::DuplicateTopicPolicy].
+    ///
     /// For sync applications you may want `self::build_sync()`
-    pub async fn build(&mut self, client: &ClientManager) -> Result<ChannelManager, RecvError> {
-        let client_tx = client.clone().get_ws_tx().await?;
-        let access_token = client.clone().get_access_token().await?;
-        let access_token_arc = client.clone().get_access_token_arc().await?;
+    pub async fn build(
+        &mut self,
+        client: &ClientManager,
+    ) -> Result<ChannelManager, ChannelBuildError> {
+        if let Some(event) = self.unsupported_presence_event.clone() {
+            return Err(ChannelBuildError::UnsupportedPresenceEvent(event));
+        }
+
+        #[cfg(feature = "schema")]
+        self.schemas.compile()?;
+
+        client.build_channel(self.clone()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presence_list_without_a_channel_task_is_a_recv_error() {
+        let rt = Arc::new(Runtime::new().unwrap());
+        let (tx, rx) = mpsc::unbounded_channel();
+        drop(rx);
 
-        let channel_manager =
-            self.build_common(client_tx, access_token, access_token_arc, client.get_rt());
+        let manager = ChannelManager {
+            tx,
+            topic: "realtime:room".into(),
+            rt: rt.clone(),
+        };
 
-        client.add_channel(channel_manager.clone()).await.unwrap();
+        let list = rt.block_on(manager.presence_list::<Value>());
 
-        Ok(channel_manager)
+        assert!(matches!(list, Err(PresenceError::Recv(_))));
     }
 }
@@ -1,28 +1,102 @@
 use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::time::SystemTime;
 use std::{collections::HashMap, time::Duration};
 
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+
 use log::debug;
 use tokio::runtime::Runtime;
 use tokio::sync::mpsc::error::SendError;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
-use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio::sync::{mpsc, oneshot, Mutex, RwLock};
 use tokio::task::JoinHandle;
 use tokio::time::sleep;
 use tokio_stream::wrappers::UnboundedReceiverStream;
-use tokio_tungstenite::connect_async;
 use tokio_tungstenite::tungstenite::client::IntoClientRequest;
 use tokio_tungstenite::tungstenite::http::{HeaderMap, HeaderValue, Request, Uri};
+use tokio_tungstenite::tungstenite::Message;
 
-use futures_util::{pin_mut, StreamExt};
+use futures_util::{SinkExt, StreamExt};
 
 use crate::message::RealtimeMessage;
+use crate::metrics::{ClientMetrics, MetricsSnapshot};
+use crate::middleware::{Middleware, MiddlewareChain, MiddlewareScope};
 use crate::realtime_channel::{
-    ChannelManager, ChannelManagerMessage, ChannelState, RealtimeChannelBuilder,
+    ChannelBuildError, ChannelError, ChannelManager, ChannelManagerMessage, ChannelManagerSync,
+    RealtimeChannelBuilder,
 };
+use crate::recording::{Recorder, RecordingTransport};
+use crate::transport::{Transport, TransportHandle};
 use crate::Responder;
 
-pub type Interceptor = fn(RealtimeMessage) -> RealtimeMessage;
+pub type AccessTokenFuture = Pin<Box<dyn Future<Output = Option<String>> + Send>>;
+
+/// Source of fresh access tokens, e.g. a session store that refreshes expired JWTs
+///
+/// Asked for a token before connecting, before each channel join and on reconnect. Returning
+/// `None` keeps the token the client already holds. A changed token is cascaded to every channel
+/// as with [ClientManager::set_access_token()].
+///
+/// Implemented for any `Fn() -> impl Future<Output = Option<String>>`:
+/// ```
+/// # use realtime_rs::realtime_client::RealtimeClientBuilder;
+/// let builder = RealtimeClientBuilder::new("http://127.0.0.1:54321/realtime/v1", "anon_key")
+///     .set_access_token_provider(|| async { Some("fresh_jwt".to_string()) })
+///     .clone();
+/// ```
+pub trait AccessTokenProvider: Send + Sync {
+    fn access_token(&self) -> AccessTokenFuture;
+}
+
+impl<F, Fut> AccessTokenProvider for F
+where
+    F: Fn() -> Fut + Send + Sync,
+    Fut: Future<Output = Option<String>> + Send + 'static,
+{
+    fn access_token(&self) -> AccessTokenFuture {
+        Box::pin(self())
+    }
+}
+
+/// Shareable handle to an [AccessTokenProvider]
+#[derive(Clone)]
+pub(crate) struct TokenProvider(Arc<dyn AccessTokenProvider>);
+
+impl TokenProvider {
+    pub(crate) async fn fetch(&self) -> Option<String> {
+        self.0.access_token().await
+    }
+}
+
+impl Debug for TokenProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("TokenProvider")
+    }
+}
+
+/// Hook set with [RealtimeClientBuilder::on_token_expiring()]
+#[derive(Clone)]
+pub(crate) struct TokenExpiringHook(Arc<dyn Fn(ClientManager) + Send + Sync>);
+
+impl Debug for TokenExpiringHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("TokenExpiringHook")
+    }
+}
+
+/// Hook set with [RealtimeClientBuilder::on_auth_error()]
+#[derive(Clone)]
+pub(crate) struct AuthErrorHook(Arc<dyn Fn(ChannelManager, ChannelError) + Send + Sync>);
+
+impl Debug for AuthErrorHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("AuthErrorHook")
+    }
+}
 
 /// Connection state of [RealtimeClient]
 #[derive(PartialEq, Debug, Default, Clone, Copy)]
@@ -38,8 +112,94 @@ pub enum ClientState {
     Closed,
 }
 
-/// Error returned by [RealtimeClient::connect()]
-#[derive(Debug, PartialEq)]
+/// Sender half of the client's outgoing message queue
+///
+/// Counts every message it queues towards [MetricsSnapshot::queue_depth], so messages sent
+/// through [ClientManagerSync::get_ws_tx()] are counted like any other.
+#[derive(Clone, Debug)]
+pub struct WsSender {
+    tx: UnboundedSender<RealtimeMessage>,
+    metrics: Arc<ClientMetrics>,
+}
+
+impl WsSender {
+    /// Queue `message` for the websocket. Fails once the connection is gone, boxed as the error
+    /// holds the unsent message.
+    pub fn send(&self, message: RealtimeMessage) -> Result<(), Box<SendError<RealtimeMessage>>> {
+        // Counted first, as the send task can take the message off the queue straight away
+        self.metrics.queued();
+
+        self.tx.send(message).map_err(|e| {
+            self.metrics.dequeued();
+            Box::new(e)
+        })
+    }
+}
+
+/// Topic to channel lookup for incoming messages
+///
+/// Channels register themselves when joining and deregister once closed, so the receive task
+/// dispatches each message with a single map lookup.
+#[derive(Clone, Default, Debug)]
+pub(crate) struct ChannelRouter(
+    Arc<RwLock<HashMap<String, Vec<UnboundedSender<RealtimeMessage>>>>>,
+);
+
+impl ChannelRouter {
+    pub(crate) async fn insert(&self, topic: &str, tx: UnboundedSender<RealtimeMessage>) {
+        let mut routes = self.0.write().await;
+        let senders = routes.entry(topic.to_string()).or_default();
+
+        if !senders.iter().any(|s| s.same_channel(&tx)) {
+            senders.push(tx);
+        }
+    }
+
+    pub(crate) async fn remove(&self, topic: &str, tx: &UnboundedSender<RealtimeMessage>) {
+        let mut routes = self.0.write().await;
+
+        let Some(senders) = routes.get_mut(topic) else {
+            return;
+        };
+
+        senders.retain(|s| !s.same_channel(tx));
+
+        if senders.is_empty() {
+            routes.remove(topic);
+        }
+    }
+
+    /// Returns false if no channel took the message
+    async fn route(&self, message: RealtimeMessage) -> bool {
+        let routes = self.0.read().await;
+
+        let Some(senders) = routes.get(&message.topic) else {
+            debug!("No channel for topic {}", message.topic);
+            return false;
+        };
+
+        for tx in senders {
+            let _ = tx.send(message.clone());
+        }
+
+        true
+    }
+}
+
+/// Behaviour when building a channel on a topic the client already holds a channel for
+#[derive(PartialEq, Debug, Default, Clone, Copy)]
+pub enum DuplicateTopicPolicy {
+    /// Build another channel on the same topic. Every channel recieves every message.
+    #[default]
+    Allow,
+    /// Return the existing [ChannelManager] instead of building a new channel
+    Reuse,
+    /// Fail the build with [crate::realtime_channel::ChannelBuildError::DuplicateTopic]
+    Reject,
+}
+
+/// Error returned by [ClientManager::connect()]
+#[derive(Debug, PartialEq, Clone)]
 pub enum ConnectError {
     BadUri,
     BadHost,
@@ -50,17 +210,38 @@ pub enum ConnectError {
     HandshakeError,
     MaxRetries,
     WrongProtocol,
+    /// The client's configuration can't form a request, e.g. after an unusable access token
+    Config(ConfigError),
+}
+
+impl From<ConfigError> for ConnectError {
+    fn from(value: ConfigError) -> Self {
+        ConnectError::Config(value)
+    }
+}
+
+/// Invalid client configuration, returned by [RealtimeClientBuilder::connect()]
+#[derive(Debug, PartialEq, Clone)]
+pub enum ConfigError {
+    /// The anon key can't be sent as a header value or URL parameter
+    AnonKey,
+    /// The access token can't be sent as a header value, e.g. it contains a newline
+    AccessToken,
+    /// The named header has a value that isn't visible ASCII
+    Header(String),
+    /// The endpoint, with any URL params, doesn't form a valid websocket URL
+    Endpoint(String),
 }
 
 pub(crate) enum ClientManagerMessage {
     Connect {
-        res: Responder<()>,
+        res: Responder<Result<(), ConnectError>>,
     },
     Disconnect {
         res: Responder<RealtimeClientBuilder>,
     },
     GetWsTx {
-        res: Responder<UnboundedSender<RealtimeMessage>>,
+        res: Responder<Option<WsSender>>,
     },
     GetAccessToken {
         res: Responder<String>,
@@ -71,13 +252,29 @@ pub(crate) enum ClientManagerMessage {
     GetState {
         res: Responder<ClientState>,
     },
+    GetError {
+        res: Responder<Option<ConnectError>>,
+    },
     SetAccessToken {
-        res: Responder<()>,
+        res: Responder<Result<(), ConfigError>>,
         access_token: String,
     },
-    AddChannel {
+    BuildChannel {
+        builder: Box<RealtimeChannelBuilder>,
+        res: Responder<Result<ChannelManager, ChannelBuildError>>,
+    },
+    GetChannels {
+        res: Responder<Vec<ChannelManager>>,
+    },
+    RemoveChannel {
+        manager: ChannelManager,
+        res: Responder<()>,
+    },
+    RemoveAllChannels {
+        res: Responder<()>,
+    },
+    ForgetChannel {
         manager: ChannelManager,
-        res: Responder<ChannelManager>,
     },
 }
 
@@ -88,6 +285,11 @@ pub(crate) enum ClientManagerMessage {
 #[derive(Clone, Debug)]
 pub struct ClientManager {
     tx: UnboundedSender<ClientManagerMessage>,
+    router: ChannelRouter,
+    duplicate_topic_policy: DuplicateTopicPolicy,
+    access_token_provider: Option<TokenProvider>,
+    on_auth_error: Option<AuthErrorHook>,
+    metrics: Arc<ClientMetrics>,
     rt: Arc<Runtime>,
 }
 
@@ -96,11 +298,14 @@ impl ClientManager {
         self.tx.send(message)
     }
     /// Connect to the websocket server
+    ///
+    /// Resolves to the [ConnectError] that stopped the connection, e.g. [ConnectError::Config]
+    /// when the [AccessTokenProvider] hands out a token that can't be sent
     // TODO example code
-    pub async fn connect(&self) {
+    pub async fn connect(&self) -> Result<Result<(), ConnectError>, oneshot::error::RecvError> {
         let (tx, rx) = oneshot::channel();
         let _ = self.send(ClientManagerMessage::Connect { res: tx });
-        rx.await.unwrap()
+        rx.await
     }
     /// Disconnect the client
     /// Returns a preconfigured [RealtimeClientBuilder] for modification or reinstantiation
@@ -115,6 +320,15 @@ impl ClientManager {
         let _ = self.send(ClientManagerMessage::GetState { res: tx });
         rx.await
     }
+    /// Returns the reason the last connection attempt failed, if it did
+    ///
+    /// The first attempt runs in the background after [RealtimeClientBuilder::connect()], so
+    /// this is where its failure is reported
+    pub async fn get_error(&self) -> Result<Option<ConnectError>, oneshot::error::RecvError> {
+        let (tx, rx) = oneshot::channel();
+        let _ = self.send(ClientManagerMessage::GetError { res: tx });
+        rx.await
+    }
     /// Returns an Arc referencing the client's internal tokio runtime
     pub fn get_rt(&self) -> Arc<Runtime> {
         self.rt.clone()
@@ -136,10 +350,19 @@ impl ClientManager {
     /// Modify the client's access token
     /// This change cascades through all connected channels and sends the appropriate messages to
     /// the server
+    ///
+    /// Fails with [ConfigError::AccessToken], keeping the current token, if the token can't be
+    /// sent.
+    ///
+    /// Returns once the token has been pushed to every channel. Realtime only answers a token it
+    /// rejects, and only later, so rejections aren't returned here: the
+    /// [RealtimeClientBuilder::on_auth_error()] hook is the only place channels rejecting the
+    /// token are reported. Channels errored by an expired or rejected token rejoin with the new
+    /// one.
     pub async fn set_access_token(
         &self,
         access_token: String,
-    ) -> Result<(), oneshot::error::RecvError> {
+    ) -> Result<Result<(), ConfigError>, oneshot::error::RecvError> {
         let (tx, rx) = oneshot::channel();
         let _ = self.send(ClientManagerMessage::SetAccessToken {
             res: tx,
@@ -154,20 +377,93 @@ impl ClientManager {
     pub fn channel(&self, topic: impl Into<String>) -> RealtimeChannelBuilder {
         RealtimeChannelBuilder::new(topic)
     }
-    pub(crate) async fn add_channel(
+    /// Returns every channel held by this client that hasn't been left or closed
+    pub async fn channels(&self) -> Result<Vec<ChannelManager>, oneshot::error::RecvError> {
+        let (tx, rx) = oneshot::channel();
+        let _ = self.send(ClientManagerMessage::GetChannels { res: tx });
+        rx.await
+    }
+    /// Returns the first open channel on `topic`, if any
+    ///
+    /// Accepts the topic with or without the `realtime:` prefix
+    pub async fn get_channel(
         &self,
-        channel_manager: ChannelManager,
-    ) -> Result<ChannelManager, oneshot::error::RecvError> {
+        topic: &str,
+    ) -> Result<Option<ChannelManager>, oneshot::error::RecvError> {
+        let topic = match topic.starts_with("realtime:") {
+            true => topic.to_string(),
+            false => format!("realtime:{}", topic),
+        };
+
+        for channel in self.channels().await? {
+            if channel.get_topic().await == topic {
+                return Ok(Some(channel));
+            }
+        }
+
+        Ok(None)
+    }
+    /// Unsubscribe a channel and remove it from this client
+    pub async fn remove_channel(
+        &self,
+        channel: &ChannelManager,
+    ) -> Result<(), oneshot::error::RecvError> {
         let (tx, rx) = oneshot::channel();
-        let _ = self.send(ClientManagerMessage::AddChannel {
+        let _ = self.send(ClientManagerMessage::RemoveChannel {
+            manager: channel.clone(),
             res: tx,
-            manager: channel_manager,
         });
         rx.await
     }
-    pub(crate) async fn get_ws_tx(
+    /// Drops a channel that has closed from this client's list, without unsubscribing it
+    pub(crate) fn forget_channel(&self, channel: &ChannelManager) {
+        let _ = self.send(ClientManagerMessage::ForgetChannel {
+            manager: channel.clone(),
+        });
+    }
+    /// Unsubscribe and remove every channel held by this client
+    pub async fn remove_all_channels(&self) -> Result<(), oneshot::error::RecvError> {
+        let (tx, rx) = oneshot::channel();
+        let _ = self.send(ClientManagerMessage::RemoveAllChannels { res: tx });
+        rx.await
+    }
+    /// Returns the [DuplicateTopicPolicy] this client was built with
+    pub fn duplicate_topic_policy(&self) -> DuplicateTopicPolicy {
+        self.duplicate_topic_policy
+    }
+    /// Returns a snapshot of this client's metrics, see [crate::metrics]
+    pub fn metrics(&self) -> MetricsSnapshot {
+        self.metrics.snapshot()
+    }
+    pub(crate) fn client_metrics(&self) -> Arc<ClientMetrics> {
+        self.metrics.clone()
+    }
+    pub(crate) fn router(&self) -> ChannelRouter {
+        self.router.clone()
+    }
+    pub(crate) fn access_token_provider(&self) -> Option<TokenProvider> {
+        self.access_token_provider.clone()
+    }
+    /// Calls the `on_auth_error` hook, if set
+    pub(crate) fn auth_error(&self, channel: ChannelManager, error: ChannelError) {
+        if let Some(hook) = &self.on_auth_error {
+            hook.0(channel, error);
+        }
+    }
+    /// Applies the [DuplicateTopicPolicy] and builds the channel in one step on the manager task,
+    /// so concurrent builds on a topic can't both pass the check
+    pub(crate) async fn build_channel(
         &self,
-    ) -> Result<UnboundedSender<RealtimeMessage>, oneshot::error::RecvError> {
+        builder: RealtimeChannelBuilder,
+    ) -> Result<ChannelManager, ChannelBuildError> {
+        let (tx, rx) = oneshot::channel();
+        let _ = self.send(ClientManagerMessage::BuildChannel {
+            builder: Box::new(builder),
+            res: tx,
+        });
+        rx.await?
+    }
+    pub(crate) async fn get_ws_tx(&self) -> Result<Option<WsSender>, oneshot::error::RecvError> {
         let (tx, rx) = oneshot::channel();
         let _ = self.send(ClientManagerMessage::GetWsTx { res: tx });
         rx.await
@@ -186,23 +482,28 @@ impl ClientManagerSync {
     pub fn channel(&self, topic: impl Into<String>) -> RealtimeChannelBuilder {
         self.inner.channel(topic)
     }
-    /// Connect to the websocket server
+    /// Connect to the websocket server, see [ClientManager::connect()]
     // TODO example code
-    pub fn connect(&self) {
-        self.inner.rt.block_on(self.inner.connect());
+    pub fn connect(&self) -> Result<Result<(), ConnectError>, oneshot::error::RecvError> {
+        self.inner.rt.block_on(self.inner.connect())
     }
     /// Disconnect the client
     /// Returns a preconfigured [RealtimeClientBuilder] for modification or reinstantiation
     pub fn disconnect(&self) -> Result<RealtimeClientBuilder, oneshot::error::RecvError> {
         self.inner.rt.block_on(self.inner.disconnect())
     }
-    pub fn get_ws_tx(&self) -> Result<UnboundedSender<RealtimeMessage>, oneshot::error::RecvError> {
+    /// Returns the sender for the client's outgoing messages, `None` until it has connected
+    pub fn get_ws_tx(&self) -> Result<Option<WsSender>, oneshot::error::RecvError> {
         self.inner.rt.block_on(self.inner.get_ws_tx())
     }
     /// Returns the current [ClientState]
     pub fn get_state(&self) -> Result<ClientState, oneshot::error::RecvError> {
         self.inner.rt.block_on(self.inner.get_state())
     }
+    /// Returns the reason the last connection attempt failed, see [ClientManager::get_error()]
+    pub fn get_error(&self) -> Result<Option<ConnectError>, oneshot::error::RecvError> {
+        self.inner.rt.block_on(self.inner.get_error())
+    }
     /// Returns the current access token used by this client
     pub fn get_access_token(&self) -> Result<String, oneshot::error::RecvError> {
         self.inner.rt.block_on(self.inner.get_access_token())
@@ -214,22 +515,55 @@ impl ClientManagerSync {
     /// Modify the client's access token
     /// This change cascades through all connected channels and sends the appropriate messages to
     /// the server
-    pub fn set_access_token(&self, access_token: String) -> Result<(), oneshot::error::RecvError> {
+    ///
+    /// Channels that reject the new token are only reported to the
+    /// [RealtimeClientBuilder::on_auth_error()] hook, see [ClientManager::set_access_token()]
+    pub fn set_access_token(
+        &self,
+        access_token: String,
+    ) -> Result<Result<(), ConfigError>, oneshot::error::RecvError> {
         self.inner
             .rt
             .block_on(self.inner.set_access_token(access_token))
     }
-    /// Unwrap the inner [ClientManager]. Consumes self.
-    pub fn to_async(self) -> ClientManager {
-        self.inner
+    /// Returns every channel held by this client that hasn't been left or closed
+    pub fn channels(&self) -> Result<Vec<ChannelManagerSync>, oneshot::error::RecvError> {
+        let channels = self.inner.rt.block_on(self.inner.channels())?;
+        Ok(channels.into_iter().map(|c| c.to_sync()).collect())
+    }
+    /// Returns the first open channel on `topic`, if any
+    ///
+    /// Accepts the topic with or without the `realtime:` prefix
+    pub fn get_channel(
+        &self,
+        topic: &str,
+    ) -> Result<Option<ChannelManagerSync>, oneshot::error::RecvError> {
+        let channel = self.inner.rt.block_on(self.inner.get_channel(topic))?;
+        Ok(channel.map(|c| c.to_sync()))
     }
-    pub(crate) fn add_channel(
+    /// Unsubscribe a channel and remove it from this client
+    pub fn remove_channel(
         &self,
-        channel_manager: ChannelManager,
-    ) -> Result<ChannelManager, oneshot::error::RecvError> {
+        channel: &ChannelManagerSync,
+    ) -> Result<(), oneshot::error::RecvError> {
+        let channel = channel.clone().to_async();
+        self.inner.rt.block_on(self.inner.remove_channel(&channel))
+    }
+    /// Unsubscribe and remove every channel held by this client
+    pub fn remove_all_channels(&self) -> Result<(), oneshot::error::RecvError> {
+        self.inner.rt.block_on(self.inner.remove_all_channels())
+    }
+    /// Returns the [DuplicateTopicPolicy] this client was built with
+    pub fn duplicate_topic_policy(&self) -> DuplicateTopicPolicy {
+        self.inner.duplicate_topic_policy
+    }
+    /// Returns a snapshot of this client's metrics, see [crate::metrics]
+    pub fn metrics(&self) -> MetricsSnapshot {
+        self.inner.metrics()
+    }
+    /// Unwrap the inner [ClientManager]. Consumes self.
+    pub fn to_async(self) -> ClientManager {
         self.inner
-            .rt
-            .block_on(self.inner.add_channel(channel_manager))
     }
 }
 
@@ -238,7 +572,9 @@ struct RealtimeClient {
     pub(crate) access_token: Arc<Mutex<String>>,
     anon_key: String,
     state: Arc<Mutex<ClientState>>,
-    ws_tx: Option<mpsc::UnboundedSender<RealtimeMessage>>,
+    /// Why the last connection attempt failed, if it did
+    error: Option<ConnectError>,
+    ws_tx: Option<WsSender>,
     channels: Arc<Mutex<Vec<ChannelManager>>>,
     // threads
     join_handles: Vec<JoinHandle<()>>,
@@ -246,10 +582,14 @@ struct RealtimeClient {
     headers: HeaderMap,
     params: Option<HashMap<String, String>>,
     heartbeat_interval: Duration,
-    encode: Option<Box<Interceptor>>,
-    decode: Option<Box<Interceptor>>,
+    middleware: MiddlewareChain,
     reconnect_interval: ReconnectFn,
     reconnect_max_attempts: usize,
+    duplicate_topic_policy: DuplicateTopicPolicy,
+    token_expiry_margin: Duration,
+    on_token_expiring: Option<TokenExpiringHook>,
+    token_expiry_task: Option<JoinHandle<()>>,
+    transport: TransportHandle,
     endpoint: String,
     manager_channel: (
         UnboundedSender<ClientManagerMessage>,
@@ -264,112 +604,179 @@ impl RealtimeClient {
         while let Some(control_message) = self.manager_channel.1.recv().await {
             match control_message {
                 ClientManagerMessage::Connect { res } => {
-                    let _ = self.connect().await;
-                    let _ = res.send(());
+                    let _ = res.send(self.connect().await);
                 }
                 ClientManagerMessage::GetWsTx { res } => {
-                    let _ = res.send(self.ws_tx.clone().unwrap());
+                    let _ = res.send(self.ws_tx.clone());
                 }
                 ClientManagerMessage::GetAccessToken { res } => {
                     let token = self.access_token.lock().await;
                     let _ = res.send(token.clone());
                 }
                 ClientManagerMessage::SetAccessToken { access_token, res } => {
-                    {
-                        let mut token = self.access_token.lock().await;
-                        *token = access_token;
-                    }
-                    let channels = self.channels.lock().await;
-                    for c in channels.iter() {
-                        c.reauth().await.unwrap();
-                    }
-                    let _ = res.send(());
+                    let _ = res.send(self.set_access_token(access_token).await);
                 }
-                ClientManagerMessage::AddChannel { manager, res } => {
-                    let added = self.add_channel(manager).await;
-
-                    let _ = res.send(added);
+                ClientManagerMessage::BuildChannel { builder, res } => {
+                    let _ = res.send(self.build_channel(*builder).await);
                 }
                 ClientManagerMessage::GetState { res } => {
                     let s = self.state.lock().await;
                     res.send(*s).unwrap();
                 }
+                ClientManagerMessage::GetError { res } => {
+                    let _ = res.send(self.error.clone());
+                }
                 ClientManagerMessage::GetAccessTokenArc { res } => {
                     res.send(self.access_token.clone()).unwrap();
                 }
                 ClientManagerMessage::Disconnect { res } => {
                     res.send(self.disconnect().await).unwrap();
                 }
+                ClientManagerMessage::GetChannels { res } => {
+                    let _ = res.send(self.channels().await);
+                }
+                ClientManagerMessage::RemoveChannel { manager, res } => {
+                    self.remove_channel(&manager).await;
+                    let _ = res.send(());
+                }
+                ClientManagerMessage::RemoveAllChannels { res } => {
+                    self.clear_channels().await;
+                    let _ = res.send(());
+                }
+                ClientManagerMessage::ForgetChannel { manager } => {
+                    self.channels.lock().await.retain(|c| *c != manager);
+                }
             }
         }
     }
 
-    /// Attempt to create a websocket connection with the server
-    async fn connect(&mut self) -> Result<&mut RealtimeClient, ConnectError> {
-        let _ = self.connect_ws().await;
+    /// Store a new access token and reauthorize every channel with it. A token that can't be
+    /// sent isn't stored.
+    async fn set_access_token(&mut self, access_token: String) -> Result<(), ConfigError> {
+        bearer_header(&access_token)?;
+
+        {
+            let mut token = self.access_token.lock().await;
+            *token = access_token;
+        }
+
+        for c in self.channels.lock().await.iter() {
+            let _ = c.reauth().await;
+        }
+
+        self.schedule_token_expiry().await;
 
-        Ok(self)
+        Ok(())
     }
 
-    async fn build_request(&self) -> Result<Request<()>, ConnectError> {
-        let token = self.access_token.lock().await;
-        let uri: Uri = match format!(
-            "{}/websocket?apikey={}&vsn=1.0.0",
-            self.endpoint, self.anon_key
-        )
-        .parse()
-        {
-            Ok(uri) => uri,
-            Err(_e) => return Err(ConnectError::BadUri),
+    /// Schedule a refresh `token_expiry_margin` ahead of the current token's `exp` claim. If no
+    /// new token has been set once the claim passes, joined channels are errored with
+    /// [ChannelError::TokenExpired](crate::realtime_channel::ChannelError::TokenExpired).
+    async fn schedule_token_expiry(&mut self) {
+        if let Some(task) = self.token_expiry_task.take() {
+            task.abort();
+        }
+
+        let access_token = self.access_token.lock().await.clone();
+
+        let Some(expires_at) = token_expiry(&access_token) else {
+            return;
+        };
+
+        let margin = self.token_expiry_margin;
+        let hook = self.on_token_expiring.clone();
+        let manager = self.manager.clone();
+        let token = self.access_token.clone();
+        let channels = self.channels.clone();
+
+        let until_expiry = move || {
+            expires_at
+                .duration_since(SystemTime::now())
+                .unwrap_or_default()
         };
 
-        let ws_scheme = match uri.scheme_str() {
-            Some(scheme) => {
-                if scheme == "http" {
-                    "ws"
-                } else {
-                    "wss"
+        self.token_expiry_task = Some(self.rt.spawn(async move {
+            sleep(until_expiry().saturating_sub(margin)).await;
+
+            debug!("Access token expiring");
+
+            if let Some(provider) = manager.access_token_provider() {
+                if let Some(fresh) = provider.fetch().await {
+                    if fresh != access_token {
+                        // Setting the token reschedules, aborting this task
+                        match manager.set_access_token(fresh).await {
+                            Ok(Ok(())) => return,
+                            result => debug!("Provided access token not set: {:?}", result),
+                        }
+                    }
                 }
             }
-            None => "wss",
-        };
 
-        let mut add_params = String::new();
-        if let Some(params) = &self.params {
-            for (field, value) in params {
-                add_params = format!("{add_params}&{field}={value}");
+            if let Some(hook) = hook {
+                hook.0(manager.clone());
             }
+
+            sleep(until_expiry()).await;
+
+            if *token.lock().await != access_token {
+                return;
+            }
+
+            debug!("Access token expired");
+
+            for c in channels.lock().await.iter() {
+                c.token_expired();
+            }
+        }));
+    }
+
+    /// Ask the [AccessTokenProvider], if any, for a token and apply it when it has changed
+    async fn refresh_access_token(&mut self) -> Result<(), ConfigError> {
+        let Some(provider) = self.manager.access_token_provider() else {
+            return Ok(());
+        };
+
+        let Some(access_token) = provider.fetch().await else {
+            return Ok(());
+        };
+
+        if *self.access_token.lock().await != access_token {
+            self.set_access_token(access_token).await?;
         }
 
-        let mut p_q = uri.path_and_query().unwrap().to_string();
+        Ok(())
+    }
+
+    /// Attempt to create a websocket connection with the server, keeping the error if it fails
+    async fn connect(&mut self) -> Result<(), ConnectError> {
+        let result = self.connect_ws().await;
 
-        if !add_params.is_empty() {
-            p_q = format!("{p_q}{add_params}");
+        if let Err(e) = &result {
+            debug!("Connect failed: {:?}", e);
         }
 
-        let uri = Uri::builder()
-            .scheme(ws_scheme)
-            .authority(uri.authority().unwrap().clone())
-            .path_and_query(p_q)
-            .build()
-            .unwrap();
+        self.error = result.clone().err();
+
+        result
+    }
 
-        let Ok(mut request) = uri.clone().into_client_request() else {
+    async fn build_request(&self) -> Result<Request<()>, ConnectError> {
+        let token = self.access_token.lock().await;
+
+        let uri = websocket_uri(&self.endpoint, &self.anon_key, &self.params)?;
+
+        let Ok(mut request) = uri.into_client_request() else {
             return Err(ConnectError::BadUri);
         };
 
         let headers = request.headers_mut();
 
-        let apikey: HeaderValue = self.anon_key.parse().expect("malformed access token?");
-        headers.insert("Authorization", apikey);
-
-        let auth: HeaderValue = format!("Bearer {}", *token)
-            .parse()
-            .expect("malformed access token?");
-        headers.insert("Authorization", auth);
-
-        let xci: HeaderValue = "realtime-rs/0.1.0".to_string().parse().unwrap();
-        headers.insert("X-Client-Info", xci);
+        headers.insert("Authorization", anon_key_header(&self.anon_key)?);
+        headers.insert("Authorization", bearer_header(&token)?);
+        headers.insert(
+            "X-Client-Info",
+            HeaderValue::from_static("realtime-rs/0.1.0"),
+        );
 
         headers.extend(self.headers.clone());
 
@@ -386,9 +793,9 @@ impl RealtimeClient {
     }
 
     async fn clear_channels(&mut self) {
-        let c = self.channels.lock().await;
-        for c in c.iter() {
-            c.unsubscribe().await.unwrap().unwrap();
+        let mut channels = self.channels.lock().await;
+        for c in channels.drain(..) {
+            let _ = c.unsubscribe().await;
         }
     }
 
@@ -396,6 +803,10 @@ impl RealtimeClient {
         self.clear_channels().await;
         self.clear_tasks();
 
+        if let Some(task) = self.token_expiry_task.take() {
+            task.abort();
+        }
+
         let mut state = self.state.lock().await;
         *state = ClientState::Closed;
         debug!("Disconnected!");
@@ -406,13 +817,20 @@ impl RealtimeClient {
             anon_key: self.anon_key.clone(),
             heartbeat_interval: self.heartbeat_interval,
             params: self.params.clone(),
-            encode: self.encode.clone(),
-            decode: self.decode.clone(),
+            middleware: self.middleware.clone(),
             headers: self.headers.clone(),
             endpoint: self.endpoint.clone(),
             access_token: access_token.clone(),
             reconnect_interval: self.reconnect_interval.clone(),
             reconnect_max_attempts: self.reconnect_max_attempts,
+            duplicate_topic_policy: self.duplicate_topic_policy,
+            access_token_provider: self.manager.access_token_provider(),
+            on_auth_error: self.manager.on_auth_error.clone(),
+            token_expiry_margin: self.token_expiry_margin,
+            on_token_expiring: self.on_token_expiring.clone(),
+            // Already wraps any recorder, so a reconnected client keeps writing the same session
+            transport: self.transport.clone(),
+            recorder: None,
         }
     }
 
@@ -421,13 +839,18 @@ impl RealtimeClient {
 
         self.clear_tasks();
 
+        self.refresh_access_token().await?;
+        self.schedule_token_expiry().await;
+
         let request = self.build_request().await?;
 
         let mut reconnect_attempts = 0;
         loop {
             let (ws_tx_tx, ws_tx_rx) = mpsc::unbounded_channel();
 
-            let conn = connect_async(request.clone()).await;
+            self.manager.metrics.handshake();
+
+            let conn = self.transport.0.connect(request.clone()).await;
 
             if let Err(e) = conn {
                 debug!("Connection failed: {}", e);
@@ -444,97 +867,116 @@ impl RealtimeClient {
                 continue;
             }
 
-            let Ok((ws_stream, _res)) = conn else {
+            let Ok((mut write, mut read)) = conn else {
                 continue;
             };
 
             debug!("WebSocket handshake has been successfully completed");
 
-            let (write, mut read) = ws_stream.split();
-
-            let encode = self.encode.clone();
+            let middleware = self.middleware.clone();
+            let metrics = self.manager.metrics.clone();
 
             let send_task = self.rt.spawn(async move {
-                let sender = UnboundedReceiverStream::new(ws_tx_rx)
-                    .map(|mut x: RealtimeMessage| {
-                        if let Some(encode) = encode.clone() {
-                            x = encode(x);
-                        }
-                        // TODO throttling. READING: drop or queue throttled messages? check what
-                        // official clients do.
-                        debug!("[SEND] {:?}", x.clone());
-
-                        Ok(x.into())
-                    })
-                    .forward(write);
-                pin_mut!(sender);
-                let _ = sender.await;
+                let mut outgoing = UnboundedReceiverStream::new(ws_tx_rx);
+
+                while let Some(x) = outgoing.next().await {
+                    metrics.dequeued();
+
+                    let Some(x) = middleware.outbound(x).await else {
+                        metrics.dropped();
+                        continue;
+                    };
+
+                    // TODO throttling. READING: drop or queue throttled messages? check what
+                    // official clients do.
+                    debug!("[SEND] {:?}", x.clone());
+
+                    let event = x.event.clone();
+                    let topic = x.topic.clone();
+                    let message_ref = x.message_ref.clone();
+                    let frame: Message = x.into();
+
+                    metrics.frame_sent(&event, &topic, message_ref.as_deref(), frame.len());
+
+                    if write.send(frame).await.is_err() {
+                        break;
+                    }
+                }
             });
 
-            let channel_list = self.channels.clone();
+            let router = self.manager.router();
             let recv_state = self.state.clone();
             let manager = self.manager.clone();
-            let decode = self.decode.clone();
+            let middleware = self.middleware.clone();
+            let metrics = self.manager.metrics.clone();
 
             let recieve_task = self.rt.spawn(async move {
-                loop {
-                    while let Some(msg) = read.next().await {
-                        if let Err(_err) = msg {
-                            debug!("Disconnected!");
-                            let mut state = recv_state.lock().await;
-                            *state = ClientState::Reconnect;
-                            continue;
+                while let Some(msg) = read.next().await {
+                    let msg = match msg {
+                        Ok(msg) => msg,
+                        Err(e) => {
+                            debug!("Connection error: {}", e);
+                            break;
                         }
+                    };
 
-                        let Ok(mut msg) = serde_json::from_str::<RealtimeMessage>(
-                            msg.as_ref().unwrap().to_text().unwrap(),
-                        ) else {
-                            continue;
-                        };
+                    let Ok(text) = msg.to_text() else {
+                        continue;
+                    };
 
-                        debug!("[RECV] {:?}", msg.clone());
+                    let Ok(msg) = serde_json::from_str::<RealtimeMessage>(text) else {
+                        debug!("Undecodable frame: {}", text);
+                        metrics.undecodable(text.len());
+                        continue;
+                    };
 
-                        if let Some(decode) = decode.clone() {
-                            msg = decode(msg);
-                        }
+                    metrics.frame_received(&msg.event, msg.message_ref.as_deref(), text.len());
 
-                        let mut list = channel_list.lock().await;
-
-                        *list = tokio_stream::iter(list.clone())
-                            .filter_map(|c| async {
-                                if c.get_state().await.unwrap() != ChannelState::Closed {
-                                    Some(c)
-                                } else {
-                                    None
-                                }
-                            })
-                            .collect()
-                            .await;
-
-                        for channel in &*list {
-                            if *channel.get_topic().await != msg.topic {
-                                continue;
-                            }
-                            let _ = channel.get_tx().await.send(msg.clone());
-                        }
+                    debug!("[RECV] {:?}", msg.clone());
+
+                    let Some(msg) = middleware.inbound(msg).await else {
+                        metrics.dropped();
+                        continue;
+                    };
+
+                    // Heartbeat replies, already timed above
+                    if msg.topic == "phoenix" {
+                        continue;
                     }
 
-                    let mut state = recv_state.lock().await;
-                    if *state == ClientState::Reconnect {
-                        debug!("Reconnecting...");
-                        *state = ClientState::Reconnecting;
-                        drop(state);
-                        manager.connect().await;
+                    if !router.route(msg).await {
+                        metrics.dropped();
                     }
                 }
+
+                debug!("Disconnected!");
+
+                // The stream is gone either way, reconnect unless the client is shutting down
+                let mut state = recv_state.lock().await;
+                if *state == ClientState::Closing || *state == ClientState::Closed {
+                    return;
+                }
+
+                debug!("Reconnecting...");
+                *state = ClientState::Reconnecting;
+                drop(state);
+                let _ = manager.connect().await;
             });
 
+            let ws_tx_tx = WsSender {
+                tx: ws_tx_tx,
+                metrics: self.manager.metrics.clone(),
+            };
+
             let hb_tx = ws_tx_tx.clone();
             let hb_ivl = self.heartbeat_interval;
             let heartbeat_task = self.rt.spawn(async move {
-                loop {
+                for count in 0u64.. {
                     sleep(hb_ivl).await;
-                    let _ = hb_tx.send(RealtimeMessage::heartbeat());
+
+                    let heartbeat = RealtimeMessage::heartbeat(format!("heartbeat+{}", count));
+
+                    let _ = hb_tx.send(heartbeat);
                 }
             });
 
@@ -547,14 +989,14 @@ impl RealtimeClient {
             self.join_handles.push(recieve_task);
             self.join_handles.push(heartbeat_task);
 
-            self.ws_tx = Some(ws_tx_tx);
+            self.ws_tx = Some(ws_tx_tx.clone());
 
             let mut channels = self.channels.lock().await;
 
             for manager in channels.iter_mut() {
                 let (cc_tx, cc_rx) = oneshot::channel();
                 let _ = manager.send(ChannelManagerMessage::ClientTx {
-                    new_tx: self.ws_tx.clone().unwrap(),
+                    new_tx: ws_tx_tx.clone(),
                     res: cc_tx,
                 });
 
@@ -569,14 +1011,70 @@ impl RealtimeClient {
         Ok(())
     }
 
-    async fn add_channel(&mut self, channel: ChannelManager) -> ChannelManager {
-        let list = self.channels.clone();
+    /// Prunes channels whose task has gone and returns the rest. Channels that close remove
+    /// themselves, see [ClientManager::forget_channel()].
+    async fn channels(&mut self) -> Vec<ChannelManager> {
+        let mut list = self.channels.lock().await;
+
+        *list = tokio_stream::iter(list.clone())
+            .filter_map(|c| async {
+                match c.get_state().await {
+                    Ok(_) => Some(c),
+                    Err(_) => None,
+                }
+            })
+            .collect()
+            .await;
+
+        list.clone()
+    }
 
-        let mut list = list.lock().await;
+    async fn remove_channel(&mut self, channel: &ChannelManager) {
+        let mut list = self.channels.lock().await;
 
-        list.push(channel.clone());
+        let Some(index) = list.iter().position(|c| c == channel) else {
+            return;
+        };
 
-        channel
+        let _ = list.remove(index).unsubscribe().await;
+    }
+
+    async fn build_channel(
+        &mut self,
+        mut builder: RealtimeChannelBuilder,
+    ) -> Result<ChannelManager, ChannelBuildError> {
+        let existing = self
+            .channels()
+            .await
+            .into_iter()
+            .find(|c| c.topic() == builder.topic());
+
+        if let Some(existing) = existing {
+            match self.duplicate_topic_policy {
+                DuplicateTopicPolicy::Allow => {}
+                DuplicateTopicPolicy::Reuse => return Ok(existing),
+                DuplicateTopicPolicy::Reject => {
+                    return Err(ChannelBuildError::DuplicateTopic(builder.topic().into()))
+                }
+            }
+        }
+
+        let Some(ws_tx) = self.ws_tx.clone() else {
+            return Err(ChannelBuildError::NotConnected);
+        };
+
+        let access_token = self.access_token.lock().await.clone();
+
+        let channel = builder.build_common(
+            ws_tx,
+            access_token,
+            self.access_token.clone(),
+            &self.manager,
+        );
+
+        self.channels.lock().await.push(channel.clone());
+
+        Ok(channel)
     }
 }
 
@@ -605,10 +1103,16 @@ pub struct RealtimeClientBuilder {
     headers: HeaderMap,
     params: Option<HashMap<String, String>>,
     heartbeat_interval: Duration,
-    encode: Option<Box<Interceptor>>,
-    decode: Option<Box<Interceptor>>,
+    middleware: MiddlewareChain,
     reconnect_interval: ReconnectFn,
     reconnect_max_attempts: usize,
+    duplicate_topic_policy: DuplicateTopicPolicy,
+    access_token_provider: Option<TokenProvider>,
+    on_auth_error: Option<AuthErrorHook>,
+    token_expiry_margin: Duration,
+    on_token_expiring: Option<TokenExpiringHook>,
+    transport: TransportHandle,
+    recorder: Option<Recorder>,
     endpoint: String,
     access_token: String,
 }
@@ -626,10 +1130,16 @@ impl RealtimeClientBuilder {
             headers,
             params: Default::default(),
             heartbeat_interval: Duration::from_secs(29),
-            encode: Default::default(),
-            decode: Default::default(),
+            middleware: Default::default(),
             reconnect_interval: ReconnectFn(Box::new(backoff)),
             reconnect_max_attempts: usize::MAX,
+            duplicate_topic_policy: Default::default(),
+            access_token_provider: None,
+            on_auth_error: None,
+            token_expiry_margin: Duration::from_secs(30),
+            on_token_expiring: None,
+            transport: Default::default(),
+            recorder: None,
             endpoint: endpoint.into(),
             access_token: anon_key,
         }
@@ -695,18 +1205,107 @@ impl RealtimeClientBuilder {
         self
     }
 
-    pub fn set_encoder(&mut self, encode: Interceptor) -> &mut Self {
-        self.encode = Some(Box::new(encode));
+    /// Configure what happens when a channel is built on a topic this client already holds.
+    /// Defaults to [DuplicateTopicPolicy::Allow]
+    pub fn set_duplicate_topic_policy(&mut self, policy: DuplicateTopicPolicy) -> &mut Self {
+        self.duplicate_topic_policy = policy;
+        self
+    }
+
+    /// Set an [AccessTokenProvider] to be asked for a fresh token before connecting, before each
+    /// channel join and on reconnect
+    pub fn set_access_token_provider(
+        &mut self,
+        provider: impl AccessTokenProvider + 'static,
+    ) -> &mut Self {
+        self.access_token_provider = Some(TokenProvider(Arc::new(provider)));
+        self
+    }
+
+    /// Set how long before the access token's `exp` claim the client asks for a new one.
+    /// Default 30 seconds.
+    pub fn set_token_expiry_margin(&mut self, margin: Duration) -> &mut Self {
+        self.token_expiry_margin = margin;
+        self
+    }
+
+    /// Set a hook fired [Self::set_token_expiry_margin()] before the access token expires, when
+    /// no [AccessTokenProvider] supplied a new one. The hook should call
+    /// [ClientManager::set_access_token()]; otherwise joined channels error with
+    /// [ChannelError::TokenExpired](crate::realtime_channel::ChannelError::TokenExpired) once the
+    /// token expires.
+    pub fn on_token_expiring(
+        &mut self,
+        hook: impl Fn(ClientManager) + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.on_token_expiring = Some(TokenExpiringHook(Arc::new(hook)));
+        self
+    }
+
+    /// Set a hook fired when the server refuses a channel's authorization: a denied join, a
+    /// rejected access token, or a token that expired. Called from the channel's task with the
+    /// channel and the [ChannelError].
+    pub fn on_auth_error(
+        &mut self,
+        hook: impl Fn(ChannelManager, ChannelError) + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.on_auth_error = Some(AuthErrorHook(Arc::new(hook)));
+        self
+    }
+
+    /// Set the [Transport] used to open websocket connections.
+    /// Defaults to [crate::transport::TungsteniteTransport].
+    pub fn set_transport(&mut self, transport: impl Transport + 'static) -> &mut Self {
+        self.transport = TransportHandle(Arc::new(transport));
+        self
+    }
+
+    /// Record every frame sent and received to a JSONL session file, replayable with
+    /// [crate::recording::ReplayTransport]. Works with any [Self::set_transport()].
+    pub fn set_recorder(&mut self, recorder: Recorder) -> &mut Self {
+        self.recorder = Some(recorder);
         self
     }
 
-    pub fn set_decoder(&mut self, decode: Interceptor) -> &mut Self {
-        self.decode = Some(Box::new(decode));
+    /// Add a [Middleware] seeing every message sent and received. See [crate::middleware] for
+    /// the order middleware runs in.
+    pub fn add_middleware(&mut self, middleware: impl Middleware + 'static) -> &mut Self {
+        self.middleware.push(MiddlewareScope::default(), middleware);
         self
     }
 
+    /// Add a [Middleware] seeing only the messages `scope` matches
+    pub fn add_scoped_middleware(
+        &mut self,
+        scope: MiddlewareScope,
+        middleware: impl Middleware + 'static,
+    ) -> &mut Self {
+        self.middleware.push(scope, middleware);
+        self
+    }
+
+    /// Check every option can be sent to the server
+    fn validate(&self) -> Result<(), ConfigError> {
+        anon_key_header(&self.anon_key)?;
+        bearer_header(&self.access_token)?;
+
+        for (name, value) in &self.headers {
+            if value.to_str().is_err() {
+                return Err(ConfigError::Header(name.to_string()));
+            }
+        }
+
+        websocket_uri(&self.endpoint, &self.anon_key, &self.params)?;
+
+        Ok(())
+    }
+
     /// Consume the [Self] and return a configured [ClientManager]
-    pub fn connect(&mut self) -> ClientManager {
+    ///
+    /// Fails with a [ConfigError] naming the first option that can't be sent to the server
+    pub fn connect(&mut self) -> Result<ClientManager, ConfigError> {
+        self.validate()?;
+
         let (mgr_tx, mgr_rx) = mpsc::unbounded_channel::<ClientManagerMessage>();
         let tx = mgr_tx.clone();
 
@@ -719,20 +1318,41 @@ impl RealtimeClientBuilder {
                 .unwrap(),
         );
 
-        let manager = ClientManager { tx, rt: rt.clone() };
+        let transport = match &self.recorder {
+            Some(recorder) => TransportHandle(Arc::new(RecordingTransport::new(
+                self.transport.clone(),
+                recorder.clone(),
+            ))),
+            None => self.transport.clone(),
+        };
+
+        let manager = ClientManager {
+            tx,
+            router: Default::default(),
+            duplicate_topic_policy: self.duplicate_topic_policy,
+            access_token_provider: self.access_token_provider.clone(),
+            on_auth_error: self.on_auth_error.clone(),
+            metrics: Default::default(),
+            rt: rt.clone(),
+        };
 
         let mut client = RealtimeClient {
             anon_key: self.anon_key.clone(),
             headers: self.headers.clone(),
             params: self.params.clone(),
             heartbeat_interval: self.heartbeat_interval,
-            encode: self.encode.clone(),
-            decode: self.decode.clone(),
+            middleware: self.middleware.clone(),
             reconnect_interval: self.reconnect_interval.clone(),
             reconnect_max_attempts: self.reconnect_max_attempts,
+            duplicate_topic_policy: self.duplicate_topic_policy,
+            token_expiry_margin: self.token_expiry_margin,
+            on_token_expiring: self.on_token_expiring.clone(),
+            token_expiry_task: None,
+            transport,
             endpoint: self.endpoint.clone(),
             access_token: Arc::new(Mutex::new(self.access_token.clone())),
             state: Arc::new(Mutex::new(ClientState::Closed)),
+            error: None,
             ws_tx: None,
             channels: Arc::new(Mutex::new(Vec::new())),
             join_handles: Vec::new(),
@@ -742,16 +1362,138 @@ impl RealtimeClientBuilder {
         };
 
         let _handle = rt.spawn(async move {
-            client.connect().await.unwrap();
+            // Kept for `get_error()`
+            let _ = client.connect().await;
             client.manager_recv().await;
         });
 
-        manager
+        Ok(manager)
     }
 }
 
+/// Builds the websocket URL for `endpoint`, upgrading the scheme and appending params
+fn websocket_uri(
+    endpoint: &str,
+    anon_key: &str,
+    params: &Option<HashMap<String, String>>,
+) -> Result<Uri, ConfigError> {
+    if anon_key
+        .chars()
+        .any(|c| c.is_whitespace() || c.is_control())
+    {
+        return Err(ConfigError::AnonKey);
+    }
+
+    let uri: Uri = format!("{}/websocket?apikey={}&vsn=1.0.0", endpoint, anon_key)
+        .parse()
+        .map_err(|e| ConfigError::Endpoint(format!("{e}")))?;
+
+    let ws_scheme = match uri.scheme_str() {
+        Some("http") => "ws",
+        _ => "wss",
+    };
+
+    let Some(authority) = uri.authority().cloned() else {
+        return Err(ConfigError::Endpoint("missing host".into()));
+    };
+
+    let mut p_q = uri
+        .path_and_query()
+        .map(|p_q| p_q.to_string())
+        .unwrap_or_default();
+
+    if let Some(params) = params {
+        for (field, value) in params {
+            p_q = format!("{p_q}&{field}={value}");
+        }
+    }
+
+    Uri::builder()
+        .scheme(ws_scheme)
+        .authority(authority)
+        .path_and_query(p_q)
+        .build()
+        .map_err(|e| ConfigError::Endpoint(format!("{e}")))
+}
+
+fn anon_key_header(anon_key: &str) -> Result<HeaderValue, ConfigError> {
+    HeaderValue::from_str(anon_key).map_err(|_| ConfigError::AnonKey)
+}
+
+pub(crate) fn bearer_header(access_token: &str) -> Result<HeaderValue, ConfigError> {
+    HeaderValue::from_str(&format!("Bearer {access_token}")).map_err(|_| ConfigError::AccessToken)
+}
+
+/// Reads the `exp` claim of a JWT. The signature is not verified.
+///
+/// Fractional seconds are dropped. An `exp` too large for [SystemTime] counts as no expiry.
+fn token_expiry(token: &str) -> Option<SystemTime> {
+    let claims = token.split('.').nth(1)?;
+    let claims = URL_SAFE_NO_PAD.decode(claims.trim_end_matches('=')).ok()?;
+    let claims: serde_json::Value = serde_json::from_slice(&claims).ok()?;
+    let exp = claims.get("exp")?;
+
+    // Float casts saturate, so a huge or negative `exp` lands on u64::MAX or 0
+    let exp = exp
+        .as_u64()
+        .or_else(|| exp.as_f64().map(|exp| exp as u64))?;
+
+    SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(exp))
+}
+
 fn backoff(attempts: usize) -> Duration {
     let times: Vec<u64> = vec![0, 1, 2, 5, 10];
 
     Duration::from_secs(times[attempts.min(times.len() - 1)])
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(claims: &str) -> String {
+        format!("header.{}.signature", URL_SAFE_NO_PAD.encode(claims))
+    }
+
+    #[test]
+    fn token_expiry_reads_exp() {
+        let expiry = token_expiry(&token(r#"{"exp": 1700000000}"#));
+
+        assert_eq!(
+            expiry,
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1700000000))
+        );
+    }
+
+    #[test]
+    fn token_expiry_drops_fractional_seconds() {
+        let expiry = token_expiry(&token(r#"{"exp": 1700000000.75}"#));
+
+        assert_eq!(
+            expiry,
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1700000000))
+        );
+    }
+
+    #[test]
+    fn token_expiry_without_exp_is_none() {
+        assert_eq!(token_expiry(&token(r#"{"role": "anon"}"#)), None);
+        assert_eq!(token_expiry(&token(r#"{"exp": "soon"}"#)), None);
+    }
+
+    #[test]
+    fn token_expiry_out_of_range_is_none() {
+        assert_eq!(token_expiry(&token(r#"{"exp": 1e20}"#)), None);
+        assert_eq!(
+            token_expiry(&token(r#"{"exp": 18446744073709551615}"#)),
+            None
+        );
+    }
+
+    #[test]
+    fn token_expiry_of_malformed_token_is_none() {
+        assert_eq!(token_expiry("anon_key"), None);
+        assert_eq!(token_expiry("header.not base64!.signature"), None);
+        assert_eq!(token_expiry(&token("not json")), None);
+    }
+}
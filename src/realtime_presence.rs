@@ -1,112 +1,147 @@
-use std::collections::HashMap;
-
 use crate::{
-    message::presence::{PresenceDiff, PresenceEvent, PresenceState},
-    realtime_channel::PresenceCallback,
+    message::presence::{PhxMap, PresenceDiff, PresenceState},
+    realtime_channel::{Callback, CallbackRunner},
 };
 
-pub(crate) type PresenceCallbackMap = HashMap<PresenceEvent, Vec<PresenceCallback>>;
+/// (key, metas before the join, joined metas)
+pub(crate) type PresenceJoinArgs = (String, Option<PhxMap>, PhxMap);
+
+/// (key, remaining metas, left metas)
+pub(crate) type PresenceLeaveArgs = (String, PhxMap, PhxMap);
+
+#[derive(Default, Clone)]
+pub(crate) struct PresenceCallbacks {
+    pub sync: Vec<Callback<PresenceState>>,
+    pub join: Vec<Callback<PresenceJoinArgs>>,
+    pub leave: Vec<Callback<PresenceLeaveArgs>>,
+}
 
 #[derive(Default)]
 pub(crate) struct RealtimePresence {
     pub state: PresenceState,
-    callbacks: PresenceCallbackMap,
+    callbacks: PresenceCallbacks,
+    /// Ref of the join whose `presence_state` has been applied
+    join_ref: Option<String>,
+    /// Ref of the channel's current join
+    channel_join_ref: Option<String>,
+    /// Diffs recieved before the current join's `presence_state`
+    pending_diffs: Vec<PresenceDiff>,
 }
 
 impl RealtimePresence {
-    pub(crate) fn from_channel_builder(
-        callbacks: HashMap<PresenceEvent, Vec<PresenceCallback>>,
-    ) -> Self {
+    pub(crate) fn from_channel_builder(callbacks: PresenceCallbacks) -> Self {
         Self {
-            state: PresenceState::default(),
             callbacks,
+            ..Default::default()
         }
     }
 
-    pub(crate) fn sync(&mut self, new_state: PresenceState) {
-        let joins: PresenceState = new_state
-            .0
-            .clone()
-            .into_iter()
-            .map(|(new_id, mut new_phx_map)| {
-                new_phx_map.retain(|new_phx_ref, _new_state_data| {
-                    let mut retain = true;
-                    let _ = self.state.0.clone().into_values().map(|self_phx_map| {
-                        if self_phx_map.contains_key(new_phx_ref) {
-                            retain = false;
-                        }
-                    });
-                    retain
-                });
-
-                (new_id, new_phx_map)
-            })
-            .collect();
-
-        let leaves: PresenceState = self
-            .state
-            .0
-            .clone()
-            .into_iter()
-            .map(|(current_id, mut current_phx_map)| {
-                current_phx_map.retain(|current_phx_ref, _current_state_data| {
-                    let mut retain = false;
-                    let _ = new_state.0.clone().into_values().map(|new_phx_map| {
-                        if !new_phx_map.contains_key(current_phx_ref) {
-                            retain = true;
-                        }
-                    });
-                    retain
-                });
-
-                (current_id, current_phx_map)
-            })
-            .collect();
-
-        let prev_state = self.state.clone();
-
-        self.sync_diff(PresenceDiff { joins, leaves });
-
-        for (id, _data) in self.state.0.clone() {
-            for cb in self
-                .callbacks
-                .get_mut(&PresenceEvent::Sync)
-                .unwrap_or(&mut vec![])
-            {
-                cb.0(id.clone(), prev_state.clone(), self.state.clone());
-            }
+    /// Called when the channel sends a join. Diffs buffered for an earlier join are stale and
+    /// get discarded.
+    pub(crate) fn set_channel_join_ref(&mut self, join_ref: String) {
+        self.channel_join_ref = Some(join_ref);
+        self.pending_diffs.clear();
+    }
+
+    /// True until the `presence_state` for the channel's current join has been applied
+    fn in_pending_sync_state(&self) -> bool {
+        self.join_ref.is_none() || self.join_ref != self.channel_join_ref
+    }
+
+    pub(crate) fn callbacks(&self) -> PresenceCallbacks {
+        self.callbacks.clone()
+    }
+
+    /// Apply a `presence_state`, then replay any diffs that arrived before it in order
+    pub(crate) fn sync(&mut self, new_state: PresenceState) -> PresenceChanges {
+        self.join_ref = self.channel_join_ref.clone();
+
+        let mut joins = vec![];
+        let mut leaves = vec![];
+
+        let mut on_join = |key: &str, current: Option<&PhxMap>, joined: &PhxMap| {
+            joins.push((key.into(), current.cloned(), joined.clone()))
+        };
+        let mut on_leave = |key: &str, current: &PhxMap, left: &PhxMap| {
+            leaves.push((key.into(), current.clone(), left.clone()))
+        };
+
+        self.state
+            .sync_state(new_state, &mut on_join, &mut on_leave);
+
+        for diff in std::mem::take(&mut self.pending_diffs) {
+            self.state.sync_diff(diff, &mut on_join, &mut on_leave);
+        }
+
+        self.changes(joins, leaves)
+    }
+
+    /// Apply a `presence_diff`, or buffer it until the current join's `presence_state` arrives
+    pub(crate) fn sync_diff(&mut self, diff: PresenceDiff) -> PresenceChanges {
+        if self.in_pending_sync_state() {
+            self.pending_diffs.push(diff);
+            return PresenceChanges::default();
         }
+
+        let mut joins = vec![];
+        let mut leaves = vec![];
+
+        self.state.sync_diff(
+            diff,
+            |key, current, joined| joins.push((key.into(), current.cloned(), joined.clone())),
+            |key, current, left| leaves.push((key.into(), current.clone(), left.clone())),
+        );
+
+        self.changes(joins, leaves)
     }
 
-    pub(crate) fn sync_diff(&mut self, diff: PresenceDiff) -> &PresenceState {
-        // mutate own state with diff
-        // return new state
-        // trigger diff callbacks
-
-        for (id, _data) in diff.joins.0.clone() {
-            for cb in self
-                .callbacks
-                .get_mut(&PresenceEvent::Join)
-                .unwrap_or(&mut vec![])
-            {
-                cb.0(id.clone(), self.state.clone(), diff.clone().joins);
-            }
+    fn changes(
+        &self,
+        joins: Vec<PresenceJoinArgs>,
+        leaves: Vec<PresenceLeaveArgs>,
+    ) -> PresenceChanges {
+        PresenceChanges {
+            callbacks: self.callbacks.clone(),
+            joins,
+            leaves,
+            state: Some(self.state.clone()),
         }
+    }
+}
 
-        for (id, _data) in diff.leaves.0.clone() {
-            for cb in self
-                .callbacks
-                .get_mut(&PresenceEvent::Leave)
-                .unwrap_or(&mut vec![])
-            {
-                cb.0(id.clone(), self.state.clone(), diff.clone().leaves);
-            }
+/// Callbacks owed for an applied presence change. Notified after the presence lock is released,
+/// so callbacks can read the channel's presence state.
+#[derive(Default)]
+pub(crate) struct PresenceChanges {
+    callbacks: PresenceCallbacks,
+    joins: Vec<PresenceJoinArgs>,
+    leaves: Vec<PresenceLeaveArgs>,
+    /// Resulting state, None when nothing was applied
+    state: Option<PresenceState>,
+}
 
-            self.state.0.remove(&id);
+impl PresenceChanges {
+    /// Calls join and leave callbacks once per changed key, then sync callbacks once with the
+    /// resulting state
+    pub(crate) async fn notify(self, runner: &CallbackRunner) {
+        let Some(state) = self.state else {
+            return;
+        };
+
+        for args in self.joins {
+            for cb in &self.callbacks.join {
+                cb.call(&args, runner).await;
+            }
         }
 
-        self.state.0.extend(diff.joins.0);
+        for args in self.leaves {
+            for cb in &self.callbacks.leave {
+                cb.call(&args, runner).await;
+            }
+        }
 
-        &self.state
+        for cb in &self.callbacks.sync {
+            cb.call(&state, runner).await;
+        }
     }
 }
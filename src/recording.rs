@@ -0,0 +1,426 @@
+//! Record websocket sessions to JSONL and replay them in tests
+//!
+//! A [Recorder] set with [crate::realtime_client::RealtimeClientBuilder::set_recorder()] writes
+//! every text frame the client sends and receives as one [RecordedFrame] per line. A
+//! [ReplayTransport] reads that file back and plays the server's side of the session, so a bug
+//! report can ship a session file that reproduces callback behaviour without a server.
+//!
+//! Credentials are redacted before frames are written, see [Recorder].
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc as std_mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use futures_util::{future, SinkExt, StreamExt};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_tungstenite::tungstenite::{http::Request, Error, Message};
+
+use crate::transport::{
+    ConnectFuture, FrameSink, FrameStream, Transport, TransportError, TransportHandle,
+};
+
+/// Which way a [RecordedFrame] travelled. Shared with the rest of the transport layer.
+pub use crate::transport::Direction;
+
+/// One line of a session file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RecordedFrame {
+    /// Milliseconds since the unix epoch
+    pub timestamp: u64,
+    /// Counts websocket connections from 0, incremented on every reconnect
+    pub connection: usize,
+    pub direction: Direction,
+    /// The frame's text as it went over the wire, unless credentials were redacted from it
+    pub frame: String,
+}
+
+impl RecordedFrame {
+    fn is_heartbeat(&self) -> bool {
+        serde_json::from_str::<Value>(&self.frame)
+            .map(|frame| frame["topic"] == "phoenix")
+            .unwrap_or(false)
+    }
+}
+
+/// Values of these keys, matched case-insensitively at any depth, are replaced with
+/// [REDACTED] before a frame is recorded
+const REDACTED_KEYS: [&str; 3] = ["access_token", "apikey", "authorization"];
+
+const REDACTED: &str = "[redacted]";
+
+enum WriterMessage {
+    Line(String),
+    Flush(std_mpsc::Sender<()>),
+}
+
+/// Writes [RecordedFrame]s as JSONL. Cloning shares the writer.
+///
+/// Writing happens on a dedicated thread, so recording never blocks the client's tasks. The
+/// thread exits once every clone is dropped. Access tokens, API keys and authorization values
+/// in frames are redacted, so session files can be shared.
+#[derive(Clone)]
+pub struct Recorder(std_mpsc::Sender<WriterMessage>);
+
+impl Recorder {
+    /// Record into any writer
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        let (tx, rx) = std_mpsc::channel();
+
+        thread::Builder::new()
+            .name("realtime-recorder".into())
+            .spawn(move || write_lines(writer, rx))
+            .expect("failed to spawn recorder thread");
+
+        Self(tx)
+    }
+
+    /// Record into a new file at `path`, truncating it if it exists
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::new(File::create(path)?))
+    }
+
+    /// Blocks until every frame recorded so far has been written and flushed
+    pub fn flush(&self) {
+        let (tx, rx) = std_mpsc::channel();
+
+        if self.0.send(WriterMessage::Flush(tx)).is_ok() {
+            let _ = rx.recv();
+        }
+    }
+
+    fn record(&self, connection: usize, direction: Direction, message: &Message) {
+        let Ok(text) = message.to_text() else {
+            return;
+        };
+
+        let frame = RecordedFrame {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            connection,
+            direction,
+            frame: redact(text),
+        };
+
+        let Ok(mut line) = serde_json::to_string(&frame) else {
+            return;
+        };
+        line.push('\n');
+
+        let _ = self.0.send(WriterMessage::Line(line));
+    }
+}
+
+fn write_lines(mut writer: impl Write, rx: std_mpsc::Receiver<WriterMessage>) {
+    for message in rx {
+        match message {
+            WriterMessage::Line(line) => {
+                // Flushed per line so a crashing process still leaves a usable file
+                if let Err(e) = writer
+                    .write_all(line.as_bytes())
+                    .and_then(|_| writer.flush())
+                {
+                    debug!("Failed to record frame: {}", e);
+                }
+            }
+            WriterMessage::Flush(done) => {
+                let _ = done.send(());
+            }
+        }
+    }
+}
+
+/// `text` with the values of [REDACTED_KEYS] replaced. Frames without any are kept verbatim.
+fn redact(text: &str) -> String {
+    let Ok(mut frame) = serde_json::from_str::<Value>(text) else {
+        return text.to_owned();
+    };
+
+    match redact_value(&mut frame) {
+        true => frame.to_string(),
+        false => text.to_owned(),
+    }
+}
+
+/// Returns true if anything was redacted
+fn redact_value(value: &mut Value) -> bool {
+    match value {
+        Value::Object(map) => {
+            let mut redacted = false;
+
+            for (key, value) in map.iter_mut() {
+                if REDACTED_KEYS
+                    .iter()
+                    .any(|redacted_key| key.eq_ignore_ascii_case(redacted_key))
+                {
+                    *value = Value::String(REDACTED.into());
+                    redacted = true;
+                } else {
+                    redacted |= redact_value(value);
+                }
+            }
+
+            redacted
+        }
+        Value::Array(values) => {
+            let mut redacted = false;
+
+            for value in values {
+                redacted |= redact_value(value);
+            }
+
+            redacted
+        }
+        _ => false,
+    }
+}
+
+impl Debug for Recorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Recorder")
+    }
+}
+
+/// Wraps the client's transport, teeing every frame into a [Recorder]
+pub(crate) struct RecordingTransport {
+    inner: TransportHandle,
+    recorder: Recorder,
+    connections: Arc<AtomicUsize>,
+}
+
+impl RecordingTransport {
+    pub(crate) fn new(inner: TransportHandle, recorder: Recorder) -> Self {
+        Self {
+            inner,
+            recorder,
+            connections: Default::default(),
+        }
+    }
+}
+
+impl Transport for RecordingTransport {
+    fn connect(&self, request: Request<()>) -> ConnectFuture {
+        let connect = self.inner.0.connect(request);
+        let sent = self.recorder.clone();
+        let received = self.recorder.clone();
+        let connections = self.connections.clone();
+
+        Box::pin(async move {
+            let (write, read) = connect.await?;
+
+            // Only successful connections take a number, so failed attempts leave no gaps
+            let connection = connections.fetch_add(1, Ordering::SeqCst);
+
+            let sink: FrameSink = Box::pin(write.with(move |message: Message| {
+                sent.record(connection, Direction::Sent, &message);
+                future::ready(Ok::<_, TransportError>(message))
+            }));
+            let stream: FrameStream = Box::pin(read.inspect(move |message| {
+                if let Ok(message) = message {
+                    received.record(connection, Direction::Received, message);
+                }
+            }));
+
+            Ok((sink, stream))
+        })
+    }
+}
+
+/// Transport playing the server's side of a recorded session
+///
+/// Each connection the client makes replays the next recorded connection. Received frames are
+/// sent as soon as every frame the client sent before them has been sent again, so the replay
+/// keeps pace with the client rather than the recorded timestamps. Refs in replies are rewritten
+/// to the refs the client uses this time. Heartbeats are left out on both sides.
+///
+/// A recorded connection is closed once played, prompting the client to reconnect into the next
+/// one. The last stays open. Connecting after that fails.
+///
+/// If the client never sends a frame the recording expects, the replay waits there.
+///
+/// ```
+/// # use realtime_rs::recording::ReplayTransport;
+/// # use realtime_rs::realtime_client::RealtimeClientBuilder;
+/// let session = r#"{"timestamp":0,"connection":0,"direction":"received","frame":"{\"event\":\"broadcast\",\"topic\":\"realtime:room\",\"payload\":{\"type\":\"broadcast\",\"event\":\"ping\",\"payload\":{}},\"ref\":null}"}"#;
+///
+/// let replay = ReplayTransport::from_reader(session.as_bytes()).unwrap();
+///
+/// let client = RealtimeClientBuilder::new("http://localhost/realtime/v1", "anon_key")
+///     .set_transport(replay.clone())
+///     .connect()
+///     .unwrap()
+///     .to_sync();
+/// ```
+#[derive(Debug, Clone)]
+pub struct ReplayTransport {
+    connections: Arc<Mutex<VecDeque<Vec<RecordedFrame>>>>,
+    remaining: Arc<AtomicUsize>,
+}
+
+impl ReplayTransport {
+    /// Replay `frames`, grouped by [RecordedFrame::connection]
+    pub fn new(frames: impl IntoIterator<Item = RecordedFrame>) -> Self {
+        let mut connections: BTreeMap<usize, Vec<RecordedFrame>> = BTreeMap::new();
+
+        for frame in frames.into_iter().filter(|f| !f.is_heartbeat()) {
+            connections.entry(frame.connection).or_default().push(frame);
+        }
+
+        let remaining = connections.values().map(Vec::len).sum();
+
+        Self {
+            connections: Arc::new(Mutex::new(connections.into_values().collect())),
+            remaining: Arc::new(AtomicUsize::new(remaining)),
+        }
+    }
+
+    /// Replay a session from JSONL. Blank lines are skipped.
+    pub fn from_reader(reader: impl io::Read) -> io::Result<Self> {
+        let mut frames = vec![];
+
+        for line in BufReader::new(reader).lines() {
+            let line = line?;
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let frame = serde_json::from_str(&line)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+            frames.push(frame);
+        }
+
+        Ok(Self::new(frames))
+    }
+
+    /// Replay a session file written by a [Recorder]
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_reader(File::open(path)?)
+    }
+
+    /// True once every recorded frame has been played
+    pub fn is_finished(&self) -> bool {
+        self.remaining.load(Ordering::SeqCst) == 0
+    }
+}
+
+impl Transport for ReplayTransport {
+    fn connect(&self, _request: Request<()>) -> ConnectFuture {
+        let (script, last) = {
+            let mut connections = self.connections.lock().unwrap();
+            (connections.pop_front(), connections.is_empty())
+        };
+
+        let remaining = self.remaining.clone();
+
+        Box::pin(async move {
+            let Some(script) = script else {
+                return Err(Error::Io(io::ErrorKind::ConnectionRefused.into()).into());
+            };
+
+            let (client_tx, server_rx) = mpsc::unbounded_channel::<Message>();
+            let (server_tx, client_rx) = mpsc::unbounded_channel::<Message>();
+
+            tokio::spawn(play(script, last, server_tx, server_rx, remaining));
+
+            let sink: FrameSink = Box::pin(futures_util::sink::unfold(
+                client_tx,
+                |tx, message: Message| async move {
+                    tx.send(message).map_err(|_| Error::ConnectionClosed)?;
+                    Ok::<_, TransportError>(tx)
+                },
+            ));
+            let stream: FrameStream = Box::pin(UnboundedReceiverStream::new(client_rx).map(Ok));
+
+            Ok((sink, stream))
+        })
+    }
+}
+
+async fn play(
+    script: Vec<RecordedFrame>,
+    last: bool,
+    tx: UnboundedSender<Message>,
+    mut rx: UnboundedReceiver<Message>,
+    remaining: Arc<AtomicUsize>,
+) {
+    // Recorded ref -> ref the client used this time
+    let mut refs: HashMap<String, String> = HashMap::new();
+
+    for recorded in script {
+        let Ok(mut frame) = serde_json::from_str::<Value>(&recorded.frame) else {
+            remaining.fetch_sub(1, Ordering::SeqCst);
+            continue;
+        };
+
+        match recorded.direction {
+            Direction::Sent => {
+                let Some(live) = next_frame(&mut rx).await else {
+                    return;
+                };
+
+                if live["topic"] != frame["topic"] || live["event"] != frame["event"] {
+                    debug!(
+                        "Replay diverged: expected {} on {}, client sent {} on {}",
+                        frame["event"], frame["topic"], live["event"], live["topic"]
+                    );
+                }
+
+                if let (Some(recorded_ref), Some(live_ref)) =
+                    (frame["ref"].as_str(), live["ref"].as_str())
+                {
+                    refs.insert(recorded_ref.to_owned(), live_ref.to_owned());
+                }
+            }
+            Direction::Received => {
+                if let Some(message_ref) = frame.get_mut("ref") {
+                    if let Some(live_ref) = message_ref.as_str().and_then(|r| refs.get(r)) {
+                        *message_ref = Value::String(live_ref.clone());
+                    }
+                }
+
+                if tx.send(Message::Text(frame.to_string())).is_err() {
+                    return;
+                }
+            }
+        }
+
+        remaining.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    if last {
+        // Hold the connection open until the client leaves
+        while rx.recv().await.is_some() {}
+    }
+}
+
+/// Next frame from the client that isn't a heartbeat
+async fn next_frame(rx: &mut UnboundedReceiver<Message>) -> Option<Value> {
+    while let Some(message) = rx.recv().await {
+        let Some(frame) = message
+            .to_text()
+            .ok()
+            .and_then(|text| serde_json::from_str::<Value>(text).ok())
+        else {
+            continue;
+        };
+
+        if frame["topic"] != "phoenix" {
+            return Some(frame);
+        }
+    }
+
+    None
+}
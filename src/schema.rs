@@ -0,0 +1,157 @@
+//! JSON Schema validation of broadcast payloads
+//!
+//! Enabled with the `schema` feature. Register a schema per broadcast event with
+//! [crate::realtime_channel::RealtimeChannelBuilder::set_broadcast_schema()]. Incoming payloads
+//! for that event are validated after channel middleware has run, so after decryption and
+//! decompression, and before any `on_broadcast` callback sees them. Payloads that fail are
+//! dropped and passed to the channel's
+//! [crate::realtime_channel::RealtimeChannelBuilder::on_invalid()] callback.
+//!
+//! Outgoing payloads are only validated when
+//! [crate::realtime_channel::RealtimeChannelBuilder::set_validate_outgoing()] is set. Invalid
+//! ones are not sent, and `broadcast()` returns
+//! [crate::realtime_channel::ChannelSendError::InvalidPayload] with the validation errors.
+//! Events without a schema are never validated.
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use jsonschema::Validator;
+use serde_json::Value;
+
+use crate::message::payload::BroadcastPayload;
+use crate::realtime_channel::ChannelBuildError;
+
+/// One way a payload failed its schema
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaError {
+    /// JSON pointer to the failing value within the payload, empty for the payload itself
+    pub path: String,
+    pub message: String,
+}
+
+/// An incoming broadcast payload rejected by its event's schema, passed to the `on_invalid`
+/// callback
+#[derive(Debug, Clone)]
+pub struct InvalidBroadcast {
+    pub payload: BroadcastPayload,
+    pub errors: Vec<SchemaError>,
+}
+
+type InvalidCallback = Arc<dyn Fn(&InvalidBroadcast) + Send + Sync>;
+
+/// A channel's broadcast schemas and what to do with payloads that fail them
+#[derive(Clone, Default)]
+pub(crate) struct BroadcastSchemas {
+    schemas: HashMap<String, Value>,
+    validators: Arc<HashMap<String, Validator>>,
+    on_invalid: Option<InvalidCallback>,
+    validate_outgoing: bool,
+}
+
+impl BroadcastSchemas {
+    pub(crate) fn insert(&mut self, event: String, schema: Value) {
+        self.schemas.insert(event, schema);
+    }
+
+    pub(crate) fn set_on_invalid(
+        &mut self,
+        callback: impl Fn(&InvalidBroadcast) + Send + Sync + 'static,
+    ) {
+        self.on_invalid = Some(Arc::new(callback));
+    }
+
+    pub(crate) fn set_validate_outgoing(&mut self, validate: bool) {
+        self.validate_outgoing = validate;
+    }
+
+    /// Compile every registered schema, failing on the first that isn't valid
+    pub(crate) fn compile(&mut self) -> Result<(), ChannelBuildError> {
+        let mut validators = HashMap::new();
+
+        for (event, schema) in &self.schemas {
+            let validator = jsonschema::validator_for(schema).map_err(|e| {
+                ChannelBuildError::InvalidSchema {
+                    event: event.clone(),
+                    error: e.to_string(),
+                }
+            })?;
+
+            validators.insert(event.clone(), validator);
+        }
+
+        self.validators = Arc::new(validators);
+
+        Ok(())
+    }
+
+    /// True if incoming `payload` may be passed on. Invalid payloads go to the `on_invalid`
+    /// callback.
+    pub(crate) fn check_incoming(&self, payload: &BroadcastPayload) -> bool {
+        let errors = self.validate(payload);
+
+        if errors.is_empty() {
+            return true;
+        }
+
+        if let Some(on_invalid) = &self.on_invalid {
+            on_invalid(&InvalidBroadcast {
+                payload: payload.clone(),
+                errors,
+            });
+        }
+
+        false
+    }
+
+    /// The validation errors for outgoing `payload`, if outgoing payloads are validated
+    pub(crate) fn check_outgoing(
+        &self,
+        payload: &BroadcastPayload,
+    ) -> Result<(), Vec<SchemaError>> {
+        if !self.validate_outgoing {
+            return Ok(());
+        }
+
+        let errors = self.validate(payload);
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Every way `payload` fails its event's schema, none if the event has no schema
+    fn validate(&self, payload: &BroadcastPayload) -> Vec<SchemaError> {
+        let Some(validator) = self.validators.get(&payload.event) else {
+            return Vec::new();
+        };
+
+        let instance = Value::Object(
+            payload
+                .payload
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+        );
+
+        validator
+            .iter_errors(&instance)
+            .map(|e| SchemaError {
+                path: e.instance_path.to_string(),
+                message: e.to_string(),
+            })
+            .collect()
+    }
+}
+
+impl Debug for BroadcastSchemas {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BroadcastSchemas")
+            .field("events", &self.schemas.keys().collect::<Vec<_>>())
+            .field("validate_outgoing", &self.validate_outgoing)
+            .finish()
+    }
+}
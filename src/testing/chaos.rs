@@ -0,0 +1,322 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use log::debug;
+use serde_json::Value;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
+use tokio::time::sleep;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_tungstenite::tungstenite::{http::Request, Error, Message};
+
+use crate::transport::Direction;
+use crate::transport::{
+    ConnectFuture, FrameSink, FrameStream, Transport, TransportError, TransportHandle,
+};
+
+/// What happens to a frame picked out by [ChaosTransport::inject()]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fault {
+    /// Close the connection instead of passing the frame on
+    Drop,
+    /// Pass the frame on after a delay. Later frames overtake it.
+    Delay(Duration),
+    /// Hold the frame back until the next frame in the same direction has passed
+    Reorder,
+    /// Cut the frame's text down to at most this many bytes
+    Truncate(usize),
+}
+
+struct Injection {
+    direction: Direction,
+    event: String,
+    fault: Fault,
+}
+
+impl Injection {
+    fn matches(&self, direction: Direction, message: &Message) -> bool {
+        if self.direction != direction {
+            return false;
+        }
+
+        if self.event == "*" {
+            return true;
+        }
+
+        message
+            .to_text()
+            .ok()
+            .and_then(|text| serde_json::from_str::<Value>(text).ok())
+            .map(|frame| frame["event"] == self.event.as_str())
+            .unwrap_or(false)
+    }
+}
+
+#[derive(Default)]
+struct ChaosState {
+    refuse_handshakes: usize,
+    handshakes: usize,
+    injections: VecDeque<Injection>,
+    connections: Vec<UnboundedSender<()>>,
+}
+
+impl ChaosState {
+    /// Takes the first injection matching the frame
+    fn fault(&mut self, direction: Direction, message: &Message) -> Option<Fault> {
+        let index = self
+            .injections
+            .iter()
+            .position(|injection| injection.matches(direction, message))?;
+
+        self.injections
+            .remove(index)
+            .map(|injection| injection.fault)
+    }
+}
+
+/// Transport wrapper injecting network faults, for testing reconnects
+///
+/// Wraps another [Transport], usually [crate::transport::TungsteniteTransport] pointed at a
+/// [super::MockServer]. Faults are scripted while the client runs: handshakes can be refused,
+/// connections dropped on demand, and single frames dropped, delayed, reordered or truncated as
+/// they pass. Clones share the same script.
+///
+/// ```
+/// # use realtime_rs::testing::{ChaosTransport, Fault, MockServer};
+/// # use realtime_rs::transport::TungsteniteTransport;
+/// # use realtime_rs::transport::Direction;
+/// # use realtime_rs::realtime_client::RealtimeClientBuilder;
+/// let server = MockServer::start();
+/// let chaos = ChaosTransport::new(TungsteniteTransport);
+///
+/// // Lose the connection when the server answers the first join
+/// chaos.inject(Direction::Received, "phx_reply", Fault::Drop);
+///
+/// let client = RealtimeClientBuilder::new(server.endpoint(), "anon_key")
+///     .set_transport(chaos.clone())
+///     .connect()
+///     .unwrap()
+///     .to_sync();
+/// ```
+#[derive(Clone)]
+pub struct ChaosTransport {
+    inner: TransportHandle,
+    state: Arc<Mutex<ChaosState>>,
+}
+
+impl ChaosTransport {
+    pub fn new(inner: impl Transport + 'static) -> Self {
+        Self {
+            inner: TransportHandle(Arc::new(inner)),
+            state: Default::default(),
+        }
+    }
+
+    /// Fail the next `count` handshakes before reaching the wrapped transport
+    pub fn refuse_handshakes(&self, count: usize) {
+        self.state.lock().unwrap().refuse_handshakes = count;
+    }
+
+    /// Number of handshakes attempted so far, refused ones included
+    pub fn handshake_count(&self) -> usize {
+        self.state.lock().unwrap().handshakes
+    }
+
+    /// Close every open connection now
+    pub fn drop_connections(&self) {
+        for connection in self.state.lock().unwrap().connections.drain(..) {
+            let _ = connection.send(());
+        }
+    }
+
+    /// Apply `fault` to the next frame travelling in `direction` with the given `event`, or any
+    /// frame for `"*"`. Each injection fires once; injections matching the same frame fire in the
+    /// order they were made.
+    pub fn inject(&self, direction: Direction, event: impl Into<String>, fault: Fault) {
+        self.state.lock().unwrap().injections.push_back(Injection {
+            direction,
+            event: event.into(),
+            fault,
+        });
+    }
+
+    /// Number of injections that haven't fired yet
+    pub fn pending_faults(&self) -> usize {
+        self.state.lock().unwrap().injections.len()
+    }
+}
+
+impl std::fmt::Debug for ChaosTransport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ChaosTransport")
+    }
+}
+
+impl Transport for ChaosTransport {
+    fn connect(&self, request: Request<()>) -> ConnectFuture {
+        let refused = {
+            let mut state = self.state.lock().unwrap();
+            state.handshakes += 1;
+
+            let refused = state.refuse_handshakes > 0;
+            state.refuse_handshakes = state.refuse_handshakes.saturating_sub(1);
+            refused
+        };
+
+        let inner = self.inner.clone();
+        let state = self.state.clone();
+
+        Box::pin(async move {
+            if refused {
+                debug!("Chaos: refusing handshake");
+                return Err(Error::Io(std::io::ErrorKind::ConnectionRefused.into()).into());
+            }
+
+            let (server_sink, server_stream) = inner.0.connect(request).await?;
+
+            let (client_tx, pump_rx) = mpsc::unbounded_channel::<Message>();
+            let (pump_tx, client_rx) = mpsc::unbounded_channel::<Message>();
+            let (kill_tx, kill_rx) = mpsc::unbounded_channel::<()>();
+            let (closed_tx, closed_rx) = oneshot::channel::<()>();
+
+            {
+                let mut state = state.lock().unwrap();
+                state.connections.retain(|c| !c.is_closed());
+                state.connections.push(kill_tx);
+            }
+
+            tokio::spawn(pump(
+                Pipes {
+                    server_sink,
+                    server_stream,
+                    from_client: pump_rx,
+                    to_client: pump_tx,
+                    kill: kill_rx,
+                    closed: closed_tx,
+                },
+                state,
+            ));
+
+            let sink: FrameSink = Box::pin(futures_util::sink::unfold(
+                client_tx,
+                |tx, message: Message| async move {
+                    tx.send(message).map_err(|_| Error::ConnectionClosed)?;
+                    Ok::<_, TransportError>(tx)
+                },
+            ));
+
+            // Delayed frames hold a sender, so the stream ends on the pump's signal rather than
+            // when every sender is gone
+            let stream: FrameStream = Box::pin(
+                UnboundedReceiverStream::new(client_rx)
+                    .map(Ok)
+                    .take_until(closed_rx),
+            );
+
+            Ok((sink, stream))
+        })
+    }
+}
+
+struct Pipes {
+    server_sink: FrameSink,
+    server_stream: FrameStream,
+    from_client: UnboundedReceiver<Message>,
+    to_client: UnboundedSender<Message>,
+    kill: UnboundedReceiver<()>,
+    closed: oneshot::Sender<()>,
+}
+
+/// Moves frames between client and server, applying faults, until either side closes
+async fn pump(pipes: Pipes, state: Arc<Mutex<ChaosState>>) {
+    let Pipes {
+        mut server_sink,
+        mut server_stream,
+        mut from_client,
+        to_client,
+        mut kill,
+        closed,
+    } = pipes;
+
+    let (to_server, mut server_rx) = mpsc::unbounded_channel::<Message>();
+    let writer = tokio::spawn(async move {
+        while let Some(message) = server_rx.recv().await {
+            if server_sink.send(message).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut held_sent: Option<Message> = None;
+    let mut held_received: Option<Message> = None;
+
+    loop {
+        let (direction, message) = tokio::select! {
+            message = from_client.recv() => match message {
+                Some(message) => (Direction::Sent, message),
+                None => break,
+            },
+            message = server_stream.next() => match message {
+                Some(Ok(message)) => (Direction::Received, message),
+                _ => break,
+            },
+            _ = kill.recv() => {
+                debug!("Chaos: dropping connection");
+                break;
+            }
+        };
+
+        let (target, held) = match direction {
+            Direction::Sent => (&to_server, &mut held_sent),
+            Direction::Received => (&to_client, &mut held_received),
+        };
+
+        let fault = state.lock().unwrap().fault(direction, &message);
+
+        let message = match fault {
+            None => message,
+            Some(Fault::Drop) => {
+                debug!("Chaos: dropping connection on {:?} frame", direction);
+                break;
+            }
+            Some(Fault::Delay(delay)) => {
+                let target = target.clone();
+                tokio::spawn(async move {
+                    sleep(delay).await;
+                    let _ = target.send(message);
+                });
+                continue;
+            }
+            Some(Fault::Reorder) => {
+                *held = Some(message);
+                continue;
+            }
+            Some(Fault::Truncate(len)) => truncate(message, len),
+        };
+
+        let _ = target.send(message);
+
+        if let Some(held) = held.take() {
+            let _ = target.send(held);
+        }
+    }
+
+    writer.abort();
+    let _ = closed.send(());
+}
+
+fn truncate(message: Message, len: usize) -> Message {
+    let Message::Text(mut text) = message else {
+        return message;
+    };
+
+    let mut len = len.min(text.len());
+    while !text.is_char_boundary(len) {
+        len -= 1;
+    }
+    text.truncate(len);
+
+    Message::Text(text)
+}
@@ -0,0 +1,572 @@
+//! In-process Realtime server for integration tests
+//!
+//! Enabled with the `testing` feature. [MockServer] speaks the Phoenix v1 JSON protocol over a
+//! local websocket, so clients can be tested end to end without a running Supabase stack.
+//! [ChaosTransport] sits between a client and the server to inject network faults.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use futures_util::{SinkExt, StreamExt};
+use log::debug;
+use serde_json::{json, Value};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::Message;
+use uuid::Uuid;
+
+use crate::message::payload::{JoinConfig, PostgresChangeData, PostgresChangesEvent};
+
+mod chaos;
+
+pub use chaos::{ChaosTransport, Fault};
+
+/// A client's membership of a topic
+struct Subscription {
+    conn: usize,
+    topic: String,
+    config: JoinConfig,
+    presence_key: String,
+}
+
+/// An open client connection, with its reader and writer tasks
+struct Connection {
+    tx: UnboundedSender<Message>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+/// A tracked presence meta
+struct Presence {
+    conn: usize,
+    key: String,
+    phx_ref: String,
+    data: Value,
+}
+
+#[derive(Default)]
+struct ServerState {
+    next_conn: usize,
+    next_ref: usize,
+    connections: HashMap<usize, Connection>,
+    subscriptions: Vec<Subscription>,
+    presences: HashMap<String, Vec<Presence>>,
+    join_errors: HashMap<String, String>,
+    token_rejection: Option<String>,
+    refuse_connections: usize,
+    received: Vec<Value>,
+}
+
+impl ServerState {
+    fn push(&self, conn: usize, topic: &str, event: &str, payload: Value, message_ref: &Value) {
+        let Some(connection) = self.connections.get(&conn) else {
+            return;
+        };
+
+        let frame = json!({
+            "topic": topic,
+            "event": event,
+            "payload": payload,
+            "ref": message_ref,
+        });
+
+        let _ = connection.tx.send(Message::Text(frame.to_string()));
+    }
+
+    fn reply(&self, conn: usize, topic: &str, status: &str, response: Value, message_ref: &Value) {
+        self.push(
+            conn,
+            topic,
+            "phx_reply",
+            json!({"status": status, "response": response}),
+            message_ref,
+        );
+    }
+
+    /// Connections subscribed to `topic`, each listed once
+    fn subscribers(&self, topic: &str) -> Vec<usize> {
+        let mut conns: Vec<usize> = self
+            .subscriptions
+            .iter()
+            .filter(|s| s.topic == topic)
+            .map(|s| s.conn)
+            .collect();
+        conns.sort();
+        conns.dedup();
+        conns
+    }
+
+    fn subscription(&self, conn: usize, topic: &str) -> Option<&Subscription> {
+        self.subscriptions
+            .iter()
+            .find(|s| s.conn == conn && s.topic == topic)
+    }
+
+    fn presence_state(&self, topic: &str) -> Value {
+        let mut state = serde_json::Map::new();
+
+        for presence in self.presences.get(topic).into_iter().flatten() {
+            let metas = state
+                .entry(presence.key.clone())
+                .or_insert_with(|| json!({"metas": []}));
+            metas["metas"].as_array_mut().unwrap().push(presence.meta());
+        }
+
+        Value::Object(state)
+    }
+
+    fn presence_diff(&self, topic: &str, joins: &[Presence], leaves: &[Presence]) {
+        let group = |presences: &[Presence]| {
+            let mut grouped = serde_json::Map::new();
+            for presence in presences {
+                let metas = grouped
+                    .entry(presence.key.clone())
+                    .or_insert_with(|| json!({"metas": []}));
+                metas["metas"].as_array_mut().unwrap().push(presence.meta());
+            }
+            Value::Object(grouped)
+        };
+
+        let payload = json!({"joins": group(joins), "leaves": group(leaves)});
+
+        for conn in self.subscribers(topic) {
+            self.push(conn, topic, "presence_diff", payload.clone(), &Value::Null);
+        }
+    }
+
+    /// Removes every presence `conn` tracks on `topic`, notifying the other subscribers
+    fn untrack(&mut self, conn: usize, topic: &str) {
+        let Some(presences) = self.presences.get_mut(topic) else {
+            return;
+        };
+
+        let (left, kept) = std::mem::take(presences)
+            .into_iter()
+            .partition(|p| p.conn == conn);
+        *presences = kept;
+
+        let left: Vec<Presence> = left;
+        if !left.is_empty() {
+            self.presence_diff(topic, &[], &left);
+        }
+    }
+
+    /// Forgets a connection and everything it had joined
+    fn drop_connection(&mut self, conn: usize) {
+        let topics: Vec<String> = self
+            .subscriptions
+            .iter()
+            .filter(|s| s.conn == conn)
+            .map(|s| s.topic.clone())
+            .collect();
+
+        self.subscriptions.retain(|s| s.conn != conn);
+
+        for topic in topics {
+            self.untrack(conn, &topic);
+        }
+
+        if let Some(connection) = self.connections.remove(&conn) {
+            for task in connection.tasks {
+                task.abort();
+            }
+        }
+    }
+
+    fn handle(&mut self, conn: usize, frame: Value) {
+        self.received.push(frame.clone());
+
+        let topic = frame["topic"].as_str().unwrap_or_default().to_string();
+        let event = frame["event"].as_str().unwrap_or_default();
+        let message_ref = &frame["ref"];
+        let payload = &frame["payload"];
+
+        match event {
+            "heartbeat" => self.reply(conn, &topic, "ok", json!({}), message_ref),
+            "phx_join" => self.join(conn, &topic, payload, message_ref),
+            "phx_leave" => {
+                self.untrack(conn, &topic);
+                self.subscriptions
+                    .retain(|s| !(s.conn == conn && s.topic == topic));
+                self.reply(conn, &topic, "ok", json!({}), message_ref);
+                self.push(conn, &topic, "phx_close", json!({}), message_ref);
+            }
+            "broadcast" => {
+                let Some(sub) = self.subscription(conn, &topic) else {
+                    return;
+                };
+                let broadcast_self = sub.config.broadcast.broadcast_self;
+                let ack = sub.config.broadcast.ack;
+
+                for other in self.subscribers(&topic) {
+                    if other != conn || broadcast_self {
+                        self.push(other, &topic, "broadcast", payload.clone(), &Value::Null);
+                    }
+                }
+
+                if ack {
+                    self.reply(conn, &topic, "ok", json!({}), message_ref);
+                }
+            }
+            "presence" => match payload["event"].as_str() {
+                Some("track") => self.track(conn, &topic, payload["payload"].clone()),
+                Some("untrack") => self.untrack(conn, &topic),
+                _ => debug!("Mock server: unknown presence event {}", payload),
+            },
+            "access_token" => {
+                let Some(message) = self.token_rejection.take() else {
+                    return;
+                };
+
+                self.push(
+                    conn,
+                    &topic,
+                    "system",
+                    json!({
+                        "channel": topic.trim_start_matches("realtime:"),
+                        "extension": "system",
+                        "message": message,
+                        "status": "error",
+                    }),
+                    &Value::Null,
+                );
+                self.untrack(conn, &topic);
+                self.subscriptions
+                    .retain(|s| !(s.conn == conn && s.topic == topic));
+                self.push(conn, &topic, "phx_close", json!({}), &Value::Null);
+            }
+            _ => debug!("Mock server: unhandled event {}", event),
+        }
+    }
+
+    fn join(&mut self, conn: usize, topic: &str, payload: &Value, message_ref: &Value) {
+        if let Some(reason) = self.join_errors.remove(topic) {
+            self.reply(conn, topic, "error", json!({"reason": reason}), message_ref);
+            return;
+        }
+
+        let config: JoinConfig =
+            serde_json::from_value(payload["config"].clone()).unwrap_or_default();
+
+        let presence_key = match &config.presence.key {
+            Some(key) if !key.is_empty() => key.clone(),
+            _ => Uuid::new_v4().to_string(),
+        };
+
+        let postgres_changes = config
+            .postgres_changes
+            .iter()
+            .enumerate()
+            .map(|(id, change)| {
+                let mut change = serde_json::to_value(change).unwrap();
+                change["id"] = json!(id);
+                change
+            })
+            .collect::<Vec<_>>();
+
+        // A rejoin on the same connection replaces the previous subscription
+        self.untrack(conn, topic);
+        self.subscriptions
+            .retain(|s| !(s.conn == conn && s.topic == topic));
+        self.subscriptions.push(Subscription {
+            conn,
+            topic: topic.to_string(),
+            config,
+            presence_key,
+        });
+
+        self.reply(
+            conn,
+            topic,
+            "ok",
+            json!({"postgres_changes": postgres_changes}),
+            message_ref,
+        );
+
+        let state = self.presence_state(topic);
+        self.push(conn, topic, "presence_state", state, &Value::Null);
+    }
+
+    fn track(&mut self, conn: usize, topic: &str, data: Value) {
+        let Some(sub) = self.subscription(conn, topic) else {
+            return;
+        };
+        let key = sub.presence_key.clone();
+
+        self.next_ref += 1;
+        let joined = Presence {
+            conn,
+            key,
+            phx_ref: self.next_ref.to_string(),
+            data,
+        };
+
+        let presences = self.presences.entry(topic.to_string()).or_default();
+        let (left, kept): (Vec<Presence>, Vec<Presence>) = std::mem::take(presences)
+            .into_iter()
+            .partition(|p| p.conn == conn);
+        *presences = kept;
+
+        self.presence_diff(topic, std::slice::from_ref(&joined), &left);
+
+        self.presences
+            .entry(topic.to_string())
+            .or_default()
+            .push(joined);
+    }
+}
+
+impl Presence {
+    fn meta(&self) -> Value {
+        let mut meta = match &self.data {
+            Value::Object(data) => data.clone(),
+            _ => serde_json::Map::new(),
+        };
+        meta.insert("phx_ref".into(), json!(self.phx_ref));
+        Value::Object(meta)
+    }
+}
+
+/// Local websocket server implementing the Realtime protocol
+///
+/// Handles join, leave and heartbeat replies, broadcast fan-out honouring `self` and `ack`,
+/// presence tracking with `presence_state` and `presence_diff`, and `access_token` pushes.
+/// Postgres changes and arbitrary messages can be injected, and joins, tokens and connections can
+/// be made to fail.
+///
+/// The server runs on its own runtime. Drive clients with the sync API from a plain `#[test]`,
+/// since the client's runtime can't be dropped inside another runtime.
+///
+/// ```no_run
+/// # use realtime_rs::testing::MockServer;
+/// # use realtime_rs::realtime_client::RealtimeClientBuilder;
+/// let server = MockServer::start();
+///
+/// let client = RealtimeClientBuilder::new(server.endpoint(), "anon_key")
+///     .connect()
+///     .unwrap()
+///     .to_sync();
+/// ```
+pub struct MockServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<ServerState>>,
+    rt: Option<Runtime>,
+}
+
+impl MockServer {
+    /// Start a server on a free local port
+    pub fn start() -> Self {
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .enable_all()
+            .build()
+            .unwrap();
+
+        let listener = rt
+            .block_on(TcpListener::bind("127.0.0.1:0"))
+            .expect("Mock server could not bind a local port");
+        let addr = listener.local_addr().unwrap();
+
+        let state: Arc<Mutex<ServerState>> = Default::default();
+
+        let accept_state = state.clone();
+        rt.spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                {
+                    let mut state = accept_state.lock().unwrap();
+                    if state.refuse_connections > 0 {
+                        state.refuse_connections -= 1;
+                        debug!("Mock server: refusing connection");
+                        continue;
+                    }
+                }
+
+                tokio::spawn(Self::serve(stream, accept_state.clone()));
+            }
+        });
+
+        Self {
+            addr,
+            state,
+            rt: Some(rt),
+        }
+    }
+
+    async fn serve(stream: TcpStream, state: Arc<Mutex<ServerState>>) {
+        let Ok(ws) = tokio_tungstenite::accept_async(stream).await else {
+            return;
+        };
+
+        let (mut write, mut read) = ws.split();
+        let (tx, mut rx) = mpsc::unbounded_channel::<Message>();
+
+        let conn = {
+            let mut state = state.lock().unwrap();
+            state.next_conn += 1;
+            let conn = state.next_conn;
+            state
+                .connections
+                .insert(conn, Connection { tx, tasks: vec![] });
+            conn
+        };
+
+        let writer = tokio::spawn(async move {
+            while let Some(message) = rx.recv().await {
+                if write.send(message).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let reader_state = state.clone();
+        let reader = tokio::spawn(async move {
+            while let Some(Ok(message)) = read.next().await {
+                let Ok(text) = message.to_text() else {
+                    continue;
+                };
+
+                let Ok(frame) = serde_json::from_str::<Value>(text) else {
+                    continue;
+                };
+
+                reader_state.lock().unwrap().handle(conn, frame);
+            }
+
+            debug!("Mock server: connection {} closed", conn);
+            reader_state.lock().unwrap().drop_connection(conn);
+        });
+
+        if let Some(connection) = state.lock().unwrap().connections.get_mut(&conn) {
+            connection.tasks.push(writer);
+            connection.tasks.push(reader);
+        }
+    }
+
+    /// Endpoint to pass to [crate::realtime_client::RealtimeClientBuilder::new()]
+    pub fn endpoint(&self) -> String {
+        format!("http://{}/realtime/v1", self.addr)
+    }
+
+    /// Number of open client connections
+    pub fn connection_count(&self) -> usize {
+        self.state.lock().unwrap().connections.len()
+    }
+
+    /// Number of joined subscriptions on `topic`. Accepts topics with or without the
+    /// `realtime:` prefix.
+    pub fn subscription_count(&self, topic: &str) -> usize {
+        let topic = full_topic(topic);
+        let state = self.state.lock().unwrap();
+        state
+            .subscriptions
+            .iter()
+            .filter(|s| s.topic == topic)
+            .count()
+    }
+
+    /// Every frame recieved from clients so far, in order
+    pub fn received(&self) -> Vec<Value> {
+        self.state.lock().unwrap().received.clone()
+    }
+
+    /// Reject the next join on `topic` with `reason`
+    pub fn fail_next_join(&self, topic: &str, reason: impl Into<String>) {
+        self.state
+            .lock()
+            .unwrap()
+            .join_errors
+            .insert(full_topic(topic), reason.into());
+    }
+
+    /// Reject the next `access_token` push with a system error carrying `message`, then close
+    /// the channel, as Realtime does for invalid tokens
+    pub fn reject_next_access_token(&self, message: impl Into<String>) {
+        self.state.lock().unwrap().token_rejection = Some(message.into());
+    }
+
+    /// Refuse the next `count` connection attempts before the websocket handshake
+    pub fn refuse_connections(&self, count: usize) {
+        self.state.lock().unwrap().refuse_connections = count;
+    }
+
+    /// Drop every client connection without a close frame, as a network failure would
+    pub fn disconnect_all(&self) {
+        let mut state = self.state.lock().unwrap();
+        let conns: Vec<usize> = state.connections.keys().copied().collect();
+
+        for conn in conns {
+            state.drop_connection(conn);
+        }
+    }
+
+    /// Send a message with any `event` and `payload` to every subscriber of `topic`
+    pub fn push(&self, topic: &str, event: &str, payload: Value) {
+        let topic = full_topic(topic);
+        let state = self.state.lock().unwrap();
+
+        for conn in state.subscribers(&topic) {
+            state.push(conn, &topic, event, payload.clone(), &Value::Null);
+        }
+    }
+
+    /// Broadcast `payload` under `event` to every subscriber of `topic`
+    pub fn broadcast(&self, topic: &str, event: &str, payload: Value) {
+        self.push(
+            topic,
+            "broadcast",
+            json!({"type": "broadcast", "event": event, "payload": payload}),
+        );
+    }
+
+    /// Deliver a postgres change to every subscription listening for it. `ids` are filled in
+    /// from each subscription's `postgres_changes` config. Filters are not evaluated.
+    pub fn postgres_change(&self, data: PostgresChangeData) {
+        let state = self.state.lock().unwrap();
+
+        for sub in &state.subscriptions {
+            let ids: Vec<usize> = sub
+                .config
+                .postgres_changes
+                .iter()
+                .enumerate()
+                .filter(|(_, change)| {
+                    (change.event == PostgresChangesEvent::All || change.event == data.change_type)
+                        && change.schema == data.schema
+                        && (change.table.is_empty()
+                            || change.table == "*"
+                            || change.table == data.table)
+                })
+                .map(|(id, _)| id)
+                .collect();
+
+            if ids.is_empty() {
+                continue;
+            }
+
+            state.push(
+                sub.conn,
+                &sub.topic,
+                "postgres_changes",
+                json!({"ids": ids, "data": data}),
+                &Value::Null,
+            );
+        }
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        if let Some(rt) = self.rt.take() {
+            rt.shutdown_background();
+        }
+    }
+}
+
+fn full_topic(topic: &str) -> String {
+    if topic.starts_with("realtime:") {
+        return topic.to_string();
+    }
+    format!("realtime:{}", topic)
+}
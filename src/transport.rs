@@ -0,0 +1,198 @@
+//! Websocket transports for the realtime client
+//!
+//! A [Transport] opens a connection from a handshake request and returns a sink of outgoing
+//! frames and a stream of incoming frames. [TungsteniteTransport] is the default.
+//! [MemoryTransport] connects to an in-process [MemoryListener] instead of a socket.
+
+use std::fmt::Debug;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use futures_util::{Sink, SinkExt, Stream, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::{http::Request, Error, Message};
+
+/// Error type of transports, tungstenite's so its errors pass through unchanged. Boxed, as it's
+/// large and returned on every frame.
+pub type TransportError = Box<Error>;
+
+/// Outgoing frames
+pub type FrameSink = Pin<Box<dyn Sink<Message, Error = TransportError> + Send>>;
+
+/// Incoming frames. The connection is considered lost once this ends or yields an error.
+pub type FrameStream = Pin<Box<dyn Stream<Item = Result<Message, TransportError>> + Send>>;
+
+/// Result of [Transport::connect()]
+pub type ConnectFuture =
+    Pin<Box<dyn Future<Output = Result<(FrameSink, FrameStream), TransportError>> + Send>>;
+
+/// Which way a frame travels, from the client's point of view
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// Opens websocket connections for the client
+///
+/// Set with [crate::realtime_client::RealtimeClientBuilder::set_transport()]. Called for the
+/// first connection and for every reconnect attempt.
+pub trait Transport: Send + Sync {
+    /// Connect using `request`, which carries the endpoint URL and handshake headers
+    fn connect(&self, request: Request<()>) -> ConnectFuture;
+}
+
+/// Shareable handle to a [Transport]
+#[derive(Clone)]
+pub(crate) struct TransportHandle(pub Arc<dyn Transport>);
+
+impl Default for TransportHandle {
+    fn default() -> Self {
+        Self(Arc::new(TungsteniteTransport))
+    }
+}
+
+impl Debug for TransportHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("TransportHandle")
+    }
+}
+
+/// Default transport, using `tokio_tungstenite::connect_async`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TungsteniteTransport;
+
+impl Transport for TungsteniteTransport {
+    fn connect(&self, request: Request<()>) -> ConnectFuture {
+        Box::pin(async move {
+            let (ws_stream, _res) = connect_async(request).await?;
+            let (write, read) = ws_stream.split();
+
+            let sink: FrameSink = Box::pin(write.sink_map_err(Box::new));
+            let stream: FrameStream = Box::pin(read.map_err(Box::new));
+
+            Ok((sink, stream))
+        })
+    }
+}
+
+/// Transport connecting to an in-process [MemoryListener]
+///
+/// Lets tests drive a client deterministically: every frame the client sends arrives at the
+/// [MemoryConnection], and every frame sent from it reaches the client.
+///
+/// ```
+/// # use realtime_rs::transport::MemoryTransport;
+/// # use realtime_rs::realtime_client::RealtimeClientBuilder;
+/// let (transport, mut listener) = MemoryTransport::new();
+///
+/// let client = RealtimeClientBuilder::new("http://localhost/realtime/v1", "anon_key")
+///     .set_transport(transport)
+///     .connect()
+///     .unwrap()
+///     .to_sync();
+///
+/// let connection = listener.accept_blocking().unwrap();
+/// assert!(connection.request().uri().path().ends_with("/websocket"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct MemoryTransport {
+    connections: UnboundedSender<MemoryConnection>,
+}
+
+impl MemoryTransport {
+    /// Create a transport and the listener its connections arrive at
+    pub fn new() -> (Self, MemoryListener) {
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        (Self { connections: tx }, MemoryListener { connections: rx })
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn connect(&self, request: Request<()>) -> ConnectFuture {
+        let (client_tx, server_rx) = mpsc::unbounded_channel::<Message>();
+        let (server_tx, client_rx) = mpsc::unbounded_channel::<Message>();
+
+        let connection = MemoryConnection {
+            request,
+            tx: server_tx,
+            rx: server_rx,
+        };
+
+        let accepted = self.connections.send(connection).is_ok();
+
+        Box::pin(async move {
+            if !accepted {
+                return Err(Error::Io(io::ErrorKind::ConnectionRefused.into()).into());
+            }
+
+            let sink: FrameSink = Box::pin(futures_util::sink::unfold(
+                client_tx,
+                |tx, message: Message| async move {
+                    tx.send(message).map_err(|_| Error::ConnectionClosed)?;
+                    Ok::<_, TransportError>(tx)
+                },
+            ));
+            let stream: FrameStream = Box::pin(UnboundedReceiverStream::new(client_rx).map(Ok));
+
+            Ok((sink, stream))
+        })
+    }
+}
+
+/// Accepts connections made through a [MemoryTransport]. Once dropped, connecting fails.
+#[derive(Debug)]
+pub struct MemoryListener {
+    connections: UnboundedReceiver<MemoryConnection>,
+}
+
+impl MemoryListener {
+    /// Wait for the next connection. `None` once every [MemoryTransport] is gone.
+    pub async fn accept(&mut self) -> Option<MemoryConnection> {
+        self.connections.recv().await
+    }
+
+    /// Blocking [Self::accept()]. Panics if called inside an async context.
+    pub fn accept_blocking(&mut self) -> Option<MemoryConnection> {
+        self.connections.blocking_recv()
+    }
+}
+
+/// Server side of a [MemoryTransport] connection. Dropping it closes the connection.
+#[derive(Debug)]
+pub struct MemoryConnection {
+    request: Request<()>,
+    tx: UnboundedSender<Message>,
+    rx: UnboundedReceiver<Message>,
+}
+
+impl MemoryConnection {
+    /// The client's handshake request
+    pub fn request(&self) -> &Request<()> {
+        &self.request
+    }
+
+    /// Send a frame to the client. Fails once the client has disconnected.
+    pub fn send(&self, message: Message) -> Result<(), TransportError> {
+        self.tx
+            .send(message)
+            .map_err(|_| Error::ConnectionClosed.into())
+    }
+
+    /// Wait for the next frame from the client. `None` once the client has disconnected.
+    pub async fn recv(&mut self) -> Option<Message> {
+        self.rx.recv().await
+    }
+
+    /// Blocking [Self::recv()]. Panics if called inside an async context.
+    pub fn recv_blocking(&mut self) -> Option<Message> {
+        self.rx.blocking_recv()
+    }
+}
@@ -0,0 +1,161 @@
+//! Access tokens supplied by an `AccessTokenProvider`, driven through `MemoryTransport`
+
+mod common;
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+
+use realtime_rs::{
+    realtime_channel::{ChannelBuildError, RealtimeChannelBuilder},
+    realtime_client::{ConfigError, ConnectError},
+    transport::{MemoryConnection, MemoryTransport},
+};
+
+use common::{builder, connect, recv, wait_for};
+
+type Token = Arc<Mutex<Option<String>>>;
+
+/// Provider handing out whatever `token` holds, counting its calls
+fn provided(
+    token: &str,
+) -> (
+    Token,
+    Arc<AtomicUsize>,
+    impl Fn() -> std::future::Ready<Option<String>>,
+) {
+    let token: Token = Arc::new(Mutex::new(Some(token.to_string())));
+    let calls = Arc::new(AtomicUsize::new(0));
+
+    let (provider_token, provider_calls) = (token.clone(), calls.clone());
+    let provider = move || {
+        provider_calls.fetch_add(1, Ordering::SeqCst);
+        std::future::ready(provider_token.lock().unwrap().clone())
+    };
+
+    (token, calls, provider)
+}
+
+/// The bearer token the client connected with
+fn bearer(connection: &MemoryConnection) -> &str {
+    connection.request().headers()["Authorization"]
+        .to_str()
+        .unwrap()
+        .trim_start_matches("Bearer ")
+}
+
+#[test]
+fn provider_is_asked_on_connect() {
+    let (_token, calls, provider) = provided("first");
+
+    let (_client, _listener, connection) = connect(builder().set_access_token_provider(provider));
+
+    assert_eq!(bearer(&connection), "first");
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn provider_is_asked_on_subscribe() {
+    let (token, calls, provider) = provided("first");
+
+    let (client, _listener, mut connection) =
+        connect(builder().set_access_token_provider(provider));
+
+    *token.lock().unwrap() = Some("second".into());
+
+    let channel = RealtimeChannelBuilder::new("room")
+        .build_sync(&client)
+        .unwrap();
+    channel.subscribe();
+
+    let join = recv(&mut connection);
+    assert_eq!(join["event"], "phx_join");
+    assert_eq!(join["payload"]["access_token"], "second");
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn provider_is_asked_on_reconnect() {
+    let (token, calls, provider) = provided("first");
+
+    let (_client, mut listener, connection) =
+        connect(builder().set_access_token_provider(provider));
+    assert_eq!(bearer(&connection), "first");
+
+    *token.lock().unwrap() = Some("second".into());
+    drop(connection);
+
+    let connection = listener.accept_blocking().unwrap();
+    assert_eq!(bearer(&connection), "second");
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn provider_returning_none_keeps_the_current_token() {
+    let (token, calls, provider) = provided("first");
+    *token.lock().unwrap() = None;
+
+    let (client, _listener, mut connection) = connect(
+        builder()
+            .set_access_token("current")
+            .set_access_token_provider(provider),
+    );
+    assert_eq!(bearer(&connection), "current");
+
+    let channel = RealtimeChannelBuilder::new("room")
+        .build_sync(&client)
+        .unwrap();
+    channel.subscribe();
+
+    let join = recv(&mut connection);
+    assert_eq!(join["payload"]["access_token"], "current");
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn unusable_provided_token_fails_the_connection() {
+    let (_token, _calls, provider) = provided("jwt\n");
+    let (transport, _listener) = MemoryTransport::new();
+
+    let client = builder()
+        .set_access_token_provider(provider)
+        .set_transport(transport)
+        .connect()
+        .unwrap()
+        .to_sync();
+
+    let unusable = ConnectError::Config(ConfigError::AccessToken);
+    wait_for(|| client.get_error().unwrap() == Some(unusable.clone()));
+
+    assert_eq!(client.connect().unwrap(), Err(unusable));
+    assert_eq!(client.get_access_token().unwrap(), "anon_key");
+
+    let result = RealtimeChannelBuilder::new("room").build_sync(&client);
+    assert!(matches!(result, Err(ChannelBuildError::NotConnected)));
+}
+
+#[test]
+fn unusable_tokens_are_not_set() {
+    let (token, _calls, provider) = provided("first");
+
+    let (client, _listener, mut connection) =
+        connect(builder().set_access_token_provider(provider));
+
+    assert_eq!(
+        client.set_access_token("jwt\n".into()).unwrap(),
+        Err(ConfigError::AccessToken)
+    );
+    assert_eq!(client.get_access_token().unwrap(), "first");
+
+    *token.lock().unwrap() = Some("jwt\n".into());
+
+    let channel = RealtimeChannelBuilder::new("room")
+        .build_sync(&client)
+        .unwrap();
+    channel.subscribe();
+
+    let join = recv(&mut connection);
+    assert_eq!(join["payload"]["access_token"], "first");
+    assert_eq!(client.get_error().unwrap(), None);
+}
@@ -0,0 +1,130 @@
+//! Async callback execution modes, driven through `MemoryTransport`
+
+mod common;
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::Duration,
+};
+
+use realtime_rs::{
+    message::presence::PresenceState,
+    realtime_channel::{CallbackMode, ChannelManager, RealtimeChannelBuilder},
+};
+use serde_json::json;
+
+use common::{accept_join, broadcast, builder, connect, send, wait_for};
+
+#[test]
+fn sequential_callbacks_keep_message_order() {
+    let (client, _listener, mut connection) = connect(&mut builder());
+
+    let log = Arc::new(Mutex::new(vec![]));
+
+    let ping_log = log.clone();
+    let channel = RealtimeChannelBuilder::new("room")
+        .set_callback_mode(CallbackMode::Sequential)
+        .on_broadcast_async("ping", move |payload| {
+            let log = ping_log.clone();
+            async move {
+                // Earlier pings take longer, so they'd finish last if run concurrently
+                let n = payload["n"].as_u64().unwrap();
+                tokio::time::sleep(Duration::from_millis(40 - n * 10)).await;
+                log.lock().unwrap().push(n);
+            }
+        })
+        .build_sync(&client)
+        .unwrap();
+
+    channel.subscribe();
+    accept_join(&mut connection);
+
+    for n in 1..=3 {
+        send(&connection, broadcast("room", "ping", json!({ "n": n })));
+    }
+
+    wait_for(|| log.lock().unwrap().len() == 3);
+    assert_eq!(*log.lock().unwrap(), vec![1, 2, 3]);
+}
+
+#[test]
+fn parallel_callbacks_are_bounded() {
+    let (client, _listener, mut connection) = connect(&mut builder());
+
+    let running = Arc::new(AtomicUsize::new(0));
+    let most_running = Arc::new(AtomicUsize::new(0));
+    let finished = Arc::new(AtomicUsize::new(0));
+
+    let (task_running, task_most, task_finished) =
+        (running.clone(), most_running.clone(), finished.clone());
+    let channel = RealtimeChannelBuilder::new("room")
+        .set_callback_mode(CallbackMode::Parallel(2))
+        .on_broadcast_async("ping", move |_| {
+            let (running, most_running, finished) = (
+                task_running.clone(),
+                task_most.clone(),
+                task_finished.clone(),
+            );
+            async move {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                most_running.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(30)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+                finished.fetch_add(1, Ordering::SeqCst);
+            }
+        })
+        .build_sync(&client)
+        .unwrap();
+
+    channel.subscribe();
+    accept_join(&mut connection);
+
+    for n in 0..6 {
+        send(&connection, broadcast("room", "ping", json!({ "n": n })));
+    }
+
+    wait_for(|| finished.load(Ordering::SeqCst) == 6);
+    assert_eq!(most_running.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn sequential_callbacks_can_call_back_into_the_channel() {
+    let (client, _listener, mut connection) = connect(&mut builder());
+
+    let manager: Arc<OnceLock<ChannelManager>> = Default::default();
+    let seen: Arc<Mutex<Vec<PresenceState>>> = Default::default();
+
+    let (task_manager, log) = (manager.clone(), seen.clone());
+    let channel = RealtimeChannelBuilder::new("room")
+        .on_presence_sync_async(move |_state| {
+            let (manager, log) = (task_manager.clone(), log.clone());
+            async move {
+                // Needs the presence lock the sync was applied under
+                let state = manager.get().unwrap().get_presence_state().await;
+                log.lock().unwrap().push(state);
+            }
+        })
+        .build_sync(&client)
+        .unwrap();
+
+    let _ = manager.set(channel.clone().to_async());
+
+    channel.subscribe();
+    let join = accept_join(&mut connection);
+
+    send(
+        &connection,
+        json!({
+            "topic": "realtime:room",
+            "event": "presence_state",
+            "payload": {"user": {"metas": [{"phx_ref": "a"}]}},
+            "ref": join["ref"],
+        }),
+    );
+
+    wait_for(|| seen.lock().unwrap().len() == 1);
+    assert!(seen.lock().unwrap()[0].contains("user"));
+}
@@ -0,0 +1,422 @@
+//! A channel's presence tracking and state, driven through `MemoryTransport`
+
+mod common;
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use realtime_rs::{
+    message::{
+        payload::BroadcastPayload,
+        presence::{PhxMap, PresenceEvent, PresenceState},
+    },
+    realtime_channel::{
+        ChannelBuildError, ChannelManagerSync, ChannelState, RealtimeChannelBuilder,
+    },
+    realtime_client::ClientManagerSync,
+    transport::MemoryConnection,
+};
+use serde_json::{json, Value};
+
+use common::{accept_join, broadcast, builder, connect, recv, reply, send, wait_for};
+
+fn status(value: &str) -> HashMap<String, Value> {
+    HashMap::from([("status".to_string(), json!(value))])
+}
+
+/// Joined channel on "room"
+fn joined(client: &ClientManagerSync, connection: &mut MemoryConnection) -> ChannelManagerSync {
+    let channel = RealtimeChannelBuilder::new("room")
+        .build_sync(client)
+        .unwrap();
+
+    channel.subscribe();
+    accept_join(connection);
+    wait_for(|| channel.get_state().unwrap() == ChannelState::Joined);
+
+    channel
+}
+
+/// Broadcasts a marker and asserts it's the next frame the server sees, so nothing was sent
+/// in between
+fn assert_nothing_else_sent(channel: &ChannelManagerSync, connection: &mut MemoryConnection) {
+    channel
+        .broadcast(BroadcastPayload::new("marker", HashMap::new()))
+        .unwrap();
+
+    let frame = recv(connection);
+    assert_eq!(frame["event"], "broadcast");
+    assert_eq!(frame["payload"]["event"], "marker");
+}
+
+#[test]
+fn track_sent_while_joining_is_not_retracked() {
+    let (client, _listener, mut connection) = connect(&mut builder());
+
+    let channel = RealtimeChannelBuilder::new("room")
+        .build_sync(&client)
+        .unwrap();
+    channel.subscribe();
+
+    let join = recv(&mut connection);
+    channel.track(status("online")).unwrap();
+
+    let track = recv(&mut connection);
+    assert_eq!(track["event"], "presence");
+    assert_eq!(track["payload"]["event"], "track");
+
+    send(
+        &connection,
+        reply(&join, "ok", json!({"postgres_changes": []})),
+    );
+    wait_for(|| channel.get_state().unwrap() == ChannelState::Joined);
+
+    assert_nothing_else_sent(&channel, &mut connection);
+}
+
+#[test]
+fn unsent_track_is_not_kept() {
+    let (client, _listener, mut connection) = connect(&mut builder());
+    let channel = joined(&client, &mut connection);
+
+    // Leaving channels refuse to send
+    let _ = channel.unsubscribe().unwrap().unwrap();
+    let leave = recv(&mut connection);
+    channel.track(status("online")).unwrap();
+
+    send(&connection, reply(&leave, "ok", json!({})));
+    wait_for(|| channel.get_state().unwrap() == ChannelState::Closed);
+
+    channel.subscribe();
+    accept_join(&mut connection);
+    wait_for(|| channel.get_state().unwrap() == ChannelState::Joined);
+
+    assert_nothing_else_sent(&channel, &mut connection);
+}
+
+#[test]
+fn tracked_payload_is_resent_on_rejoin() {
+    let (client, mut listener, mut connection) = connect(&mut builder());
+    let channel = joined(&client, &mut connection);
+
+    channel.track(status("online")).unwrap();
+    assert_eq!(recv(&mut connection)["payload"]["event"], "track");
+
+    drop(connection);
+    let mut connection = listener.accept_blocking().unwrap();
+
+    accept_join(&mut connection);
+
+    let retrack = recv(&mut connection);
+    assert_eq!(retrack["payload"]["event"], "track");
+    assert_eq!(retrack["payload"]["payload"], json!({"status": "online"}));
+}
+
+fn presence_state(value: Value) -> Value {
+    json!({
+        "topic": "realtime:room",
+        "event": "presence_state",
+        "payload": value,
+        "ref": null,
+    })
+}
+
+fn presence_diff(joins: Value, leaves: Value) -> Value {
+    json!({
+        "topic": "realtime:room",
+        "event": "presence_diff",
+        "payload": {"joins": joins, "leaves": leaves},
+        "ref": null,
+    })
+}
+
+fn metas(phx_ref: &str) -> Value {
+    json!({"metas": [{"phx_ref": phx_ref}]})
+}
+
+/// Channel on "room" counting "marker" broadcasts, to tell when earlier frames were handled
+fn marked(client: &ClientManagerSync) -> (ChannelManagerSync, Arc<AtomicUsize>) {
+    let markers = Arc::new(AtomicUsize::new(0));
+
+    let count = markers.clone();
+    let channel = RealtimeChannelBuilder::new("room")
+        .on_broadcast("marker", move |_| {
+            count.fetch_add(1, Ordering::SeqCst);
+        })
+        .build_sync(client)
+        .unwrap();
+
+    (channel, markers)
+}
+
+/// Sends a marker broadcast and waits until the channel has handled it
+fn flush(connection: &MemoryConnection, markers: &AtomicUsize) {
+    let seen = markers.load(Ordering::SeqCst);
+    send(connection, broadcast("room", "marker", json!({})));
+    wait_for(|| markers.load(Ordering::SeqCst) > seen);
+}
+
+#[test]
+fn diff_before_first_state_is_buffered() {
+    let (client, _listener, mut connection) = connect(&mut builder());
+    let (channel, markers) = marked(&client);
+
+    channel.subscribe();
+    accept_join(&mut connection);
+
+    send(
+        &connection,
+        presence_diff(json!({"b": metas("2")}), json!({})),
+    );
+    flush(&connection, &markers);
+
+    assert!(channel.get_presence_state().is_empty());
+
+    send(&connection, presence_state(json!({"a": metas("1")})));
+    wait_for(|| channel.get_presence_state().contains("b"));
+
+    assert!(channel.get_presence_state().contains("a"));
+}
+
+#[test]
+fn rejoin_buffers_diffs_again_and_drops_stale_ones() {
+    let (client, mut listener, mut connection) = connect(&mut builder());
+    let (channel, markers) = marked(&client);
+
+    channel.subscribe();
+    accept_join(&mut connection);
+    send(&connection, presence_state(json!({"a": metas("1")})));
+    wait_for(|| channel.get_presence_state().contains("a"));
+
+    // Applied straight away once the join's state is in
+    send(
+        &connection,
+        presence_diff(json!({"b": metas("2")}), json!({})),
+    );
+    wait_for(|| channel.get_presence_state().contains("b"));
+
+    drop(connection);
+    let mut connection = listener.accept_blocking().unwrap();
+    accept_join(&mut connection);
+
+    // Buffered until the new join's state arrives
+    send(
+        &connection,
+        presence_diff(json!({"c": metas("3")}), json!({})),
+    );
+    flush(&connection, &markers);
+    assert!(!channel.get_presence_state().contains("c"));
+
+    drop(connection);
+    let mut connection = listener.accept_blocking().unwrap();
+    accept_join(&mut connection);
+
+    // The diff buffered for the previous join is stale and dropped
+    send(&connection, presence_state(json!({"a": metas("1")})));
+    wait_for(|| !channel.get_presence_state().contains("b"));
+
+    flush(&connection, &markers);
+    let state = channel.get_presence_state();
+    assert!(state.contains("a"));
+    assert!(!state.contains("c"));
+}
+
+/// Sorted phx_refs of `metas`
+fn refs(metas: &PhxMap) -> Vec<String> {
+    let mut refs: Vec<String> = metas.keys().cloned().collect();
+    refs.sort();
+    refs
+}
+
+#[test]
+fn sync_fires_once_per_state_and_diff() {
+    let (client, _listener, mut connection) = connect(&mut builder());
+
+    let syncs: Arc<Mutex<Vec<PresenceState>>> = Default::default();
+
+    let log = syncs.clone();
+    let channel = RealtimeChannelBuilder::new("room")
+        .on_presence_sync(move |state| log.lock().unwrap().push(state.clone()))
+        .build_sync(&client)
+        .unwrap();
+
+    channel.subscribe();
+    accept_join(&mut connection);
+
+    send(
+        &connection,
+        presence_state(json!({"a": metas("1"), "b": metas("2"), "c": metas("3")})),
+    );
+    wait_for(|| syncs.lock().unwrap().len() == 1);
+
+    send(
+        &connection,
+        presence_diff(
+            json!({"d": metas("4"), "e": metas("5")}),
+            json!({"a": metas("1")}),
+        ),
+    );
+    wait_for(|| syncs.lock().unwrap().len() == 2);
+
+    // Any extra sync from the diff would land before this state's
+    send(&connection, presence_state(json!({"z": metas("6")})));
+    wait_for(|| syncs.lock().unwrap().len() == 3);
+
+    let syncs = syncs.lock().unwrap();
+    assert_eq!(syncs[0].keys().count(), 3);
+    assert!(syncs[1].contains("e") && !syncs[1].contains("a"));
+    assert!(syncs[2].contains("z"));
+}
+
+#[test]
+fn join_and_leave_callbacks_get_current_and_changed_metas() {
+    let (client, _listener, mut connection) = connect(&mut builder());
+
+    type Change = (String, Option<Vec<String>>, Vec<String>);
+    let joins: Arc<Mutex<Vec<Change>>> = Default::default();
+    let leaves: Arc<Mutex<Vec<Change>>> = Default::default();
+
+    let (join_log, leave_log) = (joins.clone(), leaves.clone());
+    let channel = RealtimeChannelBuilder::new("room")
+        .on_presence_join(move |key, current, joined| {
+            join_log
+                .lock()
+                .unwrap()
+                .push((key.into(), current.map(refs), refs(joined)))
+        })
+        .on_presence_leave(move |key, current, left| {
+            leave_log
+                .lock()
+                .unwrap()
+                .push((key.into(), Some(refs(current)), refs(left)))
+        })
+        .build_sync(&client)
+        .unwrap();
+
+    channel.subscribe();
+    accept_join(&mut connection);
+
+    // New key, then a second meta on the same key
+    send(&connection, presence_state(json!({"a": metas("1")})));
+    send(
+        &connection,
+        presence_diff(json!({"a": metas("2")}), json!({})),
+    );
+    wait_for(|| joins.lock().unwrap().len() == 2);
+
+    assert_eq!(
+        *joins.lock().unwrap(),
+        vec![
+            ("a".to_string(), None, vec!["1".to_string()]),
+            (
+                "a".to_string(),
+                Some(vec!["1".to_string()]),
+                vec!["2".to_string()]
+            ),
+        ]
+    );
+
+    // One meta leaves, then the last
+    send(
+        &connection,
+        presence_diff(json!({}), json!({"a": metas("1")})),
+    );
+    send(
+        &connection,
+        presence_diff(json!({}), json!({"a": metas("2")})),
+    );
+    wait_for(|| leaves.lock().unwrap().len() == 2);
+
+    assert_eq!(
+        *leaves.lock().unwrap(),
+        vec![
+            (
+                "a".to_string(),
+                Some(vec!["2".to_string()]),
+                vec!["1".to_string()]
+            ),
+            ("a".to_string(), Some(vec![]), vec!["2".to_string()]),
+        ]
+    );
+    assert!(!channel.get_presence_state().contains("a"));
+}
+
+#[test]
+#[allow(deprecated)]
+fn deprecated_presence_callbacks_still_fire() {
+    let (client, _listener, mut connection) = connect(&mut builder());
+
+    type Call = (PresenceEvent, String, PresenceState, PresenceState);
+    let calls: Arc<Mutex<Vec<Call>>> = Default::default();
+
+    let mut builder = RealtimeChannelBuilder::new("room");
+    for event in [PresenceEvent::Join, PresenceEvent::Sync] {
+        let log = calls.clone();
+        builder.on_presence(event.clone(), move |key, old, new| {
+            log.lock().unwrap().push((event.clone(), key, old, new))
+        });
+    }
+    let channel = builder.build_sync(&client).unwrap();
+
+    channel.subscribe();
+    accept_join(&mut connection);
+
+    send(
+        &connection,
+        presence_state(json!({"a": metas("1"), "b": metas("2")})),
+    );
+    wait_for(|| calls.lock().unwrap().len() == 3);
+
+    let calls = calls.lock().unwrap();
+
+    let mut joined: Vec<&str> = calls[..2].iter().map(|call| call.1.as_str()).collect();
+    joined.sort();
+    assert_eq!(joined, vec!["a", "b"]);
+
+    let (event, key, old, new) = &calls[0];
+    assert_eq!(*event, PresenceEvent::Join);
+    assert_eq!(old.keys().count(), 1);
+    assert_eq!(refs(old.0.get(key).unwrap()), Vec::<String>::new());
+    assert_eq!(new.keys().collect::<Vec<_>>(), vec![key.as_str()]);
+
+    let (event, key, old, new) = &calls[2];
+    assert_eq!(*event, PresenceEvent::Sync);
+    assert!(key.is_empty());
+    assert_eq!(old.keys().count(), 0);
+    assert_eq!(new.keys().count(), 2);
+}
+
+#[test]
+#[allow(deprecated)]
+fn deprecated_track_and_untrack_callbacks_fail_the_build() {
+    let (client, _listener, _connection) = connect(&mut builder());
+
+    for event in [PresenceEvent::Track, PresenceEvent::Untrack] {
+        let result = RealtimeChannelBuilder::new("room")
+            .on_presence(event.clone(), |_, _, _| {})
+            .build_sync(&client);
+
+        match result {
+            Err(ChannelBuildError::UnsupportedPresenceEvent(rejected)) => {
+                assert_eq!(rejected, event)
+            }
+            Err(e) => panic!("expected UnsupportedPresenceEvent, got {:?}", e),
+            Ok(_) => panic!("expected UnsupportedPresenceEvent, channel was built"),
+        }
+    }
+
+    let result = RealtimeChannelBuilder::new("room")
+        .on_presence_async(PresenceEvent::Untrack, |_, _, _| async {})
+        .build_sync(&client);
+    assert!(matches!(
+        result,
+        Err(ChannelBuildError::UnsupportedPresenceEvent(
+            PresenceEvent::Untrack
+        ))
+    ));
+}
@@ -0,0 +1,224 @@
+//! Reconnect scenarios scripted with `realtime_rs::testing::ChaosTransport`. Run with
+//! `--features testing`.
+
+mod common;
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    thread::sleep,
+    time::Duration,
+};
+
+use realtime_rs::{
+    message::payload::PresenceConfig,
+    realtime_channel::{ChannelManagerSync, ChannelState, RealtimeChannelBuilder},
+    realtime_client::ClientManagerSync,
+    testing::{ChaosTransport, Fault, MockServer},
+    transport::{Direction, TungsteniteTransport},
+};
+use serde_json::{json, Value};
+
+use common::{mock_builder, mock_client, wait_for};
+
+fn client(server: &MockServer, chaos: &ChaosTransport) -> ClientManagerSync {
+    mock_builder(server)
+        .set_transport(chaos.clone())
+        .connect()
+        .unwrap()
+        .to_sync()
+}
+
+fn joined(channel: &ChannelManagerSync) -> bool {
+    channel.get_state().unwrap() == ChannelState::Joined
+}
+
+/// Channel on "room" logging the `n` of every "ping" broadcast
+fn pinged_channel(client: &ClientManagerSync) -> (ChannelManagerSync, Arc<Mutex<Vec<Value>>>) {
+    let pings = Arc::new(Mutex::new(vec![]));
+
+    let log = pings.clone();
+    let channel = RealtimeChannelBuilder::new("room")
+        .on_broadcast("ping", move |payload| {
+            log.lock().unwrap().push(payload["n"].clone())
+        })
+        .build_sync(client)
+        .unwrap();
+
+    (channel, pings)
+}
+
+fn ping(n: i64) -> Value {
+    json!({ "n": n })
+}
+
+#[test]
+fn refused_handshakes_are_retried() {
+    let server = MockServer::start();
+    let chaos = ChaosTransport::new(TungsteniteTransport);
+    chaos.refuse_handshakes(3);
+
+    let client = client(&server, &chaos);
+
+    let channel = RealtimeChannelBuilder::new("room")
+        .build_sync(&client)
+        .unwrap();
+
+    assert_eq!(channel.subscribe_blocking().unwrap(), Ok(()));
+    assert_eq!(chaos.handshake_count(), 4);
+    assert_eq!(server.connection_count(), 1);
+}
+
+#[test]
+fn reconnecting_stops_after_max_attempts() {
+    let server = MockServer::start();
+    let chaos = ChaosTransport::new(TungsteniteTransport);
+
+    let _client = mock_builder(&server)
+        .set_transport(chaos.clone())
+        .set_reconnect_max_attempts(3)
+        .connect()
+        .unwrap()
+        .to_sync();
+
+    wait_for(|| server.connection_count() == 1);
+
+    chaos.refuse_handshakes(usize::MAX);
+    chaos.drop_connections();
+
+    wait_for(|| chaos.handshake_count() == 4);
+    sleep(Duration::from_millis(200));
+    assert_eq!(chaos.handshake_count(), 4);
+    assert_eq!(server.connection_count(), 0);
+}
+
+#[test]
+fn channel_rejoins_after_connection_drops() {
+    let server = MockServer::start();
+    let chaos = ChaosTransport::new(TungsteniteTransport);
+    let client = client(&server, &chaos);
+
+    let (channel, pings) = pinged_channel(&client);
+    channel.subscribe_blocking().unwrap().unwrap();
+
+    chaos.refuse_handshakes(2);
+    chaos.drop_connections();
+
+    wait_for(|| server.connection_count() == 1 && chaos.handshake_count() == 4);
+    wait_for(|| server.subscription_count("room") == 1);
+    wait_for(|| joined(&channel));
+
+    server.broadcast("room", "ping", ping(1));
+    wait_for(|| pings.lock().unwrap().len() == 1);
+}
+
+#[test]
+fn join_lost_with_its_connection_is_retried() {
+    let server = MockServer::start();
+    let chaos = ChaosTransport::new(TungsteniteTransport);
+
+    // The server accepts the join, but the reply never arrives
+    chaos.inject(Direction::Received, "phx_reply", Fault::Drop);
+
+    let client = client(&server, &chaos);
+    let (channel, _pings) = pinged_channel(&client);
+    channel.subscribe();
+
+    wait_for(|| joined(&channel));
+    assert_eq!(chaos.pending_faults(), 0);
+    assert_eq!(chaos.handshake_count(), 2);
+    wait_for(|| server.subscription_count("room") == 1);
+}
+
+#[test]
+fn delayed_and_reordered_frames_arrive_out_of_order() {
+    let server = MockServer::start();
+    let chaos = ChaosTransport::new(TungsteniteTransport);
+    let client = client(&server, &chaos);
+
+    let (channel, pings) = pinged_channel(&client);
+    channel.subscribe_blocking().unwrap().unwrap();
+
+    chaos.inject(Direction::Received, "broadcast", Fault::Reorder);
+    server.broadcast("room", "ping", ping(1));
+    server.broadcast("room", "ping", ping(2));
+
+    wait_for(|| pings.lock().unwrap().len() == 2);
+
+    chaos.inject(
+        Direction::Received,
+        "broadcast",
+        Fault::Delay(Duration::from_millis(200)),
+    );
+    server.broadcast("room", "ping", ping(3));
+    server.broadcast("room", "ping", ping(4));
+
+    wait_for(|| pings.lock().unwrap().len() == 4);
+    assert_eq!(
+        *pings.lock().unwrap(),
+        vec![json!(2), json!(1), json!(4), json!(3)]
+    );
+}
+
+#[test]
+fn truncated_frames_are_skipped() {
+    let server = MockServer::start();
+    let chaos = ChaosTransport::new(TungsteniteTransport);
+    let client = client(&server, &chaos);
+
+    let (channel, pings) = pinged_channel(&client);
+    channel.subscribe_blocking().unwrap().unwrap();
+
+    chaos.inject(Direction::Received, "broadcast", Fault::Truncate(20));
+    server.broadcast("room", "ping", ping(1));
+    server.broadcast("room", "ping", ping(2));
+
+    wait_for(|| pings.lock().unwrap().len() == 1);
+    sleep(Duration::from_millis(100));
+    assert_eq!(*pings.lock().unwrap(), vec![json!(2)]);
+    assert!(joined(&channel));
+}
+
+#[test]
+fn presence_recovers_after_connection_drops() {
+    let server = MockServer::start();
+    let chaos = ChaosTransport::new(TungsteniteTransport);
+
+    let client = client(&server, &chaos);
+    let observer = mock_client(&server);
+
+    let leaves = Arc::new(Mutex::new(vec![]));
+
+    let channel = RealtimeChannelBuilder::new("room")
+        .set_presence_config(PresenceConfig {
+            key: Some("user".into()),
+            ..Default::default()
+        })
+        .build_sync(&client)
+        .unwrap();
+
+    let log = leaves.clone();
+    let observer_channel = RealtimeChannelBuilder::new("room")
+        .on_presence_leave(move |key, _current, _left| log.lock().unwrap().push(key.to_string()))
+        .build_sync(&observer)
+        .unwrap();
+
+    channel.subscribe_blocking().unwrap().unwrap();
+    observer_channel.subscribe_blocking().unwrap().unwrap();
+
+    channel
+        .track(HashMap::from([("status".to_string(), json!("online"))]))
+        .unwrap();
+
+    wait_for(|| observer_channel.get_presence_state().contains("user"));
+
+    // Hold the reconnect back long enough for the observer to see the user leave
+    chaos.refuse_handshakes(5);
+    chaos.drop_connections();
+
+    wait_for(|| *leaves.lock().unwrap() == vec!["user".to_string()]);
+
+    wait_for(|| joined(&channel) && chaos.handshake_count() == 7);
+    wait_for(|| observer_channel.get_presence_state().contains("user"));
+    wait_for(|| channel.get_presence_state().contains("user"));
+}
@@ -0,0 +1,108 @@
+//! Helpers shared by the integration tests. Each test file uses its own subset.
+#![allow(dead_code)]
+
+use std::{
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use realtime_rs::{
+    realtime_client::{ClientManagerSync, RealtimeClientBuilder},
+    transport::{MemoryConnection, MemoryListener, MemoryTransport},
+};
+use serde_json::{json, Value};
+use tokio_tungstenite::tungstenite::Message;
+
+#[cfg(feature = "testing")]
+use realtime_rs::{realtime_client::ReconnectFn, testing::MockServer};
+
+/// Polls `condition` until it holds, panicking after five seconds
+pub fn wait_for(mut condition: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+
+    while !condition() {
+        assert!(Instant::now() < deadline, "timed out");
+        sleep(Duration::from_millis(10));
+    }
+}
+
+/// Client builder for an in-memory server, heartbeats effectively off
+pub fn builder() -> RealtimeClientBuilder {
+    let mut builder = RealtimeClientBuilder::new("http://localhost/realtime/v1", "anon_key");
+    builder.set_heartbeat_interval(Duration::from_secs(3600));
+    builder
+}
+
+/// Connects `builder` over a new [MemoryTransport]. Returns the client, the listener for later
+/// connections and the server's end of the first connection.
+pub fn connect(
+    builder: &mut RealtimeClientBuilder,
+) -> (ClientManagerSync, MemoryListener, MemoryConnection) {
+    let (transport, mut listener) = MemoryTransport::new();
+
+    let client = builder
+        .set_transport(transport)
+        .connect()
+        .unwrap()
+        .to_sync();
+
+    let connection = listener.accept_blocking().unwrap();
+
+    (client, listener, connection)
+}
+
+/// Client of `server` that reconnects quickly
+#[cfg(feature = "testing")]
+pub fn mock_builder(server: &MockServer) -> RealtimeClientBuilder {
+    let mut builder = RealtimeClientBuilder::new(server.endpoint(), "anon_key");
+    builder.set_reconnect_interval(ReconnectFn::new(|_| Duration::from_millis(20)));
+    builder
+}
+
+#[cfg(feature = "testing")]
+pub fn mock_client(server: &MockServer) -> ClientManagerSync {
+    mock_builder(server).connect().unwrap().to_sync()
+}
+
+/// Next frame the client wrote, as JSON
+pub fn recv(connection: &mut MemoryConnection) -> Value {
+    let message = connection.recv_blocking().unwrap();
+    serde_json::from_str(message.to_text().unwrap()).unwrap()
+}
+
+pub fn send(connection: &MemoryConnection, frame: Value) {
+    connection.send(Message::Text(frame.to_string())).unwrap();
+}
+
+/// Reply to `message` with `status` and `response`
+pub fn reply(message: &Value, status: &str, response: Value) -> Value {
+    json!({
+        "topic": message["topic"],
+        "event": "phx_reply",
+        "payload": {"status": status, "response": response},
+        "ref": message["ref"],
+    })
+}
+
+/// Receives the next frame, a join, and accepts it. Returns the join.
+pub fn accept_join(connection: &mut MemoryConnection) -> Value {
+    let join = recv(connection);
+    assert_eq!(join["event"], "phx_join");
+
+    send(
+        connection,
+        reply(&join, "ok", json!({"postgres_changes": []})),
+    );
+
+    join
+}
+
+/// Server broadcast of `event` on `topic`
+pub fn broadcast(topic: &str, event: &str, payload: Value) -> Value {
+    json!({
+        "topic": format!("realtime:{}", topic),
+        "event": "broadcast",
+        "payload": {"type": "broadcast", "event": event, "payload": payload},
+        "ref": null,
+    })
+}
@@ -0,0 +1,205 @@
+//! Compressed broadcasts, driven through `MemoryTransport`. Run with `--features compression`.
+
+mod common;
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use realtime_rs::{
+    compression::{BroadcastCompression, CompressionError, COMPRESSION_FIELD, DATA_FIELD},
+    message::payload::BroadcastPayload,
+    realtime_channel::{ChannelManagerSync, RealtimeChannelBuilder},
+    realtime_client::ClientManagerSync,
+    transport::MemoryConnection,
+};
+use serde_json::{json, Value};
+
+use common::{accept_join, broadcast, builder, connect, recv, send, wait_for};
+
+type Received = Arc<Mutex<Vec<HashMap<String, Value>>>>;
+type Errors = Arc<Mutex<Vec<CompressionError>>>;
+
+struct Room {
+    _client: ClientManagerSync,
+    connection: MemoryConnection,
+    channel: ChannelManagerSync,
+    received: Received,
+    errors: Errors,
+}
+
+/// Joined channel on "room" compressing payloads of at least `min_size` bytes
+fn room(min_size: usize) -> Room {
+    room_with(BroadcastCompression::new().min_size(min_size))
+}
+
+/// Joined channel on "room" using `compression`
+fn room_with(compression: BroadcastCompression) -> Room {
+    let (client, _listener, mut connection) = connect(&mut builder());
+
+    let received: Received = Default::default();
+    let errors: Errors = Default::default();
+
+    let log = errors.clone();
+    let compression = compression.on_error(move |error, _| log.lock().unwrap().push(error));
+
+    let log = received.clone();
+    let channel = RealtimeChannelBuilder::new("room")
+        .set_compression(compression)
+        .on_broadcast("document", move |payload| {
+            log.lock().unwrap().push(payload.clone())
+        })
+        .build_sync(&client)
+        .unwrap();
+
+    channel.subscribe();
+
+    accept_join(&mut connection);
+
+    Room {
+        _client: client,
+        connection,
+        channel,
+        received,
+        errors,
+    }
+}
+
+/// Sends `text` from `room` and returns the frame the server saw
+fn sent_frame(room: &mut Room, text: &str) -> Value {
+    room.channel
+        .broadcast(BroadcastPayload::new(
+            "document",
+            HashMap::from([("text".to_string(), json!(text))]),
+        ))
+        .unwrap();
+
+    recv(&mut room.connection)
+}
+
+#[test]
+fn large_payloads_leave_compressed_and_arrive_inflated() {
+    let mut sender = room(1024);
+    let receiver = room(1024);
+
+    let document = "all work and no play ".repeat(500);
+    let frame = sent_frame(&mut sender, &document);
+
+    let payload = &frame["payload"]["payload"];
+    assert_eq!(payload[COMPRESSION_FIELD], "deflate");
+    assert!(payload[DATA_FIELD].as_str().unwrap().len() < document.len() / 10);
+    assert!(!frame.to_string().contains("all work"));
+
+    send(&receiver.connection, frame);
+
+    wait_for(|| receiver.received.lock().unwrap().len() == 1);
+    assert_eq!(
+        receiver.received.lock().unwrap()[0]["text"],
+        json!(document)
+    );
+    assert!(receiver.errors.lock().unwrap().is_empty());
+}
+
+#[test]
+fn small_and_uncompressed_payloads_pass_through() {
+    let mut sender = room(1024);
+    let receiver = room(1024);
+
+    let frame = sent_frame(&mut sender, "short");
+    assert_eq!(frame["payload"]["payload"], json!({"text": "short"}));
+
+    send(&receiver.connection, frame);
+
+    // From a peer without compression
+    send(
+        &receiver.connection,
+        broadcast("room", "document", json!({"text": "x".repeat(4096)})),
+    );
+
+    wait_for(|| receiver.received.lock().unwrap().len() == 2);
+
+    let received = receiver.received.lock().unwrap();
+    assert_eq!(received[0]["text"], json!("short"));
+    assert_eq!(received[1]["text"], json!("x".repeat(4096)));
+    assert!(receiver.errors.lock().unwrap().is_empty());
+}
+
+#[test]
+fn uninflatable_broadcasts_go_to_the_error_callback() {
+    let receiver = room(1024);
+
+    send(
+        &receiver.connection,
+        broadcast(
+            "room",
+            "document",
+            json!({COMPRESSION_FIELD: "zstd", DATA_FIELD: "AAAA"}),
+        ),
+    );
+    send(
+        &receiver.connection,
+        broadcast(
+            "room",
+            "document",
+            json!({COMPRESSION_FIELD: "deflate", DATA_FIELD: "!!"}),
+        ),
+    );
+    send(
+        &receiver.connection,
+        broadcast(
+            "room",
+            "document",
+            json!({COMPRESSION_FIELD: "deflate", DATA_FIELD: "AAAA"}),
+        ),
+    );
+
+    wait_for(|| receiver.errors.lock().unwrap().len() == 3);
+
+    let errors = receiver.errors.lock().unwrap();
+    assert_eq!(errors[0], CompressionError::Algorithm("zstd".into()));
+    assert_eq!(errors[1], CompressionError::Malformed);
+    assert!(matches!(
+        errors[2],
+        CompressionError::Inflate(_) | CompressionError::Payload(_)
+    ));
+
+    assert!(receiver.received.lock().unwrap().is_empty());
+}
+
+#[test]
+fn payloads_compress_from_the_minimum_size() {
+    let mut sender = room(100);
+
+    // `{"text":""}` is 11 bytes of JSON
+    let below = sent_frame(&mut sender, &"x".repeat(88));
+    let at = sent_frame(&mut sender, &"x".repeat(89));
+
+    assert_eq!(below["payload"]["payload"]["text"], json!("x".repeat(88)));
+    assert_eq!(at["payload"]["payload"][COMPRESSION_FIELD], "deflate");
+}
+
+#[test]
+fn payloads_inflating_past_the_maximum_are_dropped() {
+    let mut sender = room(0);
+    let receiver = room_with(BroadcastCompression::new().max_size(1000));
+
+    let small = sent_frame(&mut sender, &"x".repeat(989));
+    let large = sent_frame(&mut sender, &"x".repeat(100_000));
+
+    // Compresses far below the limit, inflates far above it
+    assert!(large.to_string().len() < 1000);
+
+    send(&receiver.connection, large);
+    send(&receiver.connection, small);
+
+    wait_for(|| receiver.received.lock().unwrap().len() == 1);
+    assert_eq!(
+        receiver.received.lock().unwrap()[0]["text"],
+        json!("x".repeat(989))
+    );
+    assert_eq!(
+        *receiver.errors.lock().unwrap(),
+        vec![CompressionError::TooLarge]
+    );
+}
@@ -0,0 +1,44 @@
+//! `RealtimeClientBuilder::connect` validation. Only failing configs are built here, as a valid
+//! one starts a client.
+
+use realtime_rs::realtime_client::{ConfigError, RealtimeClientBuilder};
+use tokio_tungstenite::tungstenite::http::{HeaderMap, HeaderValue};
+
+const ENDPOINT: &str = "http://127.0.0.1:54321/realtime/v1";
+
+#[test]
+fn rejects_access_token_with_newline() {
+    let result = RealtimeClientBuilder::new(ENDPOINT, "anon_key")
+        .set_access_token("jwt\n")
+        .connect();
+
+    assert_eq!(result.err(), Some(ConfigError::AccessToken));
+}
+
+#[test]
+fn rejects_anon_key_with_newline() {
+    let result = RealtimeClientBuilder::new(ENDPOINT, "anon_key\n")
+        .set_access_token("jwt")
+        .connect();
+
+    assert_eq!(result.err(), Some(ConfigError::AnonKey));
+}
+
+#[test]
+fn rejects_non_ascii_header() {
+    let mut headers = HeaderMap::new();
+    headers.insert("x-custom", HeaderValue::from_bytes(b"caf\xe9").unwrap());
+
+    let result = RealtimeClientBuilder::new(ENDPOINT, "anon_key")
+        .add_headers(headers)
+        .connect();
+
+    assert_eq!(result.err(), Some(ConfigError::Header("x-custom".into())));
+}
+
+#[test]
+fn rejects_endpoint_without_host() {
+    let result = RealtimeClientBuilder::new("/realtime/v1", "anon_key").connect();
+
+    assert!(matches!(result.err(), Some(ConfigError::Endpoint(_))));
+}
@@ -0,0 +1,233 @@
+//! Round-trips the frames in `tests/corpus` through `RealtimeMessage`
+//!
+//! The corpus is hand-written from the Realtime and Phoenix sources, not captured from a server.
+//! Every frame must decode to the payload variant its event calls for and encode back to the
+//! same JSON. New frames need an entry in `EXPECTED`.
+
+use std::{collections::HashMap, fs, path::PathBuf};
+
+use realtime_rs::message::{
+    payload::Payload,
+    presence::{PresenceDiff, PresenceState},
+    MessageEvent, RealtimeMessage,
+};
+use serde_json::{json, Value};
+
+/// Corpus file stem and the payload variant it decodes to
+const EXPECTED: &[(&str, &str)] = &[
+    ("access_token", "AccessToken"),
+    ("broadcast", "Broadcast"),
+    ("broadcast_empty", "Broadcast"),
+    ("heartbeat", "Empty"),
+    ("phx_close", "Empty"),
+    ("phx_error", "Empty"),
+    ("phx_join", "Join"),
+    ("phx_join_private", "Join"),
+    ("phx_leave", "Empty"),
+    ("phx_reply_heartbeat", "Reply"),
+    ("phx_reply_join", "Response"),
+    ("phx_reply_join_empty", "Response"),
+    ("phx_reply_join_error", "Reply"),
+    ("postgres_changes_delete", "PostgresChanges"),
+    ("postgres_changes_error", "PostgresChanges"),
+    ("postgres_changes_insert", "PostgresChanges"),
+    ("postgres_changes_update", "PostgresChanges"),
+    ("presence_diff", "PresenceDiff"),
+    ("presence_diff_empty", "PresenceDiff"),
+    ("presence_state", "PresenceState"),
+    ("presence_state_empty", "PresenceState"),
+    ("presence_track", "PresenceTrack"),
+    ("presence_untrack", "PresenceTrack"),
+    ("system_error", "System"),
+    ("system_subscribed", "System"),
+];
+
+/// Every event either side sends. `track` and `untrack` are left out, as presence pushes go out
+/// as `presence` events.
+const ALL_EVENTS: &[MessageEvent] = &[
+    MessageEvent::PhxClose,
+    MessageEvent::PhxError,
+    MessageEvent::PhxJoin,
+    MessageEvent::PhxReply,
+    MessageEvent::PhxLeave,
+    MessageEvent::AccessToken,
+    MessageEvent::Presence,
+    MessageEvent::System,
+    MessageEvent::Heartbeat,
+    MessageEvent::PostgresChanges,
+    MessageEvent::PresenceState,
+    MessageEvent::PresenceDiff,
+    MessageEvent::Broadcast,
+];
+
+fn variant(payload: &Payload) -> &'static str {
+    match payload {
+        Payload::Join(_) => "Join",
+        Payload::Response(_) => "Response",
+        Payload::System(_) => "System",
+        Payload::AccessToken(_) => "AccessToken",
+        Payload::PostgresChanges(_) => "PostgresChanges",
+        Payload::Broadcast(_) => "Broadcast",
+        Payload::PresenceState(_) => "PresenceState",
+        Payload::PresenceDiff(_) => "PresenceDiff",
+        Payload::Reply(_) => "Reply",
+        Payload::PresenceTrack(_) => "PresenceTrack",
+        Payload::Empty {} => "Empty",
+        Payload::Other(_) => "Other",
+    }
+}
+
+/// Every corpus frame, by file stem
+fn corpus() -> Vec<(String, String)> {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+
+    let mut frames: Vec<(String, String)> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .map(|path| {
+            let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+            (name, fs::read_to_string(path).unwrap())
+        })
+        .collect();
+
+    frames.sort();
+    frames
+}
+
+fn decode(name: &str, text: &str) -> RealtimeMessage {
+    serde_json::from_str(text).unwrap_or_else(|e| panic!("{} failed to decode: {}", name, e))
+}
+
+#[test]
+fn corpus_matches_expectations() {
+    let names: Vec<String> = corpus().into_iter().map(|(name, _)| name).collect();
+    let expected: Vec<String> = EXPECTED.iter().map(|(name, _)| name.to_string()).collect();
+
+    assert_eq!(names, expected);
+}
+
+#[test]
+fn frames_decode_to_the_payload_their_event_calls_for() {
+    let expected: HashMap<&str, &str> = EXPECTED.iter().copied().collect();
+
+    for (name, text) in corpus() {
+        let message = decode(&name, &text);
+
+        assert_eq!(
+            variant(&message.payload),
+            expected[name.as_str()],
+            "{} decoded to the wrong payload",
+            name
+        );
+    }
+}
+
+#[test]
+fn frames_encode_back_without_loss() {
+    for (name, text) in corpus() {
+        let message = decode(&name, &text);
+
+        let original: Value = serde_json::from_str(&text).unwrap();
+        let encoded = serde_json::to_value(&message).unwrap();
+
+        assert_eq!(encoded, original, "{} changed on re-encoding", name);
+    }
+}
+
+#[test]
+fn corpus_covers_every_event() {
+    let events: Vec<MessageEvent> = corpus()
+        .iter()
+        .map(|(name, text)| decode(name, text).event)
+        .collect();
+
+    for event in ALL_EVENTS {
+        assert!(events.contains(event), "no corpus frame for {:?}", event);
+    }
+}
+
+#[test]
+fn payload_not_fitting_its_event_is_an_error() {
+    // Valid presence_state payload, sent as a broadcast
+    let frame = json!({
+        "topic": "realtime:room",
+        "event": "broadcast",
+        "payload": {"user_1": {"metas": [{"phx_ref": "F7c9dQ8kB1xU3AAk"}]}},
+        "ref": null,
+    });
+
+    assert!(serde_json::from_value::<RealtimeMessage>(frame).is_err());
+}
+
+#[test]
+fn empty_payloads_follow_their_event() {
+    // Untagged, `{}` reads as an empty presence state whatever the event
+    for (event, expected) in [("phx_close", "Empty"), ("presence_state", "PresenceState")] {
+        let message: RealtimeMessage = serde_json::from_value(json!({
+            "topic": "realtime:room",
+            "event": event,
+            "payload": {},
+            "ref": null,
+        }))
+        .unwrap();
+
+        assert_eq!(variant(&message.payload), expected);
+    }
+}
+
+#[test]
+fn control_payloads_are_kept() {
+    for (event, payload) in [
+        ("phx_close", json!({"reason": "shutdown"})),
+        ("phx_error", json!({"reason": "crashed"})),
+        ("phx_leave", json!({"reason": "left"})),
+        ("heartbeat", json!({"at": 1700000000})),
+    ] {
+        let frame = json!({
+            "topic": "realtime:room",
+            "event": event,
+            "payload": payload,
+            "ref": null,
+        });
+
+        let message: RealtimeMessage = serde_json::from_value(frame.clone()).unwrap();
+        assert_eq!(variant(&message.payload), "Other", "{}", event);
+
+        let encoded = serde_json::to_value(&message).unwrap();
+        assert_eq!(encoded, frame, "{} changed on re-encoding", event);
+    }
+}
+
+#[test]
+fn presence_frames_convert_to_presence_types() {
+    let corpus: HashMap<String, String> = corpus().into_iter().collect();
+
+    let Payload::PresenceState(raw) = decode("presence_state", &corpus["presence_state"]).payload
+    else {
+        panic!("presence_state decoded to the wrong payload");
+    };
+
+    let state: PresenceState = raw.into();
+    assert_eq!(state.0["user_1"]["F7c9dQ8kB1xU3AAk"]["status"], "online");
+    assert_eq!(state.0["user_2"].len(), 2);
+    assert_eq!(
+        state.0["user_2"]["F7c9dS7nJ9qW6ACk"]["phx_ref_prev"],
+        "F7c9dR2mH4pV5ABE"
+    );
+
+    let snapshot = serde_json::to_string(&state).unwrap();
+    assert_eq!(
+        serde_json::from_str::<PresenceState>(&snapshot).unwrap(),
+        state
+    );
+
+    let Payload::PresenceDiff(raw) = decode("presence_diff", &corpus["presence_diff"]).payload
+    else {
+        panic!("presence_diff decoded to the wrong payload");
+    };
+
+    let diff: PresenceDiff = raw.into();
+    assert!(diff.joins.0.contains_key("user_3"));
+    assert!(diff.leaves.0["user_2"].contains_key("F7c9dR2mH4pV5ABE"));
+}
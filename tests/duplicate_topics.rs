@@ -0,0 +1,111 @@
+//! Building channels on a topic the client already holds, under each `DuplicateTopicPolicy`
+
+mod common;
+
+use std::thread;
+
+use realtime_rs::{
+    realtime_channel::{ChannelBuildError, ChannelState, RealtimeChannelBuilder},
+    realtime_client::DuplicateTopicPolicy,
+};
+use serde_json::json;
+
+use common::{accept_join, builder, connect, recv, reply, send, wait_for};
+
+#[test]
+fn allow_builds_another_channel() {
+    let (client, _listener, _connection) = connect(&mut builder());
+    assert_eq!(client.duplicate_topic_policy(), DuplicateTopicPolicy::Allow);
+
+    let first = RealtimeChannelBuilder::new("room")
+        .build_sync(&client)
+        .unwrap();
+    let second = RealtimeChannelBuilder::new("room")
+        .build_sync(&client)
+        .unwrap();
+
+    assert!(first.to_async() != second.to_async());
+    assert_eq!(client.channels().unwrap().len(), 2);
+}
+
+#[test]
+fn reuse_returns_the_existing_channel() {
+    let (client, _listener, _connection) =
+        connect(builder().set_duplicate_topic_policy(DuplicateTopicPolicy::Reuse));
+
+    let first = RealtimeChannelBuilder::new("room")
+        .build_sync(&client)
+        .unwrap();
+    let second = RealtimeChannelBuilder::new("room")
+        .build_sync(&client)
+        .unwrap();
+    let other = RealtimeChannelBuilder::new("lobby")
+        .build_sync(&client)
+        .unwrap();
+
+    assert!(first.clone().to_async() == second.to_async());
+    assert!(first.to_async() != other.to_async());
+    assert_eq!(client.channels().unwrap().len(), 2);
+}
+
+#[test]
+fn reject_fails_the_build() {
+    let (client, _listener, _connection) =
+        connect(builder().set_duplicate_topic_policy(DuplicateTopicPolicy::Reject));
+
+    RealtimeChannelBuilder::new("room")
+        .build_sync(&client)
+        .unwrap();
+
+    match RealtimeChannelBuilder::new("room").build_sync(&client) {
+        Err(ChannelBuildError::DuplicateTopic(topic)) => assert_eq!(topic, "realtime:room"),
+        Err(e) => panic!("expected DuplicateTopic, got {:?}", e),
+        Ok(_) => panic!("expected DuplicateTopic, channel was built"),
+    }
+}
+
+#[test]
+fn reject_holds_for_concurrent_builds() {
+    let (client, _listener, _connection) =
+        connect(builder().set_duplicate_topic_policy(DuplicateTopicPolicy::Reject));
+
+    let builds: Vec<_> = (0..8)
+        .map(|_| {
+            let client = client.clone();
+            thread::spawn(move || {
+                RealtimeChannelBuilder::new("room")
+                    .build_sync(&client)
+                    .is_ok()
+            })
+        })
+        .collect();
+
+    let built = builds
+        .into_iter()
+        .map(|build| build.join().unwrap())
+        .filter(|built| *built)
+        .count();
+
+    assert_eq!(built, 1);
+    wait_for(|| client.channels().unwrap().len() == 1);
+}
+
+#[test]
+fn left_channels_no_longer_count_as_duplicates() {
+    let (client, _listener, mut connection) =
+        connect(builder().set_duplicate_topic_policy(DuplicateTopicPolicy::Reject));
+
+    let channel = RealtimeChannelBuilder::new("room")
+        .build_sync(&client)
+        .unwrap();
+    channel.subscribe();
+    accept_join(&mut connection);
+    wait_for(|| channel.get_state().unwrap() == ChannelState::Joined);
+
+    let mut rebuild = channel.unsubscribe().unwrap().unwrap();
+    let leave = recv(&mut connection);
+    send(&connection, reply(&leave, "ok", json!({})));
+
+    wait_for(|| client.channels().unwrap().is_empty());
+    assert!(rebuild.build_sync(&client).is_ok());
+}
@@ -0,0 +1,199 @@
+//! Encrypted broadcasts, driven through `MemoryTransport`. Run with `--features encryption`.
+
+mod common;
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use realtime_rs::{
+    encryption::{BroadcastEncryption, EncryptionError, CIPHERTEXT_FIELD},
+    message::payload::BroadcastPayload,
+    realtime_channel::{ChannelManagerSync, RealtimeChannelBuilder},
+    realtime_client::ClientManagerSync,
+    transport::MemoryConnection,
+};
+use serde_json::{json, Value};
+
+use common::{accept_join, builder, connect, recv, send, wait_for};
+
+const KEY: [u8; 32] = [7; 32];
+
+type Received = Arc<Mutex<Vec<HashMap<String, Value>>>>;
+type Errors = Arc<Mutex<Vec<EncryptionError>>>;
+
+struct Room {
+    _client: ClientManagerSync,
+    connection: MemoryConnection,
+    channel: ChannelManagerSync,
+    received: Received,
+    errors: Errors,
+}
+
+/// Joined channel on "room" encrypted with `key`
+fn room(key: [u8; 32]) -> Room {
+    let (client, _listener, mut connection) = connect(&mut builder());
+
+    let received: Received = Default::default();
+    let errors: Errors = Default::default();
+
+    let log = errors.clone();
+    let encryption =
+        BroadcastEncryption::new(key).on_error(move |error, _| log.lock().unwrap().push(error));
+
+    let log = received.clone();
+    let channel = RealtimeChannelBuilder::new("room")
+        .set_encryption(encryption)
+        .on_broadcast("message", move |payload| {
+            log.lock().unwrap().push(payload.clone())
+        })
+        .build_sync(&client)
+        .unwrap();
+
+    channel.subscribe();
+
+    accept_join(&mut connection);
+
+    Room {
+        _client: client,
+        connection,
+        channel,
+        received,
+        errors,
+    }
+}
+
+/// Sends `secret` from `room` and returns the frame the server saw
+fn sealed_frame(room: &mut Room, secret: &str) -> Value {
+    room.channel
+        .broadcast(BroadcastPayload::new(
+            "message",
+            HashMap::from([("text".to_string(), json!(secret))]),
+        ))
+        .unwrap();
+
+    recv(&mut room.connection)
+}
+
+#[test]
+fn payloads_leave_encrypted_and_arrive_decrypted() {
+    let mut sender = room(KEY);
+    let receiver = room(KEY);
+
+    let frame = sealed_frame(&mut sender, "meet at noon");
+
+    assert_eq!(frame["event"], "broadcast");
+    assert_eq!(frame["payload"]["event"], "message");
+    assert!(frame["payload"]["payload"][CIPHERTEXT_FIELD].is_string());
+    assert_eq!(frame["payload"]["payload"].as_object().unwrap().len(), 1);
+    assert!(!frame.to_string().contains("meet at noon"));
+
+    send(&receiver.connection, frame);
+
+    wait_for(|| receiver.received.lock().unwrap().len() == 1);
+    assert_eq!(
+        receiver.received.lock().unwrap()[0]["text"],
+        json!("meet at noon")
+    );
+    assert!(receiver.errors.lock().unwrap().is_empty());
+}
+
+#[test]
+fn undecryptable_broadcasts_go_to_the_error_callback() {
+    let mut sender = room(KEY);
+    let receiver = room(KEY);
+    let stranger = room([9; 32]);
+
+    let frame = sealed_frame(&mut sender, "meet at noon");
+
+    // Wrong key
+    send(&stranger.connection, frame.clone());
+
+    // Flipped ciphertext byte
+    let mut tampered = frame.clone();
+    let sealed = tampered["payload"]["payload"][CIPHERTEXT_FIELD]
+        .as_str()
+        .unwrap();
+    let mut bytes = STANDARD.decode(sealed).unwrap();
+    *bytes.last_mut().unwrap() ^= 1;
+    tampered["payload"]["payload"][CIPHERTEXT_FIELD] = json!(STANDARD.encode(bytes));
+    send(&receiver.connection, tampered);
+
+    // Replayed under another event
+    let mut replayed = frame.clone();
+    replayed["payload"]["event"] = json!("other");
+    send(&receiver.connection, replayed);
+
+    // Sent in the clear, and garbage
+    send(
+        &receiver.connection,
+        json!({
+            "topic": "realtime:room",
+            "event": "broadcast",
+            "payload": {"type": "broadcast", "event": "message", "payload": {"text": "hi"}},
+            "ref": null,
+        }),
+    );
+    send(
+        &receiver.connection,
+        json!({
+            "topic": "realtime:room",
+            "event": "broadcast",
+            "payload": {"type": "broadcast", "event": "message", "payload": {CIPHERTEXT_FIELD: "!!"}},
+            "ref": null,
+        }),
+    );
+
+    wait_for(|| stranger.errors.lock().unwrap().len() == 1);
+    wait_for(|| receiver.errors.lock().unwrap().len() == 4);
+
+    assert_eq!(
+        *stranger.errors.lock().unwrap(),
+        vec![EncryptionError::Decryption]
+    );
+    assert_eq!(
+        *receiver.errors.lock().unwrap(),
+        vec![
+            EncryptionError::Decryption,
+            EncryptionError::Decryption,
+            EncryptionError::Unencrypted,
+            EncryptionError::Malformed,
+        ]
+    );
+
+    assert!(stranger.received.lock().unwrap().is_empty());
+    assert!(receiver.received.lock().unwrap().is_empty());
+}
+
+#[test]
+fn setting_encryption_again_replaces_it() {
+    let (client, _listener, mut connection) = connect(&mut builder());
+
+    let channel = RealtimeChannelBuilder::new("room")
+        .set_encryption(BroadcastEncryption::new([9; 32]))
+        .set_encryption(BroadcastEncryption::new(KEY))
+        .build_sync(&client)
+        .unwrap();
+    channel.subscribe();
+    accept_join(&mut connection);
+
+    channel
+        .broadcast(BroadcastPayload::new(
+            "message",
+            HashMap::from([("text".to_string(), json!("meet at noon"))]),
+        ))
+        .unwrap();
+    let frame = recv(&mut connection);
+
+    // Encrypted once, under the last key set
+    let receiver = room(KEY);
+    send(&receiver.connection, frame);
+
+    wait_for(|| receiver.received.lock().unwrap().len() == 1);
+    assert_eq!(
+        receiver.received.lock().unwrap()[0]["text"],
+        json!("meet at noon")
+    );
+}
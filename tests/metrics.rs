@@ -0,0 +1,200 @@
+//! Client metrics snapshots, driven through `MemoryTransport`
+
+mod common;
+
+use std::{
+    sync::{Arc, Mutex},
+    thread::{self, sleep},
+    time::{Duration, Instant},
+};
+
+use realtime_rs::{
+    message::{
+        payload::{BroadcastPayload, Payload},
+        MessageEvent, RealtimeMessage,
+    },
+    realtime_channel::RealtimeChannelBuilder,
+    realtime_client::ReconnectFn,
+};
+use serde_json::json;
+use tokio_tungstenite::tungstenite::Message;
+
+use common::{broadcast, builder, connect, recv, reply, send, wait_for};
+
+#[test]
+fn frames_joins_and_callbacks_are_counted() {
+    let (client, _listener, mut connection) = connect(&mut builder());
+
+    let calls = Arc::new(Mutex::new(0));
+    let log = calls.clone();
+    let channel = RealtimeChannelBuilder::new("room")
+        .on_broadcast("ping", move |_| {
+            sleep(Duration::from_millis(5));
+            *log.lock().unwrap() += 1;
+        })
+        .build_sync(&client)
+        .unwrap();
+
+    channel.subscribe();
+
+    let join = recv(&mut connection);
+    let reply = reply(&join, "ok", json!({"postgres_changes": []}));
+    send(&connection, reply.clone());
+
+    let broadcast = broadcast("room", "ping", json!({}));
+    send(&connection, broadcast.clone());
+
+    // No channel on this topic
+    let stray = common::broadcast("elsewhere", "ping", json!({}));
+    send(&connection, stray.clone());
+
+    connection
+        .send(Message::Text("not a realtime message".into()))
+        .unwrap();
+
+    // Frames are handled in order, so the last one sent is handled last
+    wait_for(|| client.metrics().undecodable_frames == 1);
+    wait_for(|| *calls.lock().unwrap() == 1);
+
+    let metrics = client.metrics();
+
+    assert_eq!(metrics.frames_sent[&MessageEvent::PhxJoin], 1);
+    assert_eq!(metrics.frames_received[&MessageEvent::PhxReply], 1);
+    assert_eq!(metrics.frames_received[&MessageEvent::Broadcast], 2);
+    assert!(metrics.bytes_sent > 0);
+    assert_eq!(
+        metrics.bytes_received as usize,
+        [reply, broadcast, stray]
+            .iter()
+            .map(|frame| frame.to_string().len())
+            .sum::<usize>()
+            + "not a realtime message".len()
+    );
+    assert_eq!(metrics.dropped_messages, 1);
+    assert_eq!(metrics.queue_depth, 0);
+    assert_eq!(metrics.reconnect_attempts, 0);
+    assert!(metrics.join_latency.contains_key("realtime:room"));
+    assert_eq!(metrics.callbacks, 1);
+    assert!(metrics.callback_time >= Duration::from_millis(5));
+}
+
+#[test]
+fn heartbeat_replies_are_timed() {
+    let (client, _listener, mut connection) =
+        connect(builder().set_heartbeat_interval(Duration::from_millis(20)));
+
+    assert_eq!(client.metrics().heartbeat_rtt, None);
+
+    // Only the latest heartbeat is timed, so answer until a reply beats the next one
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while client.metrics().heartbeat_rtt.is_none() {
+        assert!(Instant::now() < deadline, "timed out");
+
+        let heartbeat = recv(&mut connection);
+        assert_eq!(heartbeat["event"], "heartbeat");
+        assert!(heartbeat["ref"].is_string());
+
+        send(&connection, reply(&heartbeat, "ok", json!({})));
+
+        sleep(Duration::from_millis(10));
+    }
+
+    assert_eq!(client.metrics().dropped_messages, 0);
+}
+
+#[test]
+fn reconnect_attempts_are_counted() {
+    let (client, mut listener, connection) =
+        connect(builder().set_reconnect_interval(ReconnectFn::new(|_| Duration::from_millis(10))));
+
+    drop(connection);
+
+    let _connection = listener.accept_blocking().unwrap();
+
+    wait_for(|| client.metrics().reconnect_attempts == 1);
+}
+
+#[test]
+fn messages_sent_through_the_ws_sender_are_queued() {
+    let (client, _listener, mut connection) = connect(&mut builder());
+
+    let ws_tx = client.get_ws_tx().unwrap().unwrap();
+
+    for _ in 0..3 {
+        ws_tx
+            .send(RealtimeMessage {
+                event: MessageEvent::Broadcast,
+                topic: "realtime:room".into(),
+                payload: Payload::Broadcast(BroadcastPayload::new("ping", Default::default())),
+                message_ref: None,
+            })
+            .unwrap();
+    }
+
+    for _ in 0..3 {
+        assert_eq!(recv(&mut connection)["event"], "broadcast");
+    }
+
+    wait_for(|| client.metrics().frames_sent.get(&MessageEvent::Broadcast) == Some(&3));
+    assert_eq!(client.metrics().queue_depth, 0);
+}
+
+#[test]
+fn queue_depth_never_exceeds_the_messages_in_flight() {
+    const SENDERS: usize = 4;
+    const MESSAGES: usize = 1000;
+
+    let (client, _listener, mut connection) = connect(&mut builder());
+
+    let senders: Vec<_> = (0..SENDERS)
+        .map(|_| {
+            let ws_tx = client.get_ws_tx().unwrap().unwrap();
+            thread::spawn(move || {
+                for _ in 0..MESSAGES {
+                    ws_tx
+                        .send(RealtimeMessage {
+                            event: MessageEvent::Broadcast,
+                            topic: "realtime:room".into(),
+                            payload: Payload::Broadcast(BroadcastPayload::new(
+                                "ping",
+                                Default::default(),
+                            )),
+                            message_ref: None,
+                        })
+                        .unwrap();
+
+                    // Lets the send task drain the queue, so messages often arrive on an empty one
+                    thread::yield_now();
+                }
+            })
+        })
+        .collect();
+
+    let reader = thread::spawn(move || {
+        for _ in 0..SENDERS * MESSAGES {
+            recv(&mut connection);
+        }
+    });
+
+    // Sample while messages are in flight, until every frame is written
+    let total = SENDERS * MESSAGES;
+    loop {
+        let metrics = client.metrics();
+        assert!(
+            metrics.queue_depth <= total,
+            "depth {}",
+            metrics.queue_depth
+        );
+
+        if metrics.frames_sent.get(&MessageEvent::Broadcast) == Some(&(total as u64)) {
+            break;
+        }
+    }
+
+    for sender in senders {
+        sender.join().unwrap();
+    }
+    reader.join().unwrap();
+
+    assert_eq!(client.metrics().queue_depth, 0);
+}
@@ -0,0 +1,120 @@
+//! Metrics reported to the `metrics` facade. Run with `--features metrics`.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use metrics::{
+    Counter, CounterFn, Gauge, Histogram, Key, KeyName, Metadata, Recorder, SharedString, Unit,
+};
+use realtime_rs::{
+    realtime_channel::RealtimeChannelBuilder, realtime_client::RealtimeClientBuilder,
+    transport::MemoryTransport,
+};
+use serde_json::Value;
+
+/// Counter totals by name and labels, e.g. `realtime_frames_sent_total{event=phx_join}`
+type Counters = Arc<Mutex<HashMap<String, u64>>>;
+
+struct CountingRecorder(Counters);
+
+struct CountingCounter {
+    key: String,
+    counters: Counters,
+}
+
+impl CounterFn for CountingCounter {
+    fn increment(&self, value: u64) {
+        *self
+            .counters
+            .lock()
+            .unwrap()
+            .entry(self.key.clone())
+            .or_default() += value;
+    }
+
+    fn absolute(&self, value: u64) {
+        self.counters
+            .lock()
+            .unwrap()
+            .insert(self.key.clone(), value);
+    }
+}
+
+impl Recorder for CountingRecorder {
+    fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+    fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+    fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+    fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+        let labels: Vec<String> = key
+            .labels()
+            .map(|label| format!("{}={}", label.key(), label.value()))
+            .collect();
+
+        let key = match labels.is_empty() {
+            true => key.name().to_string(),
+            false => format!("{}{{{}}}", key.name(), labels.join(",")),
+        };
+
+        Counter::from_arc(Arc::new(CountingCounter {
+            key,
+            counters: self.0.clone(),
+        }))
+    }
+
+    fn register_gauge(&self, _: &Key, _: &Metadata<'_>) -> Gauge {
+        Gauge::noop()
+    }
+
+    fn register_histogram(&self, _: &Key, _: &Metadata<'_>) -> Histogram {
+        Histogram::noop()
+    }
+}
+
+#[test]
+fn counters_are_reported_to_the_facade() {
+    let counters: Counters = Default::default();
+    metrics::set_global_recorder(CountingRecorder(counters.clone())).unwrap();
+
+    let (transport, mut listener) = MemoryTransport::new();
+
+    let client = RealtimeClientBuilder::new("http://localhost/realtime/v1", "anon_key")
+        .set_transport(transport)
+        .set_heartbeat_interval(Duration::from_secs(3600))
+        .connect()
+        .unwrap()
+        .to_sync();
+
+    let mut connection = listener.accept_blocking().unwrap();
+
+    let channel = RealtimeChannelBuilder::new("room")
+        .build_sync(&client)
+        .unwrap();
+    channel.subscribe();
+
+    let join = connection.recv_blocking().unwrap();
+    let join: Value = serde_json::from_str(join.to_text().unwrap()).unwrap();
+    assert_eq!(join["event"], "phx_join");
+
+    // Bytes are reported after the frame count
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !counters
+        .lock()
+        .unwrap()
+        .contains_key("realtime_bytes_sent_total")
+    {
+        assert!(Instant::now() < deadline, "timed out");
+        sleep(Duration::from_millis(10));
+    }
+
+    let counters = counters.lock().unwrap();
+    assert_eq!(counters["realtime_frames_sent_total{event=phx_join}"], 1);
+    assert_eq!(
+        counters["realtime_bytes_sent_total"],
+        client.metrics().bytes_sent
+    );
+}
@@ -0,0 +1,242 @@
+//! Middleware ordering, dropping and scoping, driven through `MemoryTransport`
+
+mod common;
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use realtime_rs::{
+    message::{payload::BroadcastPayload, payload::Payload, MessageEvent, RealtimeMessage},
+    middleware::{Inbound, Middleware, MiddlewareFuture, MiddlewareScope, Outbound},
+    realtime_channel::{
+        ChannelManagerSync, ChannelSendError, ChannelState, RealtimeChannelBuilder,
+    },
+    realtime_client::ClientManagerSync,
+    transport::MemoryConnection,
+};
+use serde_json::{json, Value};
+
+use common::{accept_join, broadcast, builder, connect, recv, send, wait_for};
+
+type Log = Arc<Mutex<Vec<String>>>;
+type Received = Arc<Mutex<Vec<HashMap<String, Value>>>>;
+
+/// Appends its name to broadcasts' "path" field in both directions, and logs each call
+struct Tag {
+    name: &'static str,
+    log: Log,
+}
+
+impl Tag {
+    fn new(name: &'static str, log: &Log) -> Self {
+        Self {
+            name,
+            log: log.clone(),
+        }
+    }
+
+    fn tag(&self, direction: &str, mut message: RealtimeMessage) -> Option<RealtimeMessage> {
+        if let Payload::Broadcast(ref mut payload) = message.payload {
+            let path = payload.payload.entry("path".into()).or_insert(json!(""));
+            *path = format!("{}{}", path.as_str().unwrap(), self.name).into();
+        }
+
+        self.log
+            .lock()
+            .unwrap()
+            .push(format!("{} {} {}", direction, self.name, message.topic));
+
+        Some(message)
+    }
+}
+
+impl Middleware for Tag {
+    fn outbound(&self, message: RealtimeMessage) -> MiddlewareFuture<'_> {
+        Box::pin(async move { self.tag("out", message) })
+    }
+
+    fn inbound(&self, message: RealtimeMessage) -> MiddlewareFuture<'_> {
+        Box::pin(async move { self.tag("in", message) })
+    }
+}
+
+/// Joined channel on `topic`, logging the payloads of "message" broadcasts
+fn channel(
+    client: &ClientManagerSync,
+    connection: &mut MemoryConnection,
+    builder: &mut RealtimeChannelBuilder,
+) -> (ChannelManagerSync, Received) {
+    let received: Received = Default::default();
+
+    let log = received.clone();
+    let channel = builder
+        .on_broadcast("message", move |payload| {
+            log.lock().unwrap().push(payload.clone())
+        })
+        .build_sync(client)
+        .unwrap();
+
+    channel.subscribe();
+    accept_join(connection);
+
+    (channel, received)
+}
+
+#[test]
+fn outbound_runs_in_order_and_inbound_in_reverse() {
+    let log: Log = Default::default();
+
+    let (client, _listener, mut connection) = connect(
+        builder()
+            .add_scoped_middleware(
+                MiddlewareScope::events([MessageEvent::Broadcast]),
+                Tag::new("a", &log),
+            )
+            .add_scoped_middleware(
+                MiddlewareScope::events([MessageEvent::Broadcast]),
+                Tag::new("b", &log),
+            ),
+    );
+
+    let (channel, received) = channel(
+        &client,
+        &mut connection,
+        RealtimeChannelBuilder::new("room")
+            .add_scoped_middleware(
+                MiddlewareScope::events([MessageEvent::Broadcast]),
+                Tag::new("x", &log),
+            )
+            .add_scoped_middleware(
+                MiddlewareScope::events([MessageEvent::Broadcast]),
+                Tag::new("y", &log),
+            ),
+    );
+
+    channel
+        .broadcast(BroadcastPayload::new("message", HashMap::new()))
+        .unwrap();
+
+    let sent = recv(&mut connection);
+    assert_eq!(sent["payload"]["payload"]["path"], "xyab");
+
+    send(&connection, broadcast("room", "message", json!({})));
+    wait_for(|| received.lock().unwrap().len() == 1);
+
+    assert_eq!(received.lock().unwrap()[0]["path"], "bayx");
+    assert_eq!(
+        *log.lock().unwrap(),
+        [
+            "out x realtime:room",
+            "out y realtime:room",
+            "out a realtime:room",
+            "out b realtime:room",
+            "in b realtime:room",
+            "in a realtime:room",
+            "in y realtime:room",
+            "in x realtime:room",
+        ]
+    );
+}
+
+#[test]
+fn middleware_can_drop_messages() {
+    let (client, _listener, mut connection) = connect(builder().add_middleware(Inbound(
+        |message: RealtimeMessage| async move {
+            match &message.payload {
+                Payload::Broadcast(payload) if payload.payload.contains_key("secret") => None,
+                _ => Some(message),
+            }
+        },
+    )));
+
+    let (channel, received) = channel(
+        &client,
+        &mut connection,
+        RealtimeChannelBuilder::new("room").add_middleware(Outbound(
+            |message: RealtimeMessage| async move {
+                (message.event != MessageEvent::Broadcast).then_some(message)
+            },
+        )),
+    );
+
+    let dropped = channel.broadcast(BroadcastPayload::new("message", HashMap::new()));
+    assert!(matches!(dropped, Err(ChannelSendError::Dropped)));
+    channel.untrack().unwrap();
+
+    // The broadcast never left, so the next frame is the untrack
+    assert_eq!(recv(&mut connection)["event"], "presence");
+
+    send(
+        &connection,
+        broadcast("room", "message", json!({"secret": true})),
+    );
+    send(&connection, broadcast("room", "message", json!({"n": 1})));
+
+    wait_for(|| received.lock().unwrap().len() == 1);
+    assert_eq!(received.lock().unwrap()[0]["n"], 1);
+}
+
+#[test]
+fn middleware_scoped_to_topic_skips_other_channels() {
+    let log: Log = Default::default();
+
+    let (client, _listener, mut connection) = connect(
+        builder().add_scoped_middleware(MiddlewareScope::topics(["a"]), Tag::new("t", &log)),
+    );
+
+    let (_a, received_a) = channel(
+        &client,
+        &mut connection,
+        &mut RealtimeChannelBuilder::new("a"),
+    );
+    let (_b, received_b) = channel(
+        &client,
+        &mut connection,
+        &mut RealtimeChannelBuilder::new("b"),
+    );
+
+    send(&connection, broadcast("b", "message", json!({})));
+    send(&connection, broadcast("a", "message", json!({})));
+
+    wait_for(|| received_a.lock().unwrap().len() == 1);
+    wait_for(|| received_b.lock().unwrap().len() == 1);
+
+    assert_eq!(received_a.lock().unwrap()[0]["path"], "t");
+    assert!(!received_b.lock().unwrap()[0].contains_key("path"));
+
+    // Only channel a's join and broadcast, and nothing from b
+    assert!(log
+        .lock()
+        .unwrap()
+        .iter()
+        .all(|entry| entry.ends_with("realtime:a")));
+}
+
+#[test]
+fn dropped_presence_messages_leave_the_channel_running() {
+    let (client, _listener, mut connection) = connect(&mut builder());
+
+    let (channel, _received) = channel(
+        &client,
+        &mut connection,
+        RealtimeChannelBuilder::new("room").add_scoped_middleware(
+            MiddlewareScope::events([MessageEvent::Presence]),
+            Outbound(|_| async { None }),
+        ),
+    );
+
+    channel
+        .track(HashMap::from([("status".to_string(), json!("online"))]))
+        .unwrap();
+    channel.untrack().unwrap();
+
+    assert_eq!(channel.get_state().unwrap(), ChannelState::Joined);
+
+    // Neither presence message reached the server
+    channel
+        .broadcast(BroadcastPayload::new("message", HashMap::new()))
+        .unwrap();
+    assert_eq!(recv(&mut connection)["event"], "broadcast");
+}
@@ -0,0 +1,320 @@
+//! End to end tests against `realtime_rs::testing::MockServer`. Run with `--features testing`.
+//!
+//! These use the sync API from plain tests, as the client's runtime can't be dropped inside
+//! another runtime.
+
+mod common;
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    thread::sleep,
+    time::Duration,
+};
+
+use realtime_rs::{
+    message::{
+        payload::{
+            BroadcastConfig, BroadcastPayload, PostgresChangeData, PostgresChangesEvent,
+            PresenceConfig,
+        },
+        PostgresChangeFilter,
+    },
+    realtime_channel::{ChannelError, ChannelState, RealtimeChannelBuilder},
+    testing::MockServer,
+};
+use serde_json::{json, Value};
+
+use common::{mock_builder, mock_client, wait_for};
+
+#[test]
+fn join_and_leave() {
+    let server = MockServer::start();
+    let client = mock_client(&server);
+
+    let channel = RealtimeChannelBuilder::new("room")
+        .build_sync(&client)
+        .unwrap();
+
+    assert_eq!(channel.subscribe_blocking().unwrap(), Ok(()));
+    assert_eq!(channel.get_state().unwrap(), ChannelState::Joined);
+    assert_eq!(server.subscription_count("room"), 1);
+
+    channel.unsubscribe().unwrap().unwrap();
+
+    wait_for(|| channel.get_state().unwrap() == ChannelState::Closed);
+    assert_eq!(server.subscription_count("room"), 0);
+}
+
+#[test]
+fn rejected_join_errors_channel() {
+    let server = MockServer::start();
+    let client = mock_client(&server);
+
+    server.fail_next_join(
+        "room",
+        "Unauthorized: You do not have permissions to read from this Channel topic: room",
+    );
+
+    let channel = RealtimeChannelBuilder::new("room")
+        .set_private(true)
+        .build_sync(&client)
+        .unwrap();
+
+    assert!(matches!(
+        channel.subscribe_blocking().unwrap(),
+        Err(ChannelError::Unauthorized(_))
+    ));
+    assert_eq!(channel.get_state().unwrap(), ChannelState::Errored);
+}
+
+#[test]
+fn broadcast_fans_out_honouring_self() {
+    let server = MockServer::start();
+    let client_a = mock_client(&server);
+    let client_b = mock_client(&server);
+
+    let recieved_a = Arc::new(Mutex::new(vec![]));
+    let recieved_b = Arc::new(Mutex::new(vec![]));
+
+    let log = recieved_a.clone();
+    let channel_a = RealtimeChannelBuilder::new("room")
+        .set_broadcast_config(BroadcastConfig {
+            broadcast_self: true,
+            ack: true,
+        })
+        .on_broadcast("message", move |payload| {
+            log.lock().unwrap().push(payload["from"].clone())
+        })
+        .build_sync(&client_a)
+        .unwrap();
+
+    let log = recieved_b.clone();
+    let channel_b = RealtimeChannelBuilder::new("room")
+        .on_broadcast("message", move |payload| {
+            log.lock().unwrap().push(payload["from"].clone())
+        })
+        .build_sync(&client_b)
+        .unwrap();
+
+    channel_a.subscribe_blocking().unwrap().unwrap();
+    channel_b.subscribe_blocking().unwrap().unwrap();
+
+    let from = |name: &str| HashMap::from([("from".to_string(), json!(name))]);
+
+    channel_a
+        .broadcast(BroadcastPayload::new("message", from("a")))
+        .unwrap();
+    channel_b
+        .broadcast(BroadcastPayload::new("message", from("b")))
+        .unwrap();
+
+    wait_for(|| recieved_a.lock().unwrap().len() == 2);
+    wait_for(|| recieved_b.lock().unwrap().len() == 1);
+
+    // b didn't set `self`, so only hears a
+    assert_eq!(*recieved_b.lock().unwrap(), vec![json!("a")]);
+}
+
+#[test]
+fn presence_track_and_untrack() {
+    let server = MockServer::start();
+    let client_a = mock_client(&server);
+    let client_b = mock_client(&server);
+
+    let joins = Arc::new(Mutex::new(vec![]));
+
+    let log = joins.clone();
+    let channel_a = RealtimeChannelBuilder::new("room")
+        .set_presence_config(PresenceConfig {
+            key: Some("user_a".into()),
+            ..Default::default()
+        })
+        .build_sync(&client_a)
+        .unwrap();
+
+    let channel_b = RealtimeChannelBuilder::new("room")
+        .on_presence_join(move |key, _current, _joined| log.lock().unwrap().push(key.to_string()))
+        .build_sync(&client_b)
+        .unwrap();
+
+    channel_a.subscribe_blocking().unwrap().unwrap();
+    channel_b.subscribe_blocking().unwrap().unwrap();
+
+    channel_a
+        .track(HashMap::from([("status".to_string(), json!("online"))]))
+        .unwrap();
+
+    wait_for(|| channel_b.get_presence_state().contains("user_a"));
+    assert_eq!(*joins.lock().unwrap(), vec!["user_a".to_string()]);
+
+    let list = channel_b.presence_list::<HashMap<String, Value>>().unwrap();
+    assert_eq!(list[0].1[0].state["status"], json!("online"));
+
+    channel_a.untrack().unwrap();
+
+    wait_for(|| channel_b.get_presence_state().is_empty());
+}
+
+#[test]
+fn injected_postgres_changes_reach_callbacks() {
+    let server = MockServer::start();
+    let client = mock_client(&server);
+
+    let changes = Arc::new(Mutex::new(vec![]));
+
+    let log = changes.clone();
+    let channel = RealtimeChannelBuilder::new("db")
+        .on_postgres_change(
+            PostgresChangesEvent::Insert,
+            PostgresChangeFilter {
+                schema: "public".into(),
+                table: Some("todos".into()),
+                ..Default::default()
+            },
+            move |payload| log.lock().unwrap().push(payload.data.record.clone()),
+        )
+        .build_sync(&client)
+        .unwrap();
+
+    channel.subscribe_blocking().unwrap().unwrap();
+
+    server.postgres_change(PostgresChangeData {
+        columns: vec![],
+        commit_timestamp: "2024-01-01T00:00:00Z".into(),
+        errors: None,
+        old_record: None,
+        record: Some(HashMap::from([("id".to_string(), json!(1))])),
+        change_type: PostgresChangesEvent::Insert,
+        schema: "public".into(),
+        table: "todos".into(),
+    });
+
+    // Different table, not delivered
+    server.postgres_change(PostgresChangeData {
+        columns: vec![],
+        commit_timestamp: "2024-01-01T00:00:00Z".into(),
+        errors: None,
+        old_record: None,
+        record: None,
+        change_type: PostgresChangesEvent::Insert,
+        schema: "public".into(),
+        table: "other".into(),
+    });
+
+    wait_for(|| changes.lock().unwrap().len() == 1);
+    sleep(Duration::from_millis(100));
+    assert_eq!(changes.lock().unwrap().len(), 1);
+}
+
+#[test]
+fn reconnects_rejoins_and_retracks_after_disconnect() {
+    let server = MockServer::start();
+    let client = mock_client(&server);
+
+    let channel = RealtimeChannelBuilder::new("room")
+        .set_presence_config(PresenceConfig {
+            key: Some("user".into()),
+            ..Default::default()
+        })
+        .build_sync(&client)
+        .unwrap();
+
+    channel.subscribe_blocking().unwrap().unwrap();
+    channel
+        .track(HashMap::from([("status".to_string(), json!("online"))]))
+        .unwrap();
+
+    wait_for(|| channel.get_presence_state().contains("user"));
+
+    server.refuse_connections(2);
+    server.disconnect_all();
+
+    wait_for(|| server.subscription_count("room") == 1);
+    wait_for(|| channel.get_state().unwrap() == ChannelState::Joined);
+    assert_eq!(server.connection_count(), 1);
+
+    wait_for(|| channel.get_presence_state().contains("user"));
+}
+
+#[test]
+fn rejected_access_token_is_reported() {
+    let server = MockServer::start();
+
+    let auth_errors = Arc::new(Mutex::new(vec![]));
+    let log = auth_errors.clone();
+
+    let client = mock_builder(&server)
+        .on_auth_error(move |_channel, error| log.lock().unwrap().push(error))
+        .connect()
+        .unwrap()
+        .to_sync();
+
+    let channel = RealtimeChannelBuilder::new("room")
+        .build_sync(&client)
+        .unwrap();
+
+    channel.subscribe_blocking().unwrap().unwrap();
+
+    server.reject_next_access_token("Token has expired 5 seconds ago");
+
+    client
+        .set_access_token("new_token".into())
+        .unwrap()
+        .unwrap();
+
+    wait_for(|| auth_errors.lock().unwrap().len() == 1);
+    assert_eq!(
+        auth_errors.lock().unwrap()[0],
+        ChannelError::TokenRejected("Token has expired 5 seconds ago".into())
+    );
+    assert_eq!(channel.get_state().unwrap(), ChannelState::Errored);
+}
+
+#[test]
+fn channel_rejecting_a_token_rejoins_with_the_next_one() {
+    let server = MockServer::start();
+
+    let auth_errors = Arc::new(Mutex::new(vec![]));
+    let log = auth_errors.clone();
+
+    let client = mock_builder(&server)
+        .on_auth_error(move |_channel, error| log.lock().unwrap().push(error))
+        .connect()
+        .unwrap()
+        .to_sync();
+
+    let channel = RealtimeChannelBuilder::new("room")
+        .build_sync(&client)
+        .unwrap();
+
+    channel.subscribe_blocking().unwrap().unwrap();
+
+    server.reject_next_access_token("InvalidJWTToken: Token has expired 5 seconds ago");
+    client
+        .set_access_token("rejected_token".into())
+        .unwrap()
+        .unwrap();
+
+    wait_for(|| auth_errors.lock().unwrap().len() == 1);
+    wait_for(|| server.subscription_count("realtime:room") == 0);
+
+    client
+        .set_access_token("new_token".into())
+        .unwrap()
+        .unwrap();
+
+    wait_for(|| channel.get_state().unwrap() == ChannelState::Joined);
+    assert_eq!(server.subscription_count("realtime:room"), 1);
+    assert_eq!(client.channels().unwrap().len(), 1);
+
+    let joins: Vec<Value> = server
+        .received()
+        .into_iter()
+        .filter(|frame| frame["event"] == "phx_join")
+        .collect();
+    assert_eq!(
+        joins.last().unwrap()["payload"]["access_token"],
+        "new_token"
+    );
+}
@@ -0,0 +1,301 @@
+//! Phoenix Presence `syncState` / `syncDiff` behaviour, ported from the fixtures in
+//! `phoenix/assets/test/presence_test.js`.
+
+use std::collections::HashMap;
+
+use realtime_rs::message::presence::{
+    PhxMap, PresenceDiff, PresenceMeta, PresenceState, RawPresenceState,
+};
+use serde_json::{json, Value};
+
+fn state(value: Value) -> PresenceState {
+    serde_json::from_value::<RawPresenceState>(value)
+        .unwrap()
+        .into()
+}
+
+fn metas(value: Value) -> PhxMap {
+    let key = "key".to_string();
+    state(json!({ "key": value })).0.remove(&key).unwrap()
+}
+
+fn fixture_joins() -> PresenceState {
+    state(json!({"u1": {"metas": [{"id": 1, "phx_ref": "1.2"}]}}))
+}
+
+fn fixture_leaves() -> PresenceState {
+    state(json!({"u2": {"metas": [{"id": 2, "phx_ref": "2"}]}}))
+}
+
+fn fixture_state() -> PresenceState {
+    state(json!({
+        "u1": {"metas": [{"id": 1, "phx_ref": "1"}]},
+        "u2": {"metas": [{"id": 2, "phx_ref": "2"}]},
+        "u3": {"metas": [{"id": 3, "phx_ref": "3"}]}
+    }))
+}
+
+type Joined = HashMap<String, (Option<PhxMap>, PhxMap)>;
+type Left = HashMap<String, (PhxMap, PhxMap)>;
+
+fn sync_state(current: &mut PresenceState, new_state: PresenceState) -> (Joined, Left) {
+    let mut joined = HashMap::new();
+    let mut left = HashMap::new();
+
+    current.sync_state(
+        new_state,
+        |key, current, new_pres| {
+            joined.insert(key.to_string(), (current.cloned(), new_pres.clone()));
+        },
+        |key, current, left_pres| {
+            left.insert(key.to_string(), (current.clone(), left_pres.clone()));
+        },
+    );
+
+    (joined, left)
+}
+
+fn sync_diff(current: &mut PresenceState, joins: PresenceState, leaves: PresenceState) {
+    current.sync_diff(PresenceDiff { joins, leaves }, |_, _, _| {}, |_, _, _| {});
+}
+
+#[test]
+fn sync_state_syncs_empty_state() {
+    let new_state = state(json!({"u1": {"metas": [{"id": 1, "phx_ref": "1"}]}}));
+    let mut current = PresenceState::default();
+
+    sync_state(&mut current, new_state.clone());
+
+    assert_eq!(current, new_state);
+}
+
+#[test]
+fn sync_state_on_joins_new_presences_and_on_leaves_left_presences() {
+    let new_state = state(json!({"u1": {"metas": [{"id": 1, "phx_ref": "1"}]}}));
+    let mut current = state(json!({"u4": {"metas": [{"id": 4, "phx_ref": "4"}]}}));
+
+    let (joined, left) = sync_state(&mut current, new_state.clone());
+
+    assert_eq!(current, new_state);
+    assert_eq!(
+        joined,
+        HashMap::from([(
+            "u1".to_string(),
+            (None, metas(json!({"metas": [{"id": 1, "phx_ref": "1"}]})))
+        )])
+    );
+    assert_eq!(
+        left,
+        HashMap::from([(
+            "u4".to_string(),
+            (
+                PhxMap::new(),
+                metas(json!({"metas": [{"id": 4, "phx_ref": "4"}]}))
+            )
+        )])
+    );
+}
+
+#[test]
+fn sync_state_on_joins_only_newly_added_metas() {
+    let new_state = state(json!({"u3": {"metas": [
+        {"id": 3, "phx_ref": "3"},
+        {"id": 3, "phx_ref": "3.new"}
+    ]}}));
+    let mut current = state(json!({"u3": {"metas": [{"id": 3, "phx_ref": "3"}]}}));
+
+    let (joined, left) = sync_state(&mut current, new_state.clone());
+
+    assert_eq!(current, new_state);
+    assert_eq!(
+        joined,
+        HashMap::from([(
+            "u3".to_string(),
+            (
+                Some(metas(json!({"metas": [{"id": 3, "phx_ref": "3"}]}))),
+                metas(json!({"metas": [{"id": 3, "phx_ref": "3.new"}]}))
+            )
+        )])
+    );
+    assert!(left.is_empty());
+}
+
+#[test]
+fn sync_state_on_leaves_only_removed_metas() {
+    let new_state = state(json!({"u3": {"metas": [{"id": 3, "phx_ref": "3.new"}]}}));
+    let mut current = state(json!({"u3": {"metas": [
+        {"id": 3, "phx_ref": "3"},
+        {"id": 3, "phx_ref": "3.new"}
+    ]}}));
+
+    let (joined, left) = sync_state(&mut current, new_state.clone());
+
+    assert_eq!(current, new_state);
+    assert!(joined.is_empty());
+    assert_eq!(
+        left,
+        HashMap::from([(
+            "u3".to_string(),
+            (
+                metas(json!({"metas": [{"id": 3, "phx_ref": "3.new"}]})),
+                metas(json!({"metas": [{"id": 3, "phx_ref": "3"}]}))
+            )
+        )])
+    );
+}
+
+#[test]
+fn sync_diff_syncs_empty_state() {
+    let joins = state(json!({"u1": {"metas": [{"id": 1, "phx_ref": "1"}]}}));
+    let mut current = PresenceState::default();
+
+    sync_diff(&mut current, joins.clone(), PresenceState::default());
+
+    assert_eq!(current, joins);
+}
+
+#[test]
+fn sync_diff_removes_presence_when_meta_is_empty_and_adds_additional_meta() {
+    let mut current = fixture_state();
+
+    sync_diff(&mut current, fixture_joins(), fixture_leaves());
+
+    assert_eq!(
+        current,
+        state(json!({
+            "u1": {"metas": [{"id": 1, "phx_ref": "1"}, {"id": 1, "phx_ref": "1.2"}]},
+            "u3": {"metas": [{"id": 3, "phx_ref": "3"}]}
+        }))
+    );
+}
+
+#[test]
+fn sync_diff_removes_meta_while_leaving_key_if_other_metas_exist() {
+    let mut current = state(json!({
+        "u1": {"metas": [{"id": 1, "phx_ref": "1"}, {"id": 1, "phx_ref": "1.2"}]}
+    }));
+
+    sync_diff(
+        &mut current,
+        PresenceState::default(),
+        state(json!({"u1": {"metas": [{"id": 1, "phx_ref": "1"}]}})),
+    );
+
+    assert_eq!(
+        current,
+        state(json!({"u1": {"metas": [{"id": 1, "phx_ref": "1.2"}]}}))
+    );
+}
+
+#[test]
+fn sync_diff_ignores_leaves_for_unknown_keys() {
+    let mut current = fixture_state();
+
+    sync_diff(
+        &mut current,
+        PresenceState::default(),
+        state(json!({"u9": {"metas": [{"id": 9, "phx_ref": "9"}]}})),
+    );
+
+    assert_eq!(current, fixture_state());
+}
+
+#[derive(serde::Deserialize, Debug, PartialEq)]
+struct User {
+    id: u32,
+}
+
+#[test]
+fn list_typed_groups_metas_by_key() {
+    let current = state(json!({
+        "u2": {"metas": [{"id": 2, "phx_ref": "2"}]},
+        "u1": {"metas": [{"id": 1, "phx_ref": "1.2"}, {"id": 1, "phx_ref": "1"}]}
+    }));
+
+    let list = current.list_typed::<User>().unwrap();
+
+    assert_eq!(
+        list,
+        vec![
+            (
+                "u1".to_string(),
+                vec![
+                    PresenceMeta {
+                        phx_ref: "1".into(),
+                        state: User { id: 1 }
+                    },
+                    PresenceMeta {
+                        phx_ref: "1.2".into(),
+                        state: User { id: 1 }
+                    }
+                ]
+            ),
+            (
+                "u2".to_string(),
+                vec![PresenceMeta {
+                    phx_ref: "2".into(),
+                    state: User { id: 2 }
+                }]
+            )
+        ]
+    );
+}
+
+#[test]
+fn list_by_lists_full_presence_by_default() {
+    let current = fixture_state();
+
+    let list = current.list_by(|_key, metas| metas.clone());
+
+    assert_eq!(
+        list,
+        vec![
+            metas(json!({"metas": [{"id": 1, "phx_ref": "1"}]})),
+            metas(json!({"metas": [{"id": 2, "phx_ref": "2"}]})),
+            metas(json!({"metas": [{"id": 3, "phx_ref": "3"}]})),
+        ]
+    );
+}
+
+#[test]
+fn list_by_lists_with_custom_function() {
+    let current = state(json!({"u1": {"metas": [
+        {"id": 1, "phx_ref": "1.first"},
+        {"id": 1, "phx_ref": "1.second"}
+    ]}}));
+
+    let list = current.list_by(|key, metas| (key.to_string(), metas.len()));
+
+    assert_eq!(list, vec![("u1".to_string(), 2)]);
+}
+
+#[test]
+fn state_helpers() {
+    let current = fixture_state();
+
+    assert_eq!(current.len(), 3);
+    assert!(!current.is_empty());
+    assert!(current.contains("u2"));
+    assert!(!current.contains("u4"));
+    assert_eq!(
+        current.metas("u3"),
+        Some(&metas(json!({"metas": [{"id": 3, "phx_ref": "3"}]})))
+    );
+
+    let mut keys: Vec<&str> = current.keys().collect();
+    keys.sort();
+    assert_eq!(keys, vec!["u1", "u2", "u3"]);
+}
+
+#[test]
+fn state_serde_round_trip() {
+    let current = fixture_state();
+
+    let json = serde_json::to_value(&current).unwrap();
+
+    assert_eq!(json["u1"]["1"], json!({"id": 1}));
+    assert_eq!(
+        serde_json::from_value::<PresenceState>(json).unwrap(),
+        current
+    );
+}
@@ -0,0 +1,239 @@
+//! Recording a session through `MemoryTransport`, then replaying it with `ReplayTransport`
+
+mod common;
+
+use std::{
+    collections::HashMap,
+    io::Write,
+    sync::{Arc, Mutex},
+};
+
+use realtime_rs::{
+    realtime_channel::{ChannelManagerSync, ChannelState, RealtimeChannelBuilder},
+    realtime_client::ClientManagerSync,
+    recording::{Direction, RecordedFrame, Recorder, ReplayTransport},
+    transport::{MemoryConnection, MemoryTransport, Transport},
+};
+use serde_json::{json, Value};
+
+use common::{accept_join, broadcast, builder, reply, send, wait_for};
+
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn client(transport: impl Transport + 'static, recorder: Option<Recorder>) -> ClientManagerSync {
+    let mut builder = builder();
+    builder.set_transport(transport);
+
+    if let Some(recorder) = recorder {
+        builder.set_recorder(recorder);
+    }
+
+    builder.connect().unwrap().to_sync()
+}
+
+fn channel(client: &ClientManagerSync, pings: Arc<Mutex<Vec<Value>>>) -> ChannelManagerSync {
+    RealtimeChannelBuilder::new("room")
+        .on_broadcast("ping", move |payload: &HashMap<String, Value>| {
+            pings.lock().unwrap().push(payload["n"].clone());
+        })
+        .build_sync(client)
+        .unwrap()
+}
+
+/// Acts as the server: answers the join, then broadcasts two pings
+fn serve(connection: &mut MemoryConnection) {
+    accept_join(connection);
+
+    send(connection, broadcast("room", "ping", json!({"n": 1})));
+    send(connection, broadcast("room", "ping", json!({"n": 2})));
+}
+
+fn record_session() -> Vec<RecordedFrame> {
+    let buffer = SharedBuffer::default();
+    let (transport, mut listener) = MemoryTransport::new();
+
+    let recorder = Recorder::new(buffer.clone());
+
+    let client = client(transport, Some(recorder.clone()));
+    let mut connection = listener.accept_blocking().unwrap();
+
+    let pings = Arc::new(Mutex::new(vec![]));
+    let channel = channel(&client, pings.clone());
+    channel.subscribe();
+
+    serve(&mut connection);
+    wait_for(|| pings.lock().unwrap().len() == 2);
+
+    recorder.flush();
+    buffer.frames()
+}
+
+impl SharedBuffer {
+    fn frames(&self) -> Vec<RecordedFrame> {
+        let bytes = self.0.lock().unwrap().clone();
+
+        String::from_utf8(bytes)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+}
+
+#[test]
+fn recorder_writes_both_directions() {
+    let frames = record_session();
+
+    let summary: Vec<(Direction, String)> = frames
+        .iter()
+        .map(|f| {
+            let frame: Value = serde_json::from_str(&f.frame).unwrap();
+            (f.direction, frame["event"].as_str().unwrap().to_owned())
+        })
+        .collect();
+
+    assert_eq!(
+        summary,
+        vec![
+            (Direction::Sent, "phx_join".into()),
+            (Direction::Received, "phx_reply".into()),
+            (Direction::Received, "broadcast".into()),
+            (Direction::Received, "broadcast".into()),
+        ]
+    );
+    assert!(frames.iter().all(|f| f.connection == 0));
+    assert!(frames.windows(2).all(|w| w[0].timestamp <= w[1].timestamp));
+}
+
+#[test]
+fn replay_reproduces_callbacks() {
+    let path = std::env::temp_dir().join(format!("realtime-rs-{}.jsonl", std::process::id()));
+
+    let mut file = std::fs::File::create(&path).unwrap();
+    for frame in record_session() {
+        writeln!(file, "{}", serde_json::to_string(&frame).unwrap()).unwrap();
+    }
+    drop(file);
+
+    let replay = ReplayTransport::from_file(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+
+    let client = client(replay.clone(), None);
+
+    let pings = Arc::new(Mutex::new(vec![]));
+    let channel = channel(&client, pings.clone());
+    channel.subscribe();
+
+    // The recorded join reply only reaches this join if its ref was rewritten
+    wait_for(|| channel.get_state().unwrap() == ChannelState::Joined);
+    wait_for(|| replay.is_finished());
+    wait_for(|| pings.lock().unwrap().len() == 2);
+
+    assert_eq!(*pings.lock().unwrap(), vec![json!(1), json!(2)]);
+}
+
+#[test]
+fn replay_closes_played_connections() {
+    let frame = |connection, direction, frame: Value| RecordedFrame {
+        timestamp: 0,
+        connection,
+        direction,
+        frame: frame.to_string(),
+    };
+    let ping = |n: i64| broadcast("room", "ping", json!({ "n": n }));
+
+    let join = |join_ref: &str| {
+        json!({
+            "topic": "realtime:room",
+            "event": "phx_join",
+            "payload": {},
+            "ref": join_ref,
+        })
+    };
+    let reply = |join_ref: &str| reply(&join(join_ref), "ok", json!({"postgres_changes": []}));
+
+    // The client rejoins after the first connection closes
+    let replay = ReplayTransport::new([
+        frame(0, Direction::Sent, join("first")),
+        frame(0, Direction::Received, reply("first")),
+        frame(0, Direction::Received, ping(1)),
+        frame(1, Direction::Sent, join("second")),
+        frame(1, Direction::Received, reply("second")),
+        frame(1, Direction::Received, ping(2)),
+    ]);
+
+    let client = client(replay.clone(), None);
+
+    let pings = Arc::new(Mutex::new(vec![]));
+    let channel = channel(&client, pings.clone());
+    channel.subscribe();
+
+    wait_for(|| pings.lock().unwrap().len() == 2);
+    wait_for(|| channel.get_state().unwrap() == ChannelState::Joined);
+    assert!(replay.is_finished());
+}
+
+#[test]
+fn recorder_redacts_credentials() {
+    let buffer = SharedBuffer::default();
+    let recorder = Recorder::new(buffer.clone());
+    let (transport, mut listener) = MemoryTransport::new();
+
+    let client = builder()
+        .set_access_token("secret_jwt")
+        .set_transport(transport)
+        .set_recorder(recorder.clone())
+        .connect()
+        .unwrap()
+        .to_sync();
+    let mut connection = listener.accept_blocking().unwrap();
+
+    let pings = Arc::new(Mutex::new(vec![]));
+    let channel = channel(&client, pings.clone());
+    channel.subscribe();
+    accept_join(&mut connection);
+
+    // Keys match at any depth, whatever their case
+    send(
+        &connection,
+        broadcast(
+            "room",
+            "ping",
+            json!({
+                "n": 1,
+                "apikey": "secret_key",
+                "headers": [{"Authorization": "Bearer secret_jwt"}],
+            }),
+        ),
+    );
+    wait_for(|| pings.lock().unwrap().len() == 1);
+
+    recorder.flush();
+    let frames = buffer.frames();
+
+    assert!(frames.iter().all(|f| !f.frame.contains("secret")));
+
+    let join: Value = serde_json::from_str(&frames[0].frame).unwrap();
+    assert_eq!(join["payload"]["access_token"], "[redacted]");
+
+    let ping: Value = serde_json::from_str(&frames[2].frame).unwrap();
+    assert_eq!(
+        ping["payload"]["payload"],
+        json!({
+            "n": 1,
+            "apikey": "[redacted]",
+            "headers": [{"Authorization": "[redacted]"}],
+        })
+    );
+}
@@ -0,0 +1,103 @@
+//! Routing incoming messages to channels by topic, driven through `MemoryTransport`
+
+mod common;
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use realtime_rs::{
+    realtime_channel::{ChannelManagerSync, ChannelState, RealtimeChannelBuilder},
+    realtime_client::ClientManagerSync,
+    transport::MemoryConnection,
+};
+use serde_json::{json, Value};
+
+use common::{accept_join, broadcast, builder, connect, recv, reply, send, wait_for};
+
+type Received = Arc<Mutex<Vec<HashMap<String, Value>>>>;
+
+/// Joined channel on `topic`, logging the payloads of "ping" broadcasts
+fn joined(
+    client: &ClientManagerSync,
+    connection: &mut MemoryConnection,
+    topic: &str,
+) -> (ChannelManagerSync, Received) {
+    let received: Received = Default::default();
+
+    let log = received.clone();
+    let channel = RealtimeChannelBuilder::new(topic)
+        .on_broadcast("ping", move |payload| {
+            log.lock().unwrap().push(payload.clone())
+        })
+        .build_sync(client)
+        .unwrap();
+
+    channel.subscribe();
+    accept_join(connection);
+    wait_for(|| channel.get_state().unwrap() == ChannelState::Joined);
+
+    (channel, received)
+}
+
+#[test]
+fn messages_reach_only_their_topic() {
+    let (client, _listener, mut connection) = connect(&mut builder());
+
+    let (_a, received_a) = joined(&client, &mut connection, "a");
+    let (_b, received_b) = joined(&client, &mut connection, "b");
+
+    send(&connection, broadcast("a", "ping", json!({"to": "a"})));
+    send(&connection, broadcast("b", "ping", json!({"to": "b"})));
+    send(&connection, broadcast("c", "ping", json!({"to": "c"})));
+
+    wait_for(|| client.metrics().dropped_messages == 1);
+    wait_for(|| received_a.lock().unwrap().len() == 1 && received_b.lock().unwrap().len() == 1);
+
+    assert_eq!(received_a.lock().unwrap()[0]["to"], "a");
+    assert_eq!(received_b.lock().unwrap()[0]["to"], "b");
+}
+
+#[test]
+fn route_is_removed_on_leave() {
+    let (client, _listener, mut connection) = connect(&mut builder());
+
+    let (channel, received) = joined(&client, &mut connection, "room");
+
+    channel.unsubscribe().unwrap().unwrap();
+    let leave = recv(&mut connection);
+    assert_eq!(leave["event"], "phx_leave");
+    send(&connection, reply(&leave, "ok", json!({})));
+
+    wait_for(|| channel.get_state().unwrap() == ChannelState::Closed);
+
+    send(&connection, broadcast("room", "ping", json!({})));
+
+    wait_for(|| client.metrics().dropped_messages == 1);
+    assert!(received.lock().unwrap().is_empty());
+}
+
+#[test]
+fn route_is_removed_on_close() {
+    let (client, _listener, mut connection) = connect(&mut builder());
+
+    let (channel, received) = joined(&client, &mut connection, "room");
+
+    send(
+        &connection,
+        json!({
+            "topic": "realtime:room",
+            "event": "phx_close",
+            "payload": {},
+            "ref": null,
+        }),
+    );
+
+    wait_for(|| channel.get_state().unwrap() == ChannelState::Closed);
+
+    send(&connection, broadcast("room", "ping", json!({})));
+
+    wait_for(|| client.metrics().dropped_messages == 1);
+    assert!(received.lock().unwrap().is_empty());
+}
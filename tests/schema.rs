@@ -0,0 +1,171 @@
+//! Broadcast schema validation, driven through `MemoryTransport`. Run with `--features schema`.
+
+mod common;
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use realtime_rs::{
+    message::payload::BroadcastPayload,
+    realtime_channel::{
+        ChannelBuildError, ChannelManagerSync, ChannelSendError, RealtimeChannelBuilder,
+    },
+    realtime_client::ClientManagerSync,
+    schema::InvalidBroadcast,
+    transport::MemoryConnection,
+};
+use serde_json::{json, Value};
+
+use common::{accept_join, broadcast, builder, connect, recv, send, wait_for};
+
+type Received = Arc<Mutex<Vec<HashMap<String, Value>>>>;
+type Invalid = Arc<Mutex<Vec<InvalidBroadcast>>>;
+
+struct Room {
+    _client: ClientManagerSync,
+    connection: MemoryConnection,
+    channel: ChannelManagerSync,
+    received: Received,
+    invalid: Invalid,
+}
+
+fn position_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "x": {"type": "number"},
+            "y": {"type": "number"},
+        },
+        "required": ["x", "y"],
+    })
+}
+
+/// Joined channel on "room" with a schema for "position" broadcasts
+fn room(validate_outgoing: bool) -> Room {
+    let (client, _listener, mut connection) = connect(&mut builder());
+
+    let received: Received = Default::default();
+    let invalid: Invalid = Default::default();
+
+    let log = received.clone();
+    let invalid_log = invalid.clone();
+    let channel = RealtimeChannelBuilder::new("room")
+        .set_broadcast_schema("position", position_schema())
+        .set_validate_outgoing(validate_outgoing)
+        .on_invalid(move |rejected| invalid_log.lock().unwrap().push(rejected.clone()))
+        .on_broadcast("position", move |payload| {
+            log.lock().unwrap().push(payload.clone())
+        })
+        .on_broadcast("chat", {
+            let log = received.clone();
+            move |payload| log.lock().unwrap().push(payload.clone())
+        })
+        .build_sync(&client)
+        .unwrap();
+
+    channel.subscribe();
+
+    accept_join(&mut connection);
+
+    Room {
+        _client: client,
+        connection,
+        channel,
+        received,
+        invalid,
+    }
+}
+
+fn payload(value: Value) -> HashMap<String, Value> {
+    serde_json::from_value(value).unwrap()
+}
+
+#[test]
+fn invalid_incoming_payloads_go_to_on_invalid() {
+    let room = room(false);
+
+    send(
+        &room.connection,
+        broadcast("room", "position", json!({"x": "left", "z": 1})),
+    );
+    send(
+        &room.connection,
+        broadcast("room", "position", json!({"x": 1, "y": 2})),
+    );
+    // No schema for this event
+    send(
+        &room.connection,
+        broadcast("room", "chat", json!({"anything": true})),
+    );
+
+    wait_for(|| room.received.lock().unwrap().len() == 2);
+
+    let received = room.received.lock().unwrap();
+    assert_eq!(received[0], payload(json!({"x": 1, "y": 2})));
+    assert_eq!(received[1], payload(json!({"anything": true})));
+
+    let invalid = room.invalid.lock().unwrap();
+    assert_eq!(invalid.len(), 1);
+    assert_eq!(invalid[0].payload.event, "position");
+
+    let mut paths: Vec<_> = invalid[0].errors.iter().map(|e| e.path.as_str()).collect();
+    paths.sort();
+    assert_eq!(paths, vec!["", "/x"]);
+}
+
+#[test]
+fn outgoing_payloads_are_validated_when_enabled() {
+    let mut room = room(true);
+
+    let result = room
+        .channel
+        .broadcast(BroadcastPayload::new("position", payload(json!({"x": 1}))));
+    match result {
+        Err(ChannelSendError::InvalidPayload(errors)) => assert_eq!(errors[0].path, ""),
+        other => panic!("expected InvalidPayload, got {:?}", other),
+    }
+
+    room.channel
+        .broadcast(BroadcastPayload::new(
+            "position",
+            payload(json!({"x": 1, "y": 2})),
+        ))
+        .unwrap();
+
+    // Only the valid payload reaches the server
+    let frame = recv(&mut room.connection);
+    assert_eq!(frame["payload"]["payload"], json!({"x": 1, "y": 2}));
+
+    // Returned to the caller, not passed to on_invalid
+    assert!(room.invalid.lock().unwrap().is_empty());
+}
+
+#[test]
+fn outgoing_payloads_are_not_validated_by_default() {
+    let mut room = room(false);
+
+    room.channel
+        .broadcast(BroadcastPayload::new("position", payload(json!({"x": 1}))))
+        .unwrap();
+
+    let frame = recv(&mut room.connection);
+    assert_eq!(frame["payload"]["payload"], json!({"x": 1}));
+    assert!(room.invalid.lock().unwrap().is_empty());
+}
+
+#[test]
+fn invalid_schemas_fail_the_build() {
+    let (client, _listener, _connection) = connect(&mut builder());
+
+    let result = RealtimeChannelBuilder::new("room")
+        .set_broadcast_schema("position", json!({"type": "coordinates"}))
+        .build_sync(&client);
+
+    match result {
+        Err(ChannelBuildError::InvalidSchema { event, .. }) => assert_eq!(event, "position"),
+        Err(e) => panic!("expected InvalidSchema, got {:?}", e),
+        Ok(_) => panic!("expected InvalidSchema, channel was built"),
+    }
+}
@@ -0,0 +1,221 @@
+//! Driving a client through `MemoryTransport`, no sockets involved
+
+mod common;
+
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use realtime_rs::realtime_channel::{ChannelError, ChannelState, RealtimeChannelBuilder};
+use serde_json::{json, Value};
+
+use common::{accept_join, builder, connect, recv, reply, send, wait_for};
+
+#[test]
+fn handshake_request_carries_credentials() {
+    let (_client, _listener, connection) = connect(&mut builder());
+
+    let request = connection.request();
+
+    assert_eq!(request.uri().scheme_str(), Some("ws"));
+    assert_eq!(request.uri().path(), "/realtime/v1/websocket");
+    assert!(request.uri().query().unwrap().contains("apikey=anon_key"));
+    assert_eq!(request.headers()["Authorization"], "Bearer anon_key");
+}
+
+#[test]
+fn join_is_sent_and_reply_joins_channel() {
+    let (client, _listener, mut connection) = connect(&mut builder());
+
+    let channel = RealtimeChannelBuilder::new("room")
+        .build_sync(&client)
+        .unwrap();
+
+    channel.subscribe();
+
+    let join = recv(&mut connection);
+    assert_eq!(join["event"], "phx_join");
+    assert_eq!(join["topic"], "realtime:room");
+    assert_eq!(join["payload"]["access_token"], "anon_key");
+
+    assert_eq!(channel.get_state().unwrap(), ChannelState::Joining);
+
+    send(
+        &connection,
+        reply(&join, "ok", json!({"postgres_changes": []})),
+    );
+
+    wait_for(|| channel.get_state().unwrap() == ChannelState::Joined);
+}
+
+#[test]
+fn dropped_connection_reconnects() {
+    let (_client, mut listener, connection) = connect(&mut builder());
+
+    drop(connection);
+
+    assert!(listener.accept_blocking().is_some());
+}
+
+#[test]
+fn rebuilt_channel_ignores_the_old_leave_reply() {
+    let (client, _listener, mut connection) = connect(&mut builder());
+
+    let channel = RealtimeChannelBuilder::new("room")
+        .build_sync(&client)
+        .unwrap();
+    channel.subscribe();
+    accept_join(&mut connection);
+    wait_for(|| channel.get_state().unwrap() == ChannelState::Joined);
+
+    let mut builder = channel.unsubscribe().unwrap().unwrap();
+    let leave = recv(&mut connection);
+    assert_eq!(leave["event"], "phx_leave");
+
+    let rebuilt = builder.build_sync(&client).unwrap();
+    rebuilt.subscribe();
+    accept_join(&mut connection);
+    wait_for(|| rebuilt.get_state().unwrap() == ChannelState::Joined);
+
+    // Only the old channel is left by this
+    send(&connection, reply(&leave, "ok", json!({})));
+
+    wait_for(|| channel.get_state().unwrap() == ChannelState::Closed);
+    assert_eq!(rebuilt.get_state().unwrap(), ChannelState::Joined);
+}
+
+/// Subscribes with `subscribe_blocking()` and answers the join with `status` and `response`
+fn join_replied_with(status: &str, response: Value) -> Result<(), ChannelError> {
+    let (client, _listener, mut connection) = connect(&mut builder());
+
+    let channel = RealtimeChannelBuilder::new("room")
+        .build_sync(&client)
+        .unwrap();
+
+    let subscribing = thread::spawn(move || channel.subscribe_blocking().unwrap());
+
+    let join = recv(&mut connection);
+    send(&connection, reply(&join, status, response));
+
+    subscribing.join().unwrap()
+}
+
+#[test]
+fn ok_reply_without_postgres_changes_joins() {
+    assert_eq!(join_replied_with("ok", json!({})), Ok(()));
+}
+
+#[test]
+fn error_status_response_fails_the_join() {
+    let result = join_replied_with("error", json!({"postgres_changes": []}));
+    assert!(matches!(result, Err(ChannelError::JoinRejected(_))));
+}
+
+#[test]
+fn join_errors_are_classified_by_server_reason() {
+    let unauthorized =
+        "Unauthorized: You do not have permissions to read from this Channel topic: room";
+    assert_eq!(
+        join_replied_with("error", json!({ "reason": unauthorized })),
+        Err(ChannelError::Unauthorized(unauthorized.into()))
+    );
+
+    // Older servers send the message without a code
+    let legacy = "You do not have permissions to read from this Channel topic: room";
+    assert_eq!(
+        join_replied_with("error", json!({ "reason": legacy })),
+        Err(ChannelError::Unauthorized(legacy.into()))
+    );
+
+    // Mentioning permissions doesn't make it an authorization failure
+    let rate_limited = "ChannelRateLimitReached: Too many channels, check your permissions plan";
+    assert_eq!(
+        join_replied_with("error", json!({ "reason": rate_limited })),
+        Err(ChannelError::JoinRejected(rate_limited.into()))
+    );
+}
+
+/// Errors a joined channel with a `system` error carrying `message`, returning the error it got
+fn system_error(message: &str) -> Option<ChannelError> {
+    let (client, _listener, mut connection) = connect(&mut builder());
+
+    let channel = RealtimeChannelBuilder::new("room")
+        .build_sync(&client)
+        .unwrap();
+    channel.subscribe();
+    accept_join(&mut connection);
+    wait_for(|| channel.get_state().unwrap() == ChannelState::Joined);
+
+    send(
+        &connection,
+        json!({
+            "topic": "realtime:room",
+            "event": "system",
+            "payload": {
+                "channel": "room",
+                "extension": "system",
+                "message": message,
+                "status": "error",
+            },
+            "ref": null,
+        }),
+    );
+    wait_for(|| channel.get_state().unwrap() == ChannelState::Errored);
+
+    channel.get_error().unwrap()
+}
+
+#[test]
+fn system_errors_are_classified_by_server_reason() {
+    let coded = "InvalidJWTToken: Token has expired 5 seconds ago";
+    assert_eq!(
+        system_error(coded),
+        Some(ChannelError::TokenRejected(coded.into()))
+    );
+
+    let signature = "JwtSignatureError: Failed to validate JWT signature";
+    assert_eq!(
+        system_error(signature),
+        Some(ChannelError::TokenRejected(signature.into()))
+    );
+
+    // Older servers send the message without a code
+    let legacy = "Token has expired 5 seconds ago";
+    assert_eq!(
+        system_error(legacy),
+        Some(ChannelError::TokenRejected(legacy.into()))
+    );
+
+    // Mentioning tokens doesn't make it a token rejection
+    let unrelated = "ChannelRateLimitReached: Too many tokens in flight";
+    assert_eq!(
+        system_error(unrelated),
+        Some(ChannelError::JoinRejected(unrelated.into()))
+    );
+}
+
+#[test]
+fn set_access_token_returns_without_a_reply() {
+    let (client, _listener, mut connection) = connect(&mut builder());
+
+    let channel = RealtimeChannelBuilder::new("room")
+        .build_sync(&client)
+        .unwrap();
+    channel.subscribe();
+    accept_join(&mut connection);
+    wait_for(|| channel.get_state().unwrap() == ChannelState::Joined);
+
+    // Realtime never answers a token it accepts
+    let started = Instant::now();
+    client
+        .set_access_token("new_token".into())
+        .unwrap()
+        .unwrap();
+    assert!(started.elapsed() < Duration::from_millis(500));
+
+    let push = recv(&mut connection);
+    assert_eq!(push["event"], "access_token");
+    assert_eq!(push["payload"]["access_token"], "new_token");
+    assert_eq!(channel.get_state().unwrap(), ChannelState::Joined);
+}